@@ -1,4 +1,6 @@
 fn main() {
+    // the schemas live outside of this package, so cargo does not watch them by default
+    println!("cargo:rerun-if-changed=../schema");
     capnpc::CompilerCommand::new()
         .src_prefix("../schema")
         .file("../schema/opentelemetry/capnp/trace/v1/trace.capnp")
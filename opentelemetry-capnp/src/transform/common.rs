@@ -1,3 +1,7 @@
+use crate::capnp::capnp_rpc::common_capnp::{any_value, key_value};
+use opentelemetry::logs::AnyValue;
+use opentelemetry::Key;
+use std::collections::HashMap;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub(crate) fn to_nanos(time: SystemTime) -> u64 {
@@ -5,3 +9,92 @@ pub(crate) fn to_nanos(time: SystemTime) -> u64 {
         .unwrap_or_else(|_| Duration::from_secs(0))
         .as_nanos() as u64
 }
+
+/// Populate `builder` with `value`, including byte arrays and arbitrarily
+/// nested lists and maps such as the bodies and attributes of log records.
+/// Kinds of values added to [AnyValue] after this crate was written are sent
+/// as their `Debug` string.
+pub fn populate_any_value(
+    builder: any_value::Builder<'_>,
+    value: &AnyValue,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut value_builder = builder.init_value();
+    match value {
+        AnyValue::Int(val) => value_builder.set_int_value(*val),
+        AnyValue::Double(val) => value_builder.set_double_value(*val),
+        AnyValue::String(val) => value_builder.set_string_value(val.as_str()),
+        AnyValue::Boolean(val) => value_builder.set_bool_value(*val),
+        AnyValue::Bytes(bytes) => value_builder.set_bytes_value(bytes),
+        AnyValue::ListAny(list) => {
+            let mut values_builder = value_builder
+                .init_array_value()
+                .init_values(list.len() as u32);
+            for (idx, item) in list.iter().enumerate() {
+                populate_any_value(values_builder.reborrow().get(idx as u32), item)?;
+            }
+        }
+        AnyValue::Map(map) => {
+            let mut values_builder = value_builder
+                .init_kvlist_value()
+                .init_values(map.len() as u32);
+            for (idx, (key, item)) in map.iter().enumerate() {
+                let mut kv_builder = values_builder.reborrow().get(idx as u32);
+                kv_builder.set_key(key.as_str());
+                populate_any_value(kv_builder.init_value(), item)?;
+            }
+        }
+        other => set_debug_string(value_builder, other),
+    }
+    Ok(())
+}
+
+/// Send a value this crate has no Cap'n Proto representation for as its
+/// `Debug` string rather than failing the whole export request.
+pub(crate) fn set_debug_string(
+    mut value_builder: any_value::value::Builder<'_>,
+    value: &dyn std::fmt::Debug,
+) {
+    let value = format!("{value:?}");
+    opentelemetry::otel_debug!(
+        name: "CapnpTransform.UnsupportedValue",
+        value = value.as_str(),
+        message = "Sending a value of an unsupported kind as its Debug string",
+    );
+    value_builder.set_string_value(value.as_str());
+}
+
+/// Read an [AnyValue] back from `reader`. Every value the Cap'n Proto schema
+/// can hold, including attributes encoded from span [opentelemetry::Value]s,
+/// is an [AnyValue].
+pub fn read_any_value(reader: any_value::Reader<'_>) -> capnp::Result<AnyValue> {
+    use any_value::value::Which;
+
+    Ok(match reader.get_value().which()? {
+        Which::StringValue(val) => AnyValue::from(val?.to_string()?),
+        Which::BoolValue(val) => AnyValue::Boolean(val),
+        Which::IntValue(val) => AnyValue::Int(val),
+        Which::DoubleValue(val) => AnyValue::Double(val),
+        Which::BytesValue(val) => AnyValue::Bytes(Box::new(val?.to_vec())),
+        Which::ArrayValue(array) => AnyValue::ListAny(Box::new(
+            array?
+                .get_values()?
+                .iter()
+                .map(read_any_value)
+                .collect::<capnp::Result<_>>()?,
+        )),
+        Which::KvlistValue(kvlist) => AnyValue::Map(Box::new(
+            kvlist?
+                .get_values()?
+                .iter()
+                .map(read_key_value)
+                .collect::<capnp::Result<HashMap<_, _>>>()?,
+        )),
+    })
+}
+
+fn read_key_value(reader: key_value::Reader<'_>) -> capnp::Result<(Key, AnyValue)> {
+    Ok((
+        Key::from(reader.get_key()?.to_string()?),
+        read_any_value(reader.get_value()?)?,
+    ))
+}
@@ -1,14 +1,24 @@
 use crate::capnp::capnp_rpc::common_capnp::{self, any_value::Builder};
-use crate::capnp::capnp_rpc::trace_capnp;
-use crate::transform::common::to_nanos;
-use opentelemetry::trace::{self, SpanKind};
+use crate::capnp::capnp_rpc::{export_trace_service_request, trace_capnp};
+use crate::transform::common::{set_debug_string, to_nanos};
+use opentelemetry::trace::{self, SpanKind, TraceFlags};
 use opentelemetry::{InstrumentationScope, KeyValue, Value};
 use opentelemetry_sdk::{trace::SpanData, Resource};
 use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::iter::Iterator;
 use std::sync::Arc;
 use std::time::UNIX_EPOCH;
 
+/// Bits 0-7 of `Span.flags` and `Link.flags` are the W3C trace flags.
+pub const SPAN_FLAGS_TRACE_FLAGS_MASK: u32 = 0x0000_00FF;
+/// Bit 8 of `Span.flags` and `Link.flags` is set when it is known whether
+/// the parent span or linked span is remote.
+pub const SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK: u32 = 0x0000_0100;
+/// Bit 9 of `Span.flags` and `Link.flags` is set when the parent span or
+/// linked span is remote.
+pub const SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK: u32 = 0x0000_0200;
+
 // How much of SpanRequest, ResourceSpans, and ScopeSpans can be
 // switched to references for performance improvements?
 
@@ -105,9 +115,85 @@ impl From<&trace::Status> for trace_capnp::status::StatusCode {
     }
 }
 
+/// Populate an export request with every resource in `resource_spans`, each
+/// with all of its scopes and their spans.
+pub fn populate_export_request(
+    builder: export_trace_service_request::Builder<'_>,
+    resource_spans: &[ResourceSpans],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut resource_spans_builder = builder.init_resource_spans(resource_spans.len() as u32);
+    for (resource_idx, resource_spans) in resource_spans.iter().enumerate() {
+        let mut builder_for_resource_spans =
+            resource_spans_builder.reborrow().get(resource_idx as u32);
+        populate_resource(
+            builder_for_resource_spans.reborrow().init_resource(),
+            resource_spans.resource.clone(),
+        )?;
+        let mut scope_spans_builder = builder_for_resource_spans
+            .reborrow()
+            .init_scope_spans(resource_spans.scope_spans.len() as u32);
+        for (scope_idx, scope_spans) in resource_spans.scope_spans.iter().enumerate() {
+            populate_scope_spans(
+                scope_spans_builder.reborrow().get(scope_idx as u32),
+                scope_spans,
+            )?;
+        }
+        builder_for_resource_spans.set_schema_url(&resource_spans.schema_url);
+    }
+    Ok(())
+}
+
+/// Populate an export request with the spans of `batch`, which all belong to
+/// `resource`, under one scope per instrumentation scope in the order the
+/// scopes first appear.
+///
+/// Unlike [populate_export_request] the spans are encoded straight from the
+/// batch, so they are not copied into [ScopeSpans] first.
+pub fn populate_export_request_from_batch(
+    builder: export_trace_service_request::Builder<'_>,
+    resource: &Resource,
+    batch: &[SpanData],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut scopes: Vec<(&InstrumentationScope, Vec<&SpanData>)> = Vec::new();
+    let mut scope_indices: HashMap<&InstrumentationScope, usize> = HashMap::new();
+    for span in batch {
+        let scope_idx = *scope_indices
+            .entry(&span.instrumentation_scope)
+            .or_insert_with(|| {
+                scopes.push((&span.instrumentation_scope, Vec::new()));
+                scopes.len() - 1
+            });
+        scopes[scope_idx].1.push(span);
+    }
+
+    let mut resource_spans_builder = builder.init_resource_spans(1).get(0);
+    populate_resource_attributes(resource_spans_builder.reborrow().init_resource(), resource)?;
+    let mut scope_spans_builder = resource_spans_builder
+        .reborrow()
+        .init_scope_spans(scopes.len() as u32);
+    for (scope_idx, (scope, spans)) in scopes.iter().enumerate() {
+        let mut builder = scope_spans_builder.reborrow().get(scope_idx as u32);
+        populate_instrumentation_scope(builder.reborrow().init_scope(), scope)?;
+        let mut spans_builder = builder.reborrow().init_spans(spans.len() as u32);
+        for (span_idx, span) in spans.iter().enumerate() {
+            populate_span(spans_builder.reborrow().get(span_idx as u32), span)?;
+        }
+        builder.set_schema_url(scope.schema_url().unwrap_or_default());
+    }
+    resource_spans_builder.set_schema_url(resource.schema_url().unwrap_or_default());
+    Ok(())
+}
+
 pub fn populate_resource(
-    mut resource_builder: crate::resource_capnp::resource::Builder<'_>,
+    resource_builder: crate::resource_capnp::resource::Builder<'_>,
     resource: Arc<Resource>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    populate_resource_attributes(resource_builder, &resource)
+}
+
+fn populate_resource_attributes(
+    mut resource_builder: crate::resource_capnp::resource::Builder<'_>,
+    resource: &Resource,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let attributes_builder = resource_builder
         .reborrow()
@@ -125,23 +211,23 @@ pub fn populate_resource(
 
 pub fn populate_scope_spans(
     mut builder: trace_capnp::scope_spans::Builder,
-    scope_spans: ScopeSpans,
+    scope_spans: &ScopeSpans,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let instrumentation_builder = builder.reborrow().init_scope();
     if let Some(instrumentation) = scope_spans.get_scope() {
         populate_instrumentation_scope(instrumentation_builder, instrumentation)?;
     }
     let scope_spans_builder = builder.reborrow().init_spans(scope_spans.len() as u32);
-    populate_scope_spans_builder(scope_spans_builder, scope_spans.spans)?;
-    builder.reborrow().set_schema_url(scope_spans.schema_url);
+    populate_scope_spans_builder(scope_spans_builder, &scope_spans.spans)?;
+    builder.reborrow().set_schema_url(&scope_spans.schema_url);
     Ok(())
 }
 
 fn populate_scope_spans_builder(
     mut scope_spans_builder: capnp::struct_list::Builder<'_, trace_capnp::span::Owned>,
-    span_records: Vec<SpanData>,
+    span_records: &[SpanData],
 ) -> Result<(), Box<dyn std::error::Error>> {
-    for (idx, span) in span_records.into_iter().enumerate() {
+    for (idx, span) in span_records.iter().enumerate() {
         let span_builder = scope_spans_builder.reborrow().get(idx as u32);
         populate_span(span_builder, span)?;
     }
@@ -171,14 +257,17 @@ fn populate_instrumentation_scope(
 
 pub fn populate_span(
     mut builder: trace_capnp::span::Builder,
-    source_span: SpanData,
+    source_span: &SpanData,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let span_kind: trace_capnp::span::SpanKind = source_span.span_kind.into();
+    let span_kind: trace_capnp::span::SpanKind = source_span.span_kind.clone().into();
     builder.set_trace_id(&source_span.span_context.trace_id().to_bytes());
     builder.set_span_id(&source_span.span_context.span_id().to_bytes());
     builder.set_trace_state(source_span.span_context.trace_state().header());
     builder.set_parent_span_id(&source_span.parent_span_id.to_bytes());
-    // TODO: set flags
+    builder.set_flags(build_span_flags(
+        source_span.span_context.trace_flags(),
+        source_span.parent_span_is_remote,
+    ));
     builder.set_name(&source_span.name);
     builder.set_kind(span_kind);
     // Timestamps
@@ -193,7 +282,7 @@ pub fn populate_span(
     // // Set kind to Internal as default
     // builder.set_kind(trace_capnp::span::SpanKind::SpanKindInternal);
 
-    let attributes = source_span.attributes;
+    let attributes = &source_span.attributes;
     let attributes_builder = builder.reborrow().init_attributes(attributes.len() as u32);
     populate_attributes(attributes_builder, attributes)?;
     builder.set_dropped_events_count(source_span.events.dropped_count);
@@ -201,16 +290,16 @@ pub fn populate_span(
     let mut events_builder = builder
         .reborrow()
         .init_events(source_span.events.len() as u32);
-    for (id, event) in source_span.events.events.into_iter().enumerate() {
+    for (id, event) in source_span.events.iter().enumerate() {
         let mut event_builder = events_builder.reborrow().get(id as u32);
         event_builder
             .reborrow()
             .set_time_unix_nano(to_nanos(event.timestamp));
-        event_builder.reborrow().set_name(event.name.into_owned());
+        event_builder.reborrow().set_name(event.name.as_ref());
         let event_attributes_builder = event_builder
             .reborrow()
             .init_attributes(event.attributes.len() as u32);
-        populate_attributes(event_attributes_builder, event.attributes)?;
+        populate_attributes(event_attributes_builder, &event.attributes)?;
         event_builder
             .reborrow()
             .set_dropped_attributes_count(event.dropped_attributes_count);
@@ -220,7 +309,7 @@ pub fn populate_span(
     let mut links_builder = builder
         .reborrow()
         .init_links(source_span.links.len() as u32);
-    for (id, link) in source_span.links.into_iter().enumerate() {
+    for (id, link) in source_span.links.iter().enumerate() {
         let mut link_builder = links_builder.reborrow().get(id as u32);
         link_builder
             .reborrow()
@@ -237,11 +326,12 @@ pub fn populate_span(
         let attr_builder = link_builder
             .reborrow()
             .init_attributes(link.attributes.len() as u32);
-        populate_attributes(attr_builder, link.attributes)?;
-        // link_builder.set_flags();
+        populate_attributes(attr_builder, &link.attributes)?;
+        link_builder.set_flags(build_span_flags(
+            link.span_context.trace_flags(),
+            link.span_context.is_remote(),
+        ));
     }
-    //TODO:
-    // - links: spanflags
     let mut status = builder.init_status();
     status.set_code(trace_capnp::status::StatusCode::from(&source_span.status));
     status.set_message(match &source_span.status {
@@ -280,25 +370,26 @@ fn populate_value_builder(
         Value::I64(val) => value_builder.set_int_value(*val),
         Value::F64(val) => value_builder.set_double_value(*val),
         Value::String(val) => value_builder.set_string_value(val),
-        Value::Array(arr) => {
-            populate_array(value_builder.init_array_value(), arr)?;
-        }
-        _ => {
-            value_builder.set_string_value("unsupported");
-        }
+        Value::Array(arr) => populate_array(value_builder, arr),
+        other => set_debug_string(value_builder, other),
     }
     Ok(())
 }
 
+/// Populate `value_builder` with `array`, or with its `Debug` string for
+/// kinds of arrays added to [opentelemetry::Array] after this crate was
+/// written.
 fn populate_array(
-    array_value_builder: common_capnp::array_value::Builder<'_>,
+    value_builder: common_capnp::any_value::value::Builder<'_>,
     array: &opentelemetry::Array,
-) -> Result<(), Box<dyn std::error::Error>> {
+) {
     use opentelemetry::Array;
 
     match array {
         Array::Bool(bools) => {
-            let mut values = array_value_builder.init_values(bools.len() as u32);
+            let mut values = value_builder
+                .init_array_value()
+                .init_values(bools.len() as u32);
             for (idx, &b) in bools.iter().enumerate() {
                 values
                     .reborrow()
@@ -308,7 +399,9 @@ fn populate_array(
             }
         }
         Array::I64(ints) => {
-            let mut values = array_value_builder.init_values(ints.len() as u32);
+            let mut values = value_builder
+                .init_array_value()
+                .init_values(ints.len() as u32);
             for (idx, &i) in ints.iter().enumerate() {
                 values
                     .reborrow()
@@ -318,7 +411,9 @@ fn populate_array(
             }
         }
         Array::F64(floats) => {
-            let mut values = array_value_builder.init_values(floats.len() as u32);
+            let mut values = value_builder
+                .init_array_value()
+                .init_values(floats.len() as u32);
             for (idx, &f) in floats.iter().enumerate() {
                 values
                     .reborrow()
@@ -328,7 +423,9 @@ fn populate_array(
             }
         }
         Array::String(strings) => {
-            let mut values = array_value_builder.init_values(strings.len() as u32);
+            let mut values = value_builder
+                .init_array_value()
+                .init_values(strings.len() as u32);
             for (idx, s) in strings.iter().enumerate() {
                 values
                     .reborrow()
@@ -337,17 +434,16 @@ fn populate_array(
                     .set_string_value(s.as_ref());
             }
         }
-        _ => {}
+        other => set_debug_string(value_builder, other),
     }
-    Ok(())
 }
 
-// pub(crate) fn build_span_flags(parent_span_is_remote: bool, base_flags: u32) -> u32 {
-//     use crate::;
-//     let mut flags = base_flags;
-//     flags |= SpanFlags::ContextHasIsRemoteMask as u32;
-//     if parent_span_is_remote {
-//         flags |= SpanFlags::ContextIsRemoteMask as u32;
-//     }
-//     flags
-// }
+/// Combine the W3C `trace_flags` with the remote bits; whether the context
+/// is remote is always known when exporting from the SDK.
+fn build_span_flags(trace_flags: TraceFlags, is_remote: bool) -> u32 {
+    let mut flags = trace_flags.to_u8() as u32 | SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK;
+    if is_remote {
+        flags |= SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK;
+    }
+    flags
+}
@@ -0,0 +1,192 @@
+use opentelemetry::logs::AnyValue;
+use opentelemetry::trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId};
+use opentelemetry::{Array, InstrumentationScope, Key, KeyValue, StringValue, Value};
+use opentelemetry_capnp::capnp::capnp_rpc::{common_capnp, export_trace_service_request};
+use opentelemetry_capnp::transform::common::{populate_any_value, read_any_value};
+use opentelemetry_capnp::transform::trace::{populate_export_request, ResourceSpans, ScopeSpans};
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use opentelemetry_sdk::Resource;
+use proptest::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Any `f64` but NaN, which never compares equal to itself.
+fn double() -> impl Strategy<Value = f64> {
+    any::<f64>().prop_filter("NaN is not equal to itself", |val| !val.is_nan())
+}
+
+/// Every shape of [AnyValue], with lists and maps nested a few levels deep.
+fn any_value() -> impl Strategy<Value = AnyValue> {
+    let leaf = prop_oneof![
+        any::<i64>().prop_map(AnyValue::Int),
+        double().prop_map(AnyValue::Double),
+        any::<String>().prop_map(AnyValue::from),
+        any::<bool>().prop_map(AnyValue::Boolean),
+        any::<Vec<u8>>().prop_map(|bytes| AnyValue::Bytes(Box::new(bytes))),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8)
+                .prop_map(|list| AnyValue::ListAny(Box::new(list))),
+            prop::collection::hash_map(any::<String>().prop_map(Key::from), inner, 0..8)
+                .prop_map(|map| AnyValue::Map(Box::new(map))),
+        ]
+    })
+}
+
+/// Every shape of span attribute [Value], including empty arrays.
+fn value() -> impl Strategy<Value = Value> {
+    let string = any::<String>().prop_map(StringValue::from);
+    prop_oneof![
+        any::<bool>().prop_map(Value::Bool),
+        any::<i64>().prop_map(Value::I64),
+        double().prop_map(Value::F64),
+        string.clone().prop_map(Value::String),
+        prop::collection::vec(any::<bool>(), 0..8).prop_map(|vals| Value::Array(Array::Bool(vals))),
+        prop::collection::vec(any::<i64>(), 0..8).prop_map(|vals| Value::Array(Array::I64(vals))),
+        prop::collection::vec(double(), 0..8).prop_map(|vals| Value::Array(Array::F64(vals))),
+        prop::collection::vec(string, 0..8).prop_map(|vals| Value::Array(Array::String(vals))),
+    ]
+}
+
+/// The [AnyValue] a span attribute [Value] is sent as.
+fn as_any_value(value: &Value) -> AnyValue {
+    match value {
+        Value::Bool(val) => AnyValue::Boolean(*val),
+        Value::I64(val) => AnyValue::Int(*val),
+        Value::F64(val) => AnyValue::Double(*val),
+        Value::String(val) => AnyValue::String(val.clone()),
+        Value::Array(Array::Bool(vals)) => vals.iter().copied().collect(),
+        Value::Array(Array::I64(vals)) => vals.iter().copied().collect(),
+        Value::Array(Array::F64(vals)) => vals.iter().copied().collect(),
+        Value::Array(Array::String(vals)) => vals.iter().cloned().collect(),
+        other => panic!("no test conversion for {other:?}"),
+    }
+}
+
+fn round_trip_any_value(value: &AnyValue) -> AnyValue {
+    let mut message = capnp::message::Builder::new_default();
+    populate_any_value(
+        message.init_root::<common_capnp::any_value::Builder>(),
+        value,
+    )
+    .expect("populate AnyValue");
+    let reader = message
+        .get_root_as_reader::<common_capnp::any_value::Reader>()
+        .expect("read AnyValue");
+    read_any_value(reader).expect("decode AnyValue")
+}
+
+/// Encode a span with `attributes`, read it back and decode its attributes.
+fn round_trip_span_attributes(attributes: Vec<KeyValue>) -> Vec<(String, AnyValue)> {
+    let scope = InstrumentationScope::builder("values").build();
+    let span = SpanData {
+        span_context: SpanContext::new(
+            TraceId::from(0x0123456789abcdef0123456789abcdef),
+            SpanId::from(0x0123456789abcdef),
+            TraceFlags::SAMPLED,
+            false,
+            Default::default(),
+        ),
+        parent_span_id: SpanId::INVALID,
+        parent_span_is_remote: false,
+        instrumentation_scope: scope.clone(),
+        dropped_attributes_count: 0,
+        span_kind: SpanKind::Internal,
+        name: Cow::Borrowed("attributed"),
+        start_time: SystemTime::now(),
+        end_time: SystemTime::now(),
+        attributes,
+        events: SpanEvents::default(),
+        links: SpanLinks::default(),
+        status: Status::Unset,
+    };
+    let resource_spans = ResourceSpans {
+        resource: Arc::new(Resource::builder_empty().build()),
+        scope_spans: vec![ScopeSpans {
+            scope: Some(scope),
+            spans: vec![span],
+            schema_url: String::new(),
+        }],
+        schema_url: String::new(),
+    };
+    let mut message = capnp::message::Builder::new_default();
+    populate_export_request(
+        message.init_root::<export_trace_service_request::Builder>(),
+        &[resource_spans],
+    )
+    .expect("populate export request");
+    let request = message
+        .get_root_as_reader::<export_trace_service_request::Reader>()
+        .expect("read export request");
+
+    let span = request
+        .get_resource_spans()
+        .unwrap()
+        .get(0)
+        .get_scope_spans()
+        .unwrap()
+        .get(0)
+        .get_spans()
+        .unwrap()
+        .get(0);
+    span.get_attributes()
+        .unwrap()
+        .iter()
+        .map(|attribute| {
+            (
+                attribute.get_key().unwrap().to_string().unwrap(),
+                read_any_value(attribute.get_value().unwrap()).expect("decode attribute"),
+            )
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn any_value_round_trips(value in any_value()) {
+        prop_assert_eq!(round_trip_any_value(&value), value);
+    }
+
+    #[test]
+    fn span_attributes_round_trip(
+        values in prop::collection::vec(value(), 0..8)
+    ) {
+        let attributes: Vec<KeyValue> = values
+            .into_iter()
+            .enumerate()
+            .map(|(idx, value)| KeyValue::new(format!("attribute.{idx}"), value))
+            .collect();
+        let expected: Vec<(String, AnyValue)> = attributes
+            .iter()
+            .map(|kv| (kv.key.to_string(), as_any_value(&kv.value)))
+            .collect();
+
+        prop_assert_eq!(round_trip_span_attributes(attributes), expected);
+    }
+}
+
+#[test]
+fn nested_log_body_round_trips() {
+    let body = AnyValue::Map(Box::new(HashMap::from([
+        (Key::from("user"), AnyValue::from("alice")),
+        (Key::from("payload"), AnyValue::from(&b"\x00\xffbinary"[..])),
+        (
+            Key::from("items"),
+            AnyValue::ListAny(Box::new(vec![
+                AnyValue::Int(1),
+                AnyValue::Double(2.5),
+                AnyValue::Boolean(false),
+                AnyValue::ListAny(Box::default()),
+                AnyValue::Map(Box::new(HashMap::from([(
+                    Key::from("depth"),
+                    AnyValue::Int(2),
+                )]))),
+            ])),
+        ),
+    ])));
+
+    assert_eq!(round_trip_any_value(&body), body);
+}
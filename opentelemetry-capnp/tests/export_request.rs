@@ -0,0 +1,169 @@
+use opentelemetry::trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId};
+use opentelemetry::{InstrumentationScope, KeyValue};
+use opentelemetry_capnp::capnp::capnp_rpc::{common_capnp, export_trace_service_request};
+use opentelemetry_capnp::transform::trace::{populate_export_request, ResourceSpans, ScopeSpans};
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use opentelemetry_sdk::Resource;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+fn span(name: &'static str, scope: &InstrumentationScope) -> SpanData {
+    SpanData {
+        span_context: SpanContext::new(
+            TraceId::from(0x0123456789abcdef0123456789abcdef),
+            SpanId::from(0x0123456789abcdef),
+            TraceFlags::SAMPLED,
+            false,
+            Default::default(),
+        ),
+        parent_span_id: SpanId::INVALID,
+        parent_span_is_remote: false,
+        instrumentation_scope: scope.clone(),
+        dropped_attributes_count: 0,
+        span_kind: SpanKind::Internal,
+        name: Cow::Borrowed(name),
+        start_time: SystemTime::now(),
+        end_time: SystemTime::now(),
+        attributes: Vec::new(),
+        events: SpanEvents::default(),
+        links: SpanLinks::default(),
+        status: Status::Unset,
+    }
+}
+
+/// Resource spans for `service` with one scope per entry of `scopes`, each
+/// holding the given span names.
+fn resource_spans(
+    service: &'static str,
+    scopes: &[(&'static str, &[&'static str])],
+) -> ResourceSpans {
+    let resource = Resource::builder_empty()
+        .with_attribute(KeyValue::new("service.name", service))
+        .build();
+    ResourceSpans {
+        resource: Arc::new(resource),
+        scope_spans: scopes
+            .iter()
+            .map(|(scope_name, span_names)| {
+                let scope = InstrumentationScope::builder(*scope_name).build();
+                ScopeSpans {
+                    spans: span_names.iter().map(|name| span(name, &scope)).collect(),
+                    scope: Some(scope),
+                    schema_url: format!("https://{scope_name}"),
+                }
+            })
+            .collect(),
+        schema_url: format!("https://{service}"),
+    }
+}
+
+/// Encode `resource_spans`, read the request back and list every span as
+/// `(service.name, scope name, span name)`.
+fn round_trip(resource_spans: &[ResourceSpans]) -> Vec<(String, String, String)> {
+    let mut message = capnp::message::Builder::new_default();
+    populate_export_request(
+        message.init_root::<export_trace_service_request::Builder>(),
+        resource_spans,
+    )
+    .expect("populate export request");
+    let request = message
+        .get_root_as_reader::<export_trace_service_request::Reader>()
+        .expect("read export request");
+
+    let mut spans = Vec::new();
+    for resource_spans in request.get_resource_spans().unwrap() {
+        let attributes = resource_spans
+            .get_resource()
+            .unwrap()
+            .get_attributes()
+            .unwrap();
+        assert_eq!(attributes.len(), 1);
+        let attribute = attributes.get(0);
+        assert_eq!(
+            attribute.get_key().unwrap().to_str().unwrap(),
+            "service.name"
+        );
+        let service = match attribute.get_value().unwrap().get_value().which().unwrap() {
+            common_capnp::any_value::value::StringValue(value) => {
+                value.unwrap().to_string().unwrap()
+            }
+            _ => panic!("service.name is not a string"),
+        };
+        assert_eq!(
+            resource_spans.get_schema_url().unwrap().to_str().unwrap(),
+            format!("https://{service}")
+        );
+        for scope_spans in resource_spans.get_scope_spans().unwrap() {
+            let scope = scope_spans
+                .get_scope()
+                .unwrap()
+                .get_name()
+                .unwrap()
+                .to_string()
+                .unwrap();
+            assert_eq!(
+                scope_spans.get_schema_url().unwrap().to_str().unwrap(),
+                format!("https://{scope}")
+            );
+            for span in scope_spans.get_spans().unwrap() {
+                let name = span.get_name().unwrap().to_string().unwrap();
+                spans.push((service.clone(), scope.clone(), name));
+            }
+        }
+    }
+    spans
+}
+
+fn expected(spans: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+    spans
+        .iter()
+        .map(|(service, scope, name)| (service.to_string(), scope.to_string(), name.to_string()))
+        .collect()
+}
+
+#[test]
+fn every_scope_of_a_resource_is_encoded() {
+    let spans = round_trip(&[resource_spans(
+        "checkout",
+        &[
+            ("http", &["GET /cart", "POST /cart"]),
+            ("db", &["SELECT"]),
+            ("cache", &["GET"]),
+        ],
+    )]);
+    assert_eq!(
+        spans,
+        expected(&[
+            ("checkout", "http", "GET /cart"),
+            ("checkout", "http", "POST /cart"),
+            ("checkout", "db", "SELECT"),
+            ("checkout", "cache", "GET"),
+        ])
+    );
+}
+
+#[test]
+fn every_resource_is_encoded() {
+    let spans = round_trip(&[
+        resource_spans("checkout", &[("http", &["GET /cart"]), ("db", &["SELECT"])]),
+        resource_spans(
+            "payments",
+            &[("http", &["POST /charge"]), ("queue", &["publish"])],
+        ),
+    ]);
+    assert_eq!(
+        spans,
+        expected(&[
+            ("checkout", "http", "GET /cart"),
+            ("checkout", "db", "SELECT"),
+            ("payments", "http", "POST /charge"),
+            ("payments", "queue", "publish"),
+        ])
+    );
+}
+
+#[test]
+fn empty_request_has_no_resources() {
+    assert!(round_trip(&[]).is_empty());
+}
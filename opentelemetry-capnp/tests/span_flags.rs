@@ -0,0 +1,133 @@
+use opentelemetry::trace::{Link, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId};
+use opentelemetry::InstrumentationScope;
+use opentelemetry_capnp::capnp::capnp_rpc::export_trace_service_request;
+use opentelemetry_capnp::transform::trace::{
+    populate_export_request, ResourceSpans, ScopeSpans, SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK,
+    SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK, SPAN_FLAGS_TRACE_FLAGS_MASK,
+};
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use opentelemetry_sdk::Resource;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+fn span_context(trace_flags: TraceFlags, is_remote: bool) -> SpanContext {
+    SpanContext::new(
+        TraceId::from(0x0123456789abcdef0123456789abcdef),
+        SpanId::from(0x0123456789abcdef),
+        trace_flags,
+        is_remote,
+        Default::default(),
+    )
+}
+
+fn span(trace_flags: TraceFlags, parent_span_is_remote: bool, links: Vec<Link>) -> SpanData {
+    let mut span_links = SpanLinks::default();
+    span_links.links = links;
+    SpanData {
+        span_context: span_context(trace_flags, false),
+        parent_span_id: SpanId::from(0xfedcba9876543210),
+        parent_span_is_remote,
+        instrumentation_scope: InstrumentationScope::builder("flags").build(),
+        dropped_attributes_count: 0,
+        span_kind: SpanKind::Internal,
+        name: Cow::Borrowed("flagged"),
+        start_time: SystemTime::now(),
+        end_time: SystemTime::now(),
+        attributes: Vec::new(),
+        events: SpanEvents::default(),
+        links: span_links,
+        status: Status::Unset,
+    }
+}
+
+fn link(trace_flags: TraceFlags, is_remote: bool) -> Link {
+    Link::with_context(span_context(trace_flags, is_remote))
+}
+
+/// Encode `span`, read it back and return the flags of the span and of each
+/// of its links.
+fn round_trip(span: SpanData) -> (u32, Vec<u32>) {
+    let resource_spans = ResourceSpans {
+        resource: Arc::new(Resource::builder_empty().build()),
+        scope_spans: vec![ScopeSpans {
+            scope: Some(span.instrumentation_scope.clone()),
+            spans: vec![span],
+            schema_url: String::new(),
+        }],
+        schema_url: String::new(),
+    };
+    let mut message = capnp::message::Builder::new_default();
+    populate_export_request(
+        message.init_root::<export_trace_service_request::Builder>(),
+        &[resource_spans],
+    )
+    .expect("populate export request");
+    let request = message
+        .get_root_as_reader::<export_trace_service_request::Reader>()
+        .expect("read export request");
+
+    let span = request
+        .get_resource_spans()
+        .unwrap()
+        .get(0)
+        .get_scope_spans()
+        .unwrap()
+        .get(0)
+        .get_spans()
+        .unwrap()
+        .get(0);
+    let link_flags = span
+        .get_links()
+        .unwrap()
+        .iter()
+        .map(|link| link.get_flags())
+        .collect();
+    (span.get_flags(), link_flags)
+}
+
+#[test]
+fn sampled_span_with_local_parent() {
+    let (flags, _) = round_trip(span(TraceFlags::SAMPLED, false, Vec::new()));
+
+    assert_eq!(flags & SPAN_FLAGS_TRACE_FLAGS_MASK, 0x01);
+    assert_ne!(flags & SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK, 0);
+    assert_eq!(flags & SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK, 0);
+}
+
+#[test]
+fn unsampled_span_with_remote_parent() {
+    let (flags, _) = round_trip(span(TraceFlags::NOT_SAMPLED, true, Vec::new()));
+
+    assert_eq!(flags & SPAN_FLAGS_TRACE_FLAGS_MASK, 0x00);
+    assert_ne!(flags & SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK, 0);
+    assert_ne!(flags & SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK, 0);
+}
+
+#[test]
+fn every_trace_flag_bit_is_kept() {
+    let (flags, _) = round_trip(span(TraceFlags::new(0xff), false, Vec::new()));
+
+    assert_eq!(flags, 0xff | SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK);
+}
+
+#[test]
+fn links_carry_their_own_flags() {
+    let (flags, link_flags) = round_trip(span(
+        TraceFlags::SAMPLED,
+        false,
+        vec![
+            link(TraceFlags::SAMPLED, true),
+            link(TraceFlags::NOT_SAMPLED, false),
+        ],
+    ));
+
+    assert_eq!(flags, 0x01 | SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK);
+    assert_eq!(
+        link_flags,
+        [
+            0x01 | SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK | SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK,
+            SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK,
+        ]
+    );
+}
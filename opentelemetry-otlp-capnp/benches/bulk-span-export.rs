@@ -2,21 +2,31 @@ use criterion::Criterion;
 use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId};
 use opentelemetry_capnp::transform::trace::SpanRequest;
 use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig as _};
+use opentelemetry_otlp_capnp::{Compression, SpanExporter, WithExportConfig as _};
 use opentelemetry_sdk::trace::SpanExporter as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::runtime::Runtime;
 use utilities::capnp::{receiver::NoOpSpanReceiver, span::FakeCapnp};
 use utilities::otlp;
 
-const CAPNP_ENDPOINT: &str = "127.0.0.1:4318";
 const OTLP_ENDPOINT: &str = "http://127.0.0.1:4317";
 const OTLP_RECEIVER_ADDR: &str = "127.0.0.1:4317";
+/// One Cap'n Proto receiver per wire compression, each expecting that compression.
+/// zstd and lz4 are only measured with the `zstd` and `lz4` features.
+const CAPNP_ENDPOINTS: &[(&str, Option<Compression>)] = &[
+    ("127.0.0.1:4318", None),
+    ("127.0.0.1:4319", Some(Compression::Packed)),
+    #[cfg(feature = "zstd")]
+    ("127.0.0.1:4320", Some(Compression::Zstd)),
+    #[cfg(feature = "lz4")]
+    ("127.0.0.1:4321", Some(Compression::Lz4)),
+];
+/// Receiver for the exporter that encodes the batches on the calling thread.
+const CAPNP_CALLER_ENCODED_ENDPOINT: &str = "127.0.0.1:4323";
 
 fn span_export_comparison(c: &mut Criterion) {
     let rt = Runtime::new().expect("able to create new runtime");
-    let _capnp_span_receiver = NoOpSpanReceiver::new(CAPNP_ENDPOINT)
-        .start()
-        .map_err(|e| format!("Failed to start SpanReceiver: {e}"));
     let _otlp_receiver = otlp::MinimalOtlpReceiver::new(OTLP_RECEIVER_ADDR)
         .start()
         .expect("Failed to start OTLP receiver");
@@ -26,25 +36,64 @@ fn span_export_comparison(c: &mut Criterion) {
     let req_medium = FakeCapnp::trace_service_request_with_spans(100);
     let req_large = FakeCapnp::trace_service_request_with_spans(1000);
     let input: [(&str, SpanRequest); 4] = [
-        ("single", req_single),
+        ("single", req_single.clone()),
         ("small", req_small),
         ("medium", req_medium),
         ("large", req_large),
     ];
     let mut group = c.benchmark_group("SpanExport");
-    let capnp_exporter = SpanExporter::builder()
-        .with_capnp()
-        .with_endpoint(CAPNP_ENDPOINT)
-        .build()
-        .expect("build Capnp SpanExporter with endpoint: {ENDPOINT}");
-    for (name, req) in input.iter() {
-        group.bench_with_input(BenchmarkId::new("CapnP", name), req, |b, req| {
-            b.iter_batched(
-                || req.batch.clone(),
-                |data| rt.block_on(async { capnp_exporter.export(data).await }),
-                BatchSize::PerIteration,
-            )
-        });
+    // The compressed runs trade CPU time, which shows up in the timings,
+    // against bandwidth, which is printed as the bytes each batch puts on the wire.
+    // The caller-encoded run builds the export request inside export() instead
+    // of handing the spans to the exporter thread.
+    let capnp_exporters = CAPNP_ENDPOINTS
+        .iter()
+        .map(|&(endpoint, compression)| (endpoint, compression, false))
+        .chain([(CAPNP_CALLER_ENCODED_ENDPOINT, None, true)]);
+    for (endpoint, compression, encode_on_caller) in capnp_exporters {
+        let bytes_received = Arc::new(AtomicU64::new(0));
+        let receiver = NoOpSpanReceiver::new(endpoint).with_bytes_received(bytes_received.clone());
+        let mut builder = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(endpoint)
+            .with_encoding_on_caller(encode_on_caller);
+        let id = match compression {
+            Some(compression) => {
+                builder = builder.with_compression(compression);
+                format!("CapnP-{compression}")
+            }
+            None if encode_on_caller => "CapnP-caller-encoded".to_string(),
+            None => "CapnP".to_string(),
+        };
+        let _capnp_span_receiver = match compression {
+            Some(compression) => receiver.with_compression(compression),
+            None => receiver,
+        }
+        .start()
+        .map_err(|e| format!("Failed to start SpanReceiver: {e}"));
+        let mut capnp_exporter = builder.build().expect("build Capnp SpanExporter");
+        // connect before measuring
+        rt.block_on(async { capnp_exporter.export(req_single.batch.clone()).await })
+            .expect("export batch");
+        capnp_exporter
+            .force_flush()
+            .expect("connect to SpanReceiver");
+        for (name, req) in input.iter() {
+            let before = bytes_received.load(Ordering::Relaxed);
+            rt.block_on(async { capnp_exporter.export(req.batch.clone()).await })
+                .expect("export batch");
+            println!(
+                "{id}/{name}: {} bytes on the wire per batch",
+                bytes_received.load(Ordering::Relaxed) - before
+            );
+            group.bench_with_input(BenchmarkId::new(&id, name), req, |b, req| {
+                b.iter_batched(
+                    || req.batch.clone(),
+                    |data| rt.block_on(async { capnp_exporter.export(data).await }),
+                    BatchSize::PerIteration,
+                )
+            });
+        }
     }
     let otlp_exporter = rt.block_on(async {
         opentelemetry_otlp::SpanExporter::builder()
@@ -0,0 +1,60 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use futures::future::join_all;
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use utilities::capnp::{receiver::NoOpSpanReceiver, span::FakeCapnp};
+
+const RECEIVER_ADDR: &str = "127.0.0.1:4322";
+/// Round-trip latency of the simulated link to the receiver.
+const RESPONSE_DELAY: Duration = Duration::from_millis(5);
+/// Batches exported at once in every iteration.
+const CONCURRENT_EXPORTS: usize = 32;
+const MAX_IN_FLIGHT_REQUESTS: [usize; 4] = [1, 4, 16, 32];
+
+fn pipelined_export(c: &mut Criterion) {
+    let rt = Runtime::new().expect("able to create new runtime");
+    let _capnp_span_receiver = NoOpSpanReceiver::new(RECEIVER_ADDR)
+        .with_response_delay(RESPONSE_DELAY)
+        .start()
+        .map_err(|e| format!("Failed to start SpanReceiver: {e}"));
+    let req = FakeCapnp::trace_service_request_with_spans(10);
+
+    let mut group = c.benchmark_group("PipelinedSpanExport");
+    for max_in_flight_requests in MAX_IN_FLIGHT_REQUESTS {
+        let mut capnp_exporter = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(RECEIVER_ADDR)
+            .with_max_in_flight_requests(max_in_flight_requests)
+            .build()
+            .expect("build Capnp SpanExporter");
+        // connect before measuring
+        rt.block_on(async { capnp_exporter.export(req.batch.clone()).await })
+            .expect("export batch");
+        capnp_exporter
+            .force_flush()
+            .expect("connect to SpanReceiver");
+        group.bench_with_input(
+            BenchmarkId::new("max-in-flight", max_in_flight_requests),
+            &req,
+            |b, req| {
+                b.iter_batched(
+                    || vec![req.batch.clone(); CONCURRENT_EXPORTS],
+                    |batches| {
+                        rt.block_on(join_all(
+                            batches
+                                .into_iter()
+                                .map(|batch| capnp_exporter.export(batch)),
+                        ))
+                    },
+                    BatchSize::PerIteration,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pipelined_export);
+criterion_main!(benches);
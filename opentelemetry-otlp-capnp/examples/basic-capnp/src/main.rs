@@ -8,7 +8,6 @@ use opentelemetry_sdk::Resource;
 use std::error::Error;
 use std::io;
 use std::sync::OnceLock;
-use std::time::Duration;
 use tracing::info;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
@@ -42,7 +41,7 @@ fn init_traces() -> io::Result<SdkTracerProvider> {
 async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     // Normally the SpanReceiver would run in a different process, often
     // in a different VM or machine.
-    let _span_receiver = SpanReceiver::new(TEST_ADDRESS)
+    let _span_receiver = SpanReceiver::new(TEST_ADDRESS)?
         .start()
         .map_err(|e| format!("Failed to start SpanReceiver: {e}"))?;
 
@@ -97,8 +96,5 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         )
         .into());
     }
-    // this keeps the process alive long enough for all telemetry to be exported;
-    // need to make this unnecessary
-    tokio::time::sleep(Duration::from_secs(1)).await;
     Ok(())
 }
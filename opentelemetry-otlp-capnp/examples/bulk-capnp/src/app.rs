@@ -64,9 +64,6 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         .into());
     }
-    // this keeps the process alive long enough for all telemetry to be exported;
-    // need to make this unnecessary
-    tokio::time::sleep(Duration::from_secs(1)).await;
     Ok(())
 }
 
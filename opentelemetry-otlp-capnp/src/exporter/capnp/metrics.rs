@@ -0,0 +1,211 @@
+//! Self-observability of the Cap'n Proto span exporter.
+//!
+//! The exporter records how it is doing as OpenTelemetry metrics on the
+//! [Meter] handed to [crate::WithCapnpConfig::with_meter_provider]. The same
+//! values are kept in atomics so they can be read back with
+//! [crate::SpanExporter::metrics_snapshot] whether or not a meter is set.
+use opentelemetry::metrics::{Counter, Histogram, Meter, UpDownCounter};
+use opentelemetry::InstrumentationScope;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Name of the instrumentation scope of the exporter's metrics.
+pub(crate) const METER_NAME: &str = "opentelemetry-otlp-capnp";
+
+/// The values recorded by a span exporter since it was built.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExporterMetricsSnapshot {
+    /// Count of span batches waiting for the exporter thread.
+    pub queue_depth: u64,
+    /// Count of spans the receiver acknowledged.
+    pub spans_exported: u64,
+    /// Count of spans whose export failed after all retries.
+    pub spans_failed: u64,
+    /// Count of spans the receiver reported as rejected in a partial success
+    /// response. The other spans of those batches count as exported.
+    pub spans_rejected: u64,
+    /// Count of spans dropped without an export attempt, e.g. because the
+    /// queue to the exporter thread was full.
+    pub spans_dropped: u64,
+    /// Count of export requests sent again after a failed attempt.
+    pub retries: u64,
+    /// Count of connections re-established after a receiver was lost.
+    pub reconnects: u64,
+    /// Count of export RPCs that completed, successfully or not.
+    pub rpcs: u64,
+    /// Total time the completed export RPCs took.
+    pub rpc_duration: Duration,
+}
+
+/// Records the exporter's metrics, shared by the callers and the exporter
+/// thread.
+#[derive(Debug, Default)]
+pub(crate) struct ExporterMetrics {
+    instruments: Option<Instruments>,
+    queue_depth: AtomicI64,
+    spans_exported: AtomicU64,
+    spans_failed: AtomicU64,
+    spans_rejected: AtomicU64,
+    spans_dropped: AtomicU64,
+    retries: AtomicU64,
+    reconnects: AtomicU64,
+    rpcs: AtomicU64,
+    rpc_duration_nanos: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Instruments {
+    queue_depth: UpDownCounter<i64>,
+    spans_exported: Counter<u64>,
+    spans_failed: Counter<u64>,
+    spans_rejected: Counter<u64>,
+    spans_dropped: Counter<u64>,
+    retries: Counter<u64>,
+    reconnects: Counter<u64>,
+    rpc_duration: Histogram<f64>,
+}
+
+impl Instruments {
+    fn new(meter: &Meter) -> Self {
+        Self {
+            queue_depth: meter
+                .i64_up_down_counter("capnp.exporter.queue.depth")
+                .with_description("Span batches waiting for the exporter thread")
+                .with_unit("{batch}")
+                .build(),
+            spans_exported: meter
+                .u64_counter("capnp.exporter.spans.exported")
+                .with_description("Spans acknowledged by the receiver")
+                .with_unit("{span}")
+                .build(),
+            spans_failed: meter
+                .u64_counter("capnp.exporter.spans.failed")
+                .with_description("Spans whose export failed after all retries")
+                .with_unit("{span}")
+                .build(),
+            spans_rejected: meter
+                .u64_counter("capnp.exporter.spans.rejected")
+                .with_description("Spans the receiver rejected in a partial success response")
+                .with_unit("{span}")
+                .build(),
+            spans_dropped: meter
+                .u64_counter("capnp.exporter.spans.dropped")
+                .with_description("Spans dropped without an export attempt")
+                .with_unit("{span}")
+                .build(),
+            retries: meter
+                .u64_counter("capnp.exporter.retries")
+                .with_description("Export requests sent again after a failed attempt")
+                .with_unit("{request}")
+                .build(),
+            reconnects: meter
+                .u64_counter("capnp.exporter.reconnects")
+                .with_description("Connections re-established after a receiver was lost")
+                .with_unit("{connection}")
+                .build(),
+            rpc_duration: meter
+                .f64_histogram("capnp.exporter.rpc.duration")
+                .with_description("Duration of export RPCs")
+                .with_unit("s")
+                .build(),
+        }
+    }
+}
+
+impl ExporterMetrics {
+    /// Record on `meter` if there is one; the snapshot is kept either way.
+    pub(crate) fn new(meter: Option<&Meter>) -> Self {
+        Self {
+            instruments: meter.map(Instruments::new),
+            ..Self::default()
+        }
+    }
+
+    /// The scope of the meter the exporter takes from a [opentelemetry::metrics::MeterProvider].
+    pub(crate) fn scope() -> InstrumentationScope {
+        InstrumentationScope::builder(METER_NAME)
+            .with_version(env!("CARGO_PKG_VERSION"))
+            .build()
+    }
+
+    pub(crate) fn snapshot(&self) -> ExporterMetricsSnapshot {
+        ExporterMetricsSnapshot {
+            queue_depth: self.queue_depth.load(Ordering::Relaxed).max(0) as u64,
+            spans_exported: self.spans_exported.load(Ordering::Relaxed),
+            spans_failed: self.spans_failed.load(Ordering::Relaxed),
+            spans_rejected: self.spans_rejected.load(Ordering::Relaxed),
+            spans_dropped: self.spans_dropped.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            rpcs: self.rpcs.load(Ordering::Relaxed),
+            rpc_duration: Duration::from_nanos(self.rpc_duration_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Track span batches entering (`delta > 0`) or leaving the export queue.
+    pub(crate) fn queue_changed(&self, delta: i64) {
+        self.queue_depth.fetch_add(delta, Ordering::Relaxed);
+        if let Some(instruments) = &self.instruments {
+            instruments.queue_depth.add(delta, &[]);
+        }
+    }
+
+    pub(crate) fn spans_exported(&self, count: usize) {
+        self.spans_exported
+            .fetch_add(count as u64, Ordering::Relaxed);
+        if let Some(instruments) = &self.instruments {
+            instruments.spans_exported.add(count as u64, &[]);
+        }
+    }
+
+    pub(crate) fn spans_failed(&self, count: usize) {
+        self.spans_failed.fetch_add(count as u64, Ordering::Relaxed);
+        if let Some(instruments) = &self.instruments {
+            instruments.spans_failed.add(count as u64, &[]);
+        }
+    }
+
+    pub(crate) fn spans_rejected(&self, count: usize) {
+        self.spans_rejected
+            .fetch_add(count as u64, Ordering::Relaxed);
+        if let Some(instruments) = &self.instruments {
+            instruments.spans_rejected.add(count as u64, &[]);
+        }
+    }
+
+    /// Returns the count of spans dropped since the exporter was built.
+    pub(crate) fn spans_dropped(&self, count: usize) -> u64 {
+        let total = self
+            .spans_dropped
+            .fetch_add(count as u64, Ordering::Relaxed)
+            + count as u64;
+        if let Some(instruments) = &self.instruments {
+            instruments.spans_dropped.add(count as u64, &[]);
+        }
+        total
+    }
+
+    pub(crate) fn retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+        if let Some(instruments) = &self.instruments {
+            instruments.retries.add(1, &[]);
+        }
+    }
+
+    pub(crate) fn reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        if let Some(instruments) = &self.instruments {
+            instruments.reconnects.add(1, &[]);
+        }
+    }
+
+    pub(crate) fn rpc_completed(&self, duration: Duration) {
+        self.rpcs.fetch_add(1, Ordering::Relaxed);
+        self.rpc_duration_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        if let Some(instruments) = &self.instruments {
+            instruments.rpc_duration.record(duration.as_secs_f64(), &[]);
+        }
+    }
+}
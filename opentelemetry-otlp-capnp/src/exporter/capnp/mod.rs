@@ -1,13 +1,50 @@
+use capnp::message::ReaderOptions;
+use opentelemetry::metrics::{Meter, MeterProvider};
+use std::collections::HashMap;
 use std::env;
-use std::io;
-use std::io::{ErrorKind, Write};
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::fmt;
+#[cfg(feature = "rt-tokio")]
+use std::io::{self, ErrorKind};
+#[cfg(feature = "rt-tokio")]
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+pub(crate) mod metrics;
+pub(crate) mod persistent_queue;
 pub(crate) mod trace;
 use crate::retry::RetryPolicy;
-use crate::span::OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT;
-use crate::{ExportConfig, ExporterBuildError};
-use crate::{OTEL_EXPORTER_CAPNP_ENDPOINT, OTEL_EXPORTER_CAPNP_ENDPOINT_DEFAULT};
+use crate::runtime::{default_runtime, ExporterTask, Runtime};
+use crate::span::{
+    OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY,
+    OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_TIMEOUT, OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION,
+    OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT, OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT,
+    OTEL_EXPORTER_CAPNP_TRACES_HEADERS, OTEL_EXPORTER_CAPNP_TRACES_NESTING_LIMIT,
+    OTEL_EXPORTER_CAPNP_TRACES_QUEUE_CAPACITY, OTEL_EXPORTER_CAPNP_TRACES_RETRY_INITIAL_DELAY,
+    OTEL_EXPORTER_CAPNP_TRACES_RETRY_JITTER, OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_DELAY,
+    OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_RETRIES, OTEL_EXPORTER_CAPNP_TRACES_TCP_NODELAY,
+    OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT, OTEL_EXPORTER_CAPNP_TRACES_TRAVERSAL_LIMIT,
+};
+use crate::transport::{Connector, Endpoint};
+use crate::{Compression, ExportConfig, ExporterBuildError, Interceptor, Metadata};
+use crate::{
+    OTEL_EXPORTER_CAPNP_BACKPRESSURE_POLICY, OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT,
+    OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT_DEFAULT, OTEL_EXPORTER_CAPNP_COMPRESSION,
+    OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT, OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT_DEFAULT,
+    OTEL_EXPORTER_CAPNP_ENDPOINT, OTEL_EXPORTER_CAPNP_ENDPOINT_DEFAULT,
+    OTEL_EXPORTER_CAPNP_HEADERS, OTEL_EXPORTER_CAPNP_NESTING_LIMIT,
+    OTEL_EXPORTER_CAPNP_QUEUE_CAPACITY, OTEL_EXPORTER_CAPNP_RETRY_INITIAL_DELAY,
+    OTEL_EXPORTER_CAPNP_RETRY_JITTER, OTEL_EXPORTER_CAPNP_RETRY_MAX_DELAY,
+    OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES, OTEL_EXPORTER_CAPNP_TCP_NODELAY,
+    OTEL_EXPORTER_CAPNP_TIMEOUT, OTEL_EXPORTER_CAPNP_TIMEOUT_DEFAULT,
+    OTEL_EXPORTER_CAPNP_TRAVERSAL_LIMIT,
+};
+use metrics::ExporterMetrics;
+use persistent_queue::{PersistentQueue, PersistentQueueConfig};
+use trace::{
+    CapnpBlockingTracesClient, CapnpTracesClient, ClientConfig, Execution, RequestMetadata,
+    Timeouts, SPAN_EXPORTER_MAX_IN_FLIGHT_REQUESTS, SPAN_EXPORTER_MPSC_CHANNEL_BUFFER_SIZE,
+};
 
 // use crate::ExportConfig;
 /// Configuration for [capnp]
@@ -16,12 +53,96 @@ use crate::{OTEL_EXPORTER_CAPNP_ENDPOINT, OTEL_EXPORTER_CAPNP_ENDPOINT_DEFAULT};
 #[non_exhaustive]
 pub struct CapnpConfig {
     // The compression algorithm to use when communicating with the collector.
-    // pub(crate) compression: Option<Compression>,
+    pub(crate) compression: Option<Compression>,
     // pub(crate) channel: Option<tonic::transport::Channel>,
-    // pub(crate) interceptor: Option<BoxInterceptor>,
+    // Metadata sent with every export request.
+    pub(crate) headers: Option<HashMap<String, String>>,
+    // Adds or modifies the metadata of every export request.
+    pub(crate) interceptor: Option<Arc<dyn Interceptor>>,
     // The retry policy to use for gRPC requests.
     // #[cfg(feature = "experimental-grpc-retry")]
     pub(crate) retry_policy: Option<RetryPolicy>,
+    // Spill span batches to disk before exporting them.
+    pub(crate) persistent_queue: Option<PersistentQueueConfig>,
+    // How span batches are spread over the receivers.
+    pub(crate) load_balancing_strategy: LoadBalancingStrategy,
+    // Count of export requests awaiting the receiver's response at the same time.
+    pub(crate) max_in_flight_requests: Option<usize>,
+    // Encode span batches on the thread calling export.
+    pub(crate) encode_on_caller: bool,
+    // Max waiting time for a single attempt to connect to a receiver.
+    pub(crate) connect_timeout: Option<Duration>,
+    // Count of span batches waiting for the exporter thread.
+    pub(crate) queue_capacity: Option<usize>,
+    // What an export does when the queue to the exporter thread is full.
+    pub(crate) backpressure_policy: Option<BackpressurePolicy>,
+    // Limits for reading the receiver's messages.
+    pub(crate) reader_options: Option<ReaderOptions>,
+    // Disable Nagle's algorithm on TCP connections.
+    pub(crate) tcp_nodelay: Option<bool>,
+    // Records the exporter's own metrics.
+    pub(crate) meter: Option<Meter>,
+    // The async runtime the exporter runs on.
+    pub(crate) runtime: Option<Arc<dyn Runtime>>,
+    // Wrap the connection to the receiver in TLS.
+    #[cfg(feature = "tls")]
+    pub(crate) tls_config: Option<crate::tls::ClientTlsConfig>,
+}
+
+/// How the exporter picks the receiver for a span batch when it was given
+/// several endpoints, or a host name that resolves to several addresses.
+///
+/// Either way, a receiver whose requests keep failing is avoided until it has
+/// recovered, and batches go to the other receivers in the meantime.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LoadBalancingStrategy {
+    /// Send every batch to the first healthy receiver in the order the
+    /// endpoints were given, falling back to the next one when it fails.
+    #[default]
+    Failover,
+    /// Send the batches to the healthy receivers in turn.
+    RoundRobin,
+}
+
+/// What an export does when the queue to the exporter thread is full, e.g.
+/// because the receiver is slow or has stopped answering.
+///
+/// A dropped batch fails the export it was handed to, with an error that
+/// reports the count of spans dropped so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Wait for room, but drop the batch being exported if there is still
+    /// none after `timeout`.
+    Block { timeout: Duration },
+    /// Drop the batch being exported and keep the queued ones.
+    DropNewest,
+    /// Drop the oldest queued batch to make room for the one being exported.
+    DropOldest,
+}
+
+impl Default for BackpressurePolicy {
+    fn default() -> Self {
+        BackpressurePolicy::Block {
+            timeout: OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT_DEFAULT,
+        }
+    }
+}
+
+/// Parses `block`, `drop_newest` or `drop_oldest`. `block` waits for the
+/// default timeout.
+impl FromStr for BackpressurePolicy {
+    type Err = ExporterBuildError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(BackpressurePolicy::default()),
+            "drop_newest" => Ok(BackpressurePolicy::DropNewest),
+            "drop_oldest" => Ok(BackpressurePolicy::DropOldest),
+            _ => Err(ExporterBuildError::InternalFailure(format!(
+                "Unsupported backpressure policy: {s}"
+            ))),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -31,7 +152,6 @@ pub struct CapnpExporterBuilder {
 }
 
 // Expose interface for modifying [CapnpConfig] fields within the exporter builders.
-#[allow(dead_code)]
 pub trait HasCapnpConfig {
     /// Return a mutable reference to the export config within the exporter builders.
     fn capnp_config(&mut self) -> &mut CapnpConfig;
@@ -44,23 +164,404 @@ impl HasCapnpConfig for CapnpExporterBuilder {
     }
 }
 
+/// Expose methods to override [CapnpConfig].
+///
+/// This trait will be implemented for every struct that implemented [`HasCapnpConfig`] trait.
+///
+/// ## Examples
+/// ```
+/// use opentelemetry_otlp_capnp::retry::RetryPolicy;
+/// use opentelemetry_otlp_capnp::WithCapnpConfig;
+/// let exporter_builder = opentelemetry_otlp_capnp::SpanExporter::builder()
+///     .with_capnp()
+///     .with_retry_policy(RetryPolicy {
+///         max_retries: 5,
+///         ..RetryPolicy::default()
+///     })
+///     .with_queue_capacity(64);
+/// ```
+pub trait WithCapnpConfig {
+    /// Set how failed export requests are retried.
+    ///
+    /// Note: Programmatically setting this will override the
+    /// `OTEL_EXPORTER_CAPNP_RETRY_*` environment variables.
+    fn with_retry_policy(self, policy: RetryPolicy) -> Self;
+    /// Set the count of span batches that can wait for the exporter thread,
+    /// e.g. while it retries a request, before the [BackpressurePolicy] applies.
+    /// Defaults to 32; 0 is treated as 1.
+    ///
+    /// Note: Programmatically setting this will override any value set via the environment variable.
+    fn with_queue_capacity(self, capacity: usize) -> Self;
+    /// Set what an export does when the queue to the exporter thread is full.
+    /// Defaults to [BackpressurePolicy::Block] for 10 seconds.
+    ///
+    /// Note: Programmatically setting this will override the
+    /// `OTEL_EXPORTER_CAPNP_BACKPRESSURE_*` environment variables.
+    fn with_backpressure_policy(self, policy: BackpressurePolicy) -> Self;
+    /// Set the max waiting time for a single attempt to connect to the
+    /// receiver, including the TLS handshake. Failed attempts are retried with
+    /// a backoff.
+    ///
+    /// Note: Programmatically setting this will override any value set via the environment variable.
+    fn with_connect_timeout(self, timeout: Duration) -> Self;
+    /// Set the traversal and nesting limits for reading the messages of the
+    /// receiver.
+    ///
+    /// Note: Programmatically setting this will override the
+    /// `OTEL_EXPORTER_CAPNP_TRAVERSAL_LIMIT` and
+    /// `OTEL_EXPORTER_CAPNP_NESTING_LIMIT` environment variables.
+    fn with_reader_options(self, options: ReaderOptions) -> Self;
+    /// Enable or disable Nagle's algorithm on TCP connections to the receiver.
+    /// Defaults to `true`, which sends every request right away.
+    ///
+    /// Note: Programmatically setting this will override any value set via the environment variable.
+    fn with_tcp_nodelay(self, nodelay: bool) -> Self;
+    /// Record the exporter's own metrics, e.g. the queue depth, the count of
+    /// exported and dropped spans, retries, reconnects and the RPC latency, on
+    /// a meter of `provider`.
+    ///
+    /// The exporter thread runs with telemetry suppressed, so exporting spans
+    /// does not produce spans of its own. Without a provider the values are
+    /// only available from [crate::SpanExporter::metrics_snapshot].
+    fn with_meter_provider<P: MeterProvider>(self, provider: &P) -> Self;
+    /// Run the exporter on `runtime`, e.g. [crate::runtime::Smol]. Defaults
+    /// to [crate::runtime::Tokio] with the `rt-tokio` feature, else to
+    /// [crate::runtime::Smol] with the `rt-smol` feature.
+    fn with_runtime<R: Runtime>(self, runtime: R) -> Self;
+}
+
+impl<B: HasCapnpConfig> WithCapnpConfig for B {
+    fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.capnp_config().retry_policy = Some(policy);
+        self
+    }
+
+    fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.capnp_config().queue_capacity = Some(capacity);
+        self
+    }
+
+    fn with_backpressure_policy(mut self, policy: BackpressurePolicy) -> Self {
+        self.capnp_config().backpressure_policy = Some(policy);
+        self
+    }
+
+    fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.capnp_config().connect_timeout = Some(timeout);
+        self
+    }
+
+    fn with_reader_options(mut self, options: ReaderOptions) -> Self {
+        self.capnp_config().reader_options = Some(options);
+        self
+    }
+
+    fn with_tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.capnp_config().tcp_nodelay = Some(nodelay);
+        self
+    }
+
+    fn with_meter_provider<P: MeterProvider>(mut self, provider: &P) -> Self {
+        self.capnp_config().meter = Some(provider.meter_with_scope(ExporterMetrics::scope()));
+        self
+    }
+
+    fn with_runtime<R: Runtime>(mut self, runtime: R) -> Self {
+        self.capnp_config().runtime = Some(Arc::new(runtime));
+        self
+    }
+}
+
 impl CapnpExporterBuilder {
-    /// Build a new capnp span exporter
-    pub(crate) fn build_span_exporter(self) -> Result<crate::SpanExporter, ExporterBuildError> {
-        use crate::exporter::capnp::trace::CapnpTracesClient;
+    /// Build a new capnp span exporter, and with [Execution::Local] the task
+    /// that performs its exports.
+    pub(crate) fn build_span_exporter(
+        self,
+        execution: Execution,
+    ) -> Result<(crate::SpanExporter, Option<ExporterTask>), ExporterBuildError> {
+        let (connectors, client_config) = self.resolve_client_config()?;
+        let (client, task) = CapnpTracesClient::new(connectors, client_config, execution)?;
+        Ok((crate::SpanExporter::from_capnp(client), task))
+    }
+
+    /// Build a capnp span exporter whose exports block the calling thread.
+    pub(crate) fn build_blocking_span_exporter(
+        mut self,
+    ) -> Result<crate::SpanExporter, ExporterBuildError> {
+        // blocking exports are never held past their timeout, so there is
+        // nothing to persist
+        self.capnp_config.persistent_queue = None;
+        let (connectors, client_config) = self.resolve_client_config()?;
+        Ok(crate::SpanExporter::from_capnp_blocking(
+            CapnpBlockingTracesClient::new(connectors, client_config)?,
+        ))
+    }
 
+    /// Resolve the programmatic configuration, the environment variables and
+    /// the defaults into the receivers to connect to and the client settings.
+    fn resolve_client_config(self) -> Result<(Vec<Connector>, ClientConfig), ExporterBuildError> {
         // otel_debug!(name: "TracesCapnpChannelBuilding");
         let config = self.exporter_config;
         let endpoint = Self::resolve_endpoint(OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT, config.endpoint);
-        let endpoint = endpoint
-            .to_socket_addrs()
-            .expect("endpoint should convert to at least one socket address")
-            .next()
-            .expect("endpoint should be syntactically correct socket address");
-        let retry_policy = self.capnp_config.retry_policy.clone();
-        let client = CapnpTracesClient::new(endpoint, retry_policy);
+        let compression = Self::resolve_compression(
+            OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION,
+            self.capnp_config.compression,
+        )?;
+        // programmatic configuration overrides any value set via environment variables
+        #[cfg(feature = "tls")]
+        let tls_config = match self.capnp_config.tls_config {
+            Some(tls_config) => Some(tls_config),
+            None => crate::tls::ClientTlsConfig::from_env()?,
+        };
+        let reader_options = Self::resolve_reader_options(self.capnp_config.reader_options)?;
+        let tcp_nodelay = self
+            .capnp_config
+            .tcp_nodelay
+            .map_or_else(
+                || {
+                    env_value(
+                        OTEL_EXPORTER_CAPNP_TRACES_TCP_NODELAY,
+                        OTEL_EXPORTER_CAPNP_TCP_NODELAY,
+                    )
+                },
+                |tcp_nodelay| Ok(Some(tcp_nodelay)),
+            )?
+            .unwrap_or(true);
+        let mut connectors = Vec::new();
+        let endpoints: Vec<&str> = endpoint.split(',').map(str::trim).collect();
+        for &entry in &endpoints {
+            let resolved = Endpoint::resolve(entry).map_err(|e| match e {
+                // e.g. the tail of a Unix domain socket path with a comma in it
+                ExporterBuildError::InvalidEndpoint { reason, .. } if endpoints.len() > 1 => {
+                    ExporterBuildError::InvalidEndpoint {
+                        endpoint: endpoint.clone(),
+                        reason: format!(
+                            "'{entry}': {reason} (commas separate endpoints, \
+                             so they cannot appear in a Unix domain socket path)"
+                        ),
+                    }
+                }
+                e => e,
+            })?;
+            #[cfg(feature = "tls")]
+            let tls_connector = tls_config
+                .as_ref()
+                .map(|tls_config| tls_config.connector(entry))
+                .transpose()
+                .map_err(|e| {
+                    ExporterBuildError::InternalFailure(format!("Invalid TLS configuration: {e}"))
+                })?;
+            for resolved_endpoint in resolved {
+                let connector = Connector::new(resolved_endpoint)
+                    .with_compression(compression)
+                    .with_reader_options(reader_options)
+                    .with_tcp_nodelay(tcp_nodelay);
+                #[cfg(feature = "tls")]
+                let connector = match &tls_connector {
+                    Some(tls_connector) => connector.with_tls(tls_connector.clone()),
+                    None => connector,
+                };
+                connectors.push(connector);
+            }
+        }
+        let persistent_queue = self
+            .capnp_config
+            .persistent_queue
+            .map(PersistentQueue::open)
+            .transpose()
+            .map_err(|e| {
+                ExporterBuildError::InternalFailure(format!(
+                    "Failed to open the persistent queue: {e}"
+                ))
+            })?;
+        let request_metadata = RequestMetadata {
+            headers: Self::resolve_headers(
+                OTEL_EXPORTER_CAPNP_TRACES_HEADERS,
+                self.capnp_config.headers,
+            ),
+            interceptor: self.capnp_config.interceptor,
+        };
+        let client_config = ClientConfig {
+            load_balancing_strategy: self.capnp_config.load_balancing_strategy,
+            retry_policy: Self::resolve_retry_policy(self.capnp_config.retry_policy)?,
+            persistent_queue,
+            request_metadata,
+            max_in_flight_requests: self
+                .capnp_config
+                .max_in_flight_requests
+                .unwrap_or(SPAN_EXPORTER_MAX_IN_FLIGHT_REQUESTS)
+                .max(1),
+            queue_capacity: self
+                .capnp_config
+                .queue_capacity
+                .map_or_else(
+                    || {
+                        env_value(
+                            OTEL_EXPORTER_CAPNP_TRACES_QUEUE_CAPACITY,
+                            OTEL_EXPORTER_CAPNP_QUEUE_CAPACITY,
+                        )
+                    },
+                    |queue_capacity| Ok(Some(queue_capacity)),
+                )?
+                .unwrap_or(SPAN_EXPORTER_MPSC_CHANNEL_BUFFER_SIZE)
+                .max(1),
+            backpressure_policy: Self::resolve_backpressure_policy(
+                self.capnp_config.backpressure_policy,
+            )?,
+            metrics: Arc::new(ExporterMetrics::new(self.capnp_config.meter.as_ref())),
+            encode_on_caller: self.capnp_config.encode_on_caller,
+            runtime: self
+                .capnp_config
+                .runtime
+                .or_else(default_runtime)
+                .ok_or(ExporterBuildError::NoRuntime)?,
+            timeouts: Timeouts {
+                rpc: Self::resolve_timeout(
+                    OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT,
+                    OTEL_EXPORTER_CAPNP_TIMEOUT,
+                    config.timeout,
+                    OTEL_EXPORTER_CAPNP_TIMEOUT_DEFAULT,
+                )?,
+                connect: Self::resolve_timeout(
+                    OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT,
+                    OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT,
+                    self.capnp_config.connect_timeout,
+                    OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT_DEFAULT,
+                )?,
+            },
+        };
+        Ok((connectors, client_config))
+    }
+
+    fn resolve_compression(
+        env_override: &str,
+        provided_compression: Option<Compression>,
+    ) -> Result<Option<Compression>, ExporterBuildError> {
+        // programmatic configuration overrides any value set via environment variables
+        let compression = if let Some(compression) = provided_compression {
+            Some(compression)
+        } else if let Ok(compression) = env::var(env_override) {
+            Some(compression.parse::<Compression>()?)
+        } else if let Ok(compression) = env::var(OTEL_EXPORTER_CAPNP_COMPRESSION) {
+            Some(compression.parse::<Compression>()?)
+        } else {
+            None
+        };
+        compression.map(Compression::ensure_supported).transpose()
+    }
 
-        Ok(crate::SpanExporter::from_capnp(client))
+    fn resolve_headers(
+        signal_headers_var: &str,
+        provided_headers: Option<HashMap<String, String>>,
+    ) -> Metadata {
+        let mut headers = env::var(signal_headers_var)
+            .or_else(|_| env::var(OTEL_EXPORTER_CAPNP_HEADERS))
+            .map(|value| Metadata::parse_header_string(&value))
+            .unwrap_or_default();
+        // programmatic configuration overrides any value set via environment variables
+        headers.extend(provided_headers.unwrap_or_default());
+        headers
+    }
+
+    /// Environment variables hold the timeout in milliseconds.
+    fn resolve_timeout(
+        signal_timeout_var: &str,
+        generic_timeout_var: &str,
+        provided_timeout: Option<Duration>,
+        default_timeout: Duration,
+    ) -> Result<Duration, ExporterBuildError> {
+        // programmatic configuration overrides any value set via environment variables
+        if let Some(timeout) = provided_timeout {
+            return Ok(timeout);
+        }
+        Ok(env_value(signal_timeout_var, generic_timeout_var)?
+            .map_or(default_timeout, Duration::from_millis))
+    }
+
+    /// Start from the default policy and override the fields set via
+    /// environment variables, unless a policy was provided.
+    fn resolve_retry_policy(
+        provided_policy: Option<RetryPolicy>,
+    ) -> Result<RetryPolicy, ExporterBuildError> {
+        // programmatic configuration overrides any value set via environment variables
+        if let Some(policy) = provided_policy {
+            return Ok(policy);
+        }
+        let default = RetryPolicy::default();
+        Ok(RetryPolicy {
+            max_retries: env_value(
+                OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_RETRIES,
+                OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES,
+            )?
+            .unwrap_or(default.max_retries),
+            initial_delay_ms: env_value(
+                OTEL_EXPORTER_CAPNP_TRACES_RETRY_INITIAL_DELAY,
+                OTEL_EXPORTER_CAPNP_RETRY_INITIAL_DELAY,
+            )?
+            .unwrap_or(default.initial_delay_ms),
+            max_delay_ms: env_value(
+                OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_DELAY,
+                OTEL_EXPORTER_CAPNP_RETRY_MAX_DELAY,
+            )?
+            .unwrap_or(default.max_delay_ms),
+            jitter_ms: env_value(
+                OTEL_EXPORTER_CAPNP_TRACES_RETRY_JITTER,
+                OTEL_EXPORTER_CAPNP_RETRY_JITTER,
+            )?
+            .unwrap_or(default.jitter_ms),
+        })
+    }
+
+    /// The timeout of [BackpressurePolicy::Block] is only read from the
+    /// environment if the policy is.
+    fn resolve_backpressure_policy(
+        provided_policy: Option<BackpressurePolicy>,
+    ) -> Result<BackpressurePolicy, ExporterBuildError> {
+        // programmatic configuration overrides any value set via environment variables
+        if let Some(policy) = provided_policy {
+            return Ok(policy);
+        }
+        Ok(
+            match env_value(
+                OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY,
+                OTEL_EXPORTER_CAPNP_BACKPRESSURE_POLICY,
+            )?
+            .unwrap_or_default()
+            {
+                BackpressurePolicy::Block { .. } => BackpressurePolicy::Block {
+                    timeout: Self::resolve_timeout(
+                        OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_TIMEOUT,
+                        OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT,
+                        None,
+                        OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT_DEFAULT,
+                    )?,
+                },
+                policy => policy,
+            },
+        )
+    }
+
+    fn resolve_reader_options(
+        provided_options: Option<ReaderOptions>,
+    ) -> Result<ReaderOptions, ExporterBuildError> {
+        // programmatic configuration overrides any value set via environment variables
+        if let Some(options) = provided_options {
+            return Ok(options);
+        }
+        let mut options = ReaderOptions::new();
+        if let Some(words) = env_value(
+            OTEL_EXPORTER_CAPNP_TRACES_TRAVERSAL_LIMIT,
+            OTEL_EXPORTER_CAPNP_TRAVERSAL_LIMIT,
+        )? {
+            options.traversal_limit_in_words(Some(words));
+        }
+        if let Some(depth) = env_value(
+            OTEL_EXPORTER_CAPNP_TRACES_NESTING_LIMIT,
+            OTEL_EXPORTER_CAPNP_NESTING_LIMIT,
+        )? {
+            options.nesting_limit(depth);
+        }
+        Ok(options)
     }
 
     fn resolve_endpoint(default_endpoint_var: &str, provided_endpoint: Option<String>) -> String {
@@ -83,22 +584,50 @@ impl CapnpExporterBuilder {
     }
 }
 
+/// The value of the signal-specific environment variable, else of the generic
+/// one. Empty variables count as unset; a value that does not parse fails the
+/// build rather than silently falling back to the default.
+fn env_value<T>(signal_var: &str, generic_var: &str) -> Result<Option<T>, ExporterBuildError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    for var in [signal_var, generic_var] {
+        let Some(value) = env::var(var).ok().filter(|value| !value.trim().is_empty()) else {
+            continue;
+        };
+        return value.trim().parse().map(Some).map_err(|e: T::Err| {
+            ExporterBuildError::InvalidEnvValue {
+                name: var.to_string(),
+                value,
+                reason: e.to_string(),
+            }
+        });
+    }
+    Ok(None)
+}
+
+#[cfg(feature = "rt-tokio")]
 pub async fn connect_with_retry(
     addr: &SocketAddr,
     timeout_ms: u64,
 ) -> io::Result<tokio::net::TcpStream> {
     let mut delay = Duration::from_millis(1);
+    let mut attempt = 0u32;
     loop {
         tokio::time::sleep(delay).await;
+        attempt += 1;
 
         match tokio::net::TcpStream::connect(&addr).await {
             Ok(stream) => {
                 return io::Result::Ok(stream);
             }
             Err(e) => {
-                let _ = writeln!(
-                    io::stdout(),
-                    "Connection attempt failed for SpanExporter: {e}"
+                opentelemetry::otel_debug!(
+                    name: "CapnpSpanExporter.ConnectAttemptFailed",
+                    endpoint = addr.to_string(),
+                    attempt = attempt,
+                    error = e.to_string(),
                 );
                 if delay > Duration::from_millis(timeout_ms) {
                     return Err(io::Error::new(
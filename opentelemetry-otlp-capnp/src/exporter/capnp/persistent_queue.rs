@@ -0,0 +1,329 @@
+//! On-disk queue of encoded export requests.
+//!
+//! Every segment is a single flat Cap'n Proto message in its own file, named
+//! after a monotonically increasing sequence number so that the queue can be
+//! replayed in order after a process restart. A segment is written to a
+//! temporary file first, synced, renamed into place and the directory synced
+//! after, so a crash never leaves a half-written segment behind nor loses one
+//! that was reported as persisted.
+//!
+//! The file I/O runs with [Runtime::spawn_blocking], as the exporter's task
+//! may run on the app's own executor.
+use crate::runtime::{self, Runtime};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// Default upper bound for the total size of all segments on disk.
+pub const PERSISTENT_QUEUE_MAX_SIZE_BYTES_DEFAULT: u64 = 256 * 1024 * 1024;
+/// Default age after which a segment is discarded without being exported.
+pub const PERSISTENT_QUEUE_MAX_AGE_DEFAULT: Duration = Duration::from_secs(24 * 60 * 60);
+
+const SEGMENT_EXTENSION: &str = "capnp";
+const TEMPORARY_EXTENSION: &str = "tmp";
+/// How many times a segment may be traversed once read: the exporter counts
+/// its spans and copies it into the RPC request, with room to spare.
+const SEGMENT_TRAVERSALS: usize = 4;
+
+/// What the persistent queue does with a new batch when it would exceed its size cap.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Delete the oldest segments until the new batch fits.
+    #[default]
+    DropOldest,
+    /// Reject the new batch and keep the segments already on disk.
+    DropNewest,
+}
+
+/// Configuration for the on-disk queue of the Cap'n Proto span exporter.
+///
+/// Span batches are written to `directory` before they are exported and
+/// deleted once the receiver has acknowledged them, so they survive both
+/// receiver outages and restarts of the process. Only one exporter may use a
+/// directory at a time.
+///
+/// ```no_run
+/// use opentelemetry_otlp_capnp::{OverflowPolicy, PersistentQueueConfig, SpanExporter};
+/// use std::time::Duration;
+///
+/// let exporter = SpanExporter::builder()
+///     .with_capnp()
+///     .with_persistent_queue(
+///         PersistentQueueConfig::new("/var/lib/my-app/spans")
+///             .with_max_size_bytes(64 * 1024 * 1024)
+///             .with_max_age(Duration::from_secs(60 * 60))
+///             .with_overflow_policy(OverflowPolicy::DropNewest),
+///     )
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct PersistentQueueConfig {
+    pub(crate) directory: PathBuf,
+    pub(crate) max_size_bytes: u64,
+    pub(crate) max_age: Duration,
+    pub(crate) overflow_policy: OverflowPolicy,
+}
+
+impl PersistentQueueConfig {
+    /// Keep the queue in `directory`, which is created if it does not exist.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            max_size_bytes: PERSISTENT_QUEUE_MAX_SIZE_BYTES_DEFAULT,
+            max_age: PERSISTENT_QUEUE_MAX_AGE_DEFAULT,
+            overflow_policy: OverflowPolicy::default(),
+        }
+    }
+
+    /// Set the upper bound for the total size of the queue on disk.
+    pub fn with_max_size_bytes(mut self, max_size_bytes: u64) -> Self {
+        self.max_size_bytes = max_size_bytes;
+        self
+    }
+
+    /// Set the age after which queued batches are discarded without being exported.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Set what happens to a new batch when the queue is full.
+    pub fn with_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    sequence: u64,
+    path: PathBuf,
+    size_bytes: u64,
+    created: SystemTime,
+}
+
+/// The segments of a [PersistentQueueConfig] directory, oldest first.
+///
+/// Only the exporter's task uses the queue and it waits for every operation,
+/// so the lock is never contended; it lets the operations run on a blocking
+/// thread.
+#[derive(Debug)]
+pub(crate) struct PersistentQueue {
+    segments: Arc<Mutex<Segments>>,
+}
+
+impl PersistentQueue {
+    /// Open the queue, picking up the segments left behind by a previous process.
+    pub(crate) fn open(config: PersistentQueueConfig) -> io::Result<Self> {
+        Ok(Self {
+            segments: Arc::new(Mutex::new(Segments::open(config)?)),
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().segments.is_empty()
+    }
+
+    /// Append `message` as the newest segment, applying the size cap and the
+    /// overflow policy first.
+    pub(crate) async fn push(
+        &self,
+        runtime: &dyn Runtime,
+        message: capnp::message::Builder<capnp::message::HeapAllocator>,
+    ) -> io::Result<()> {
+        let segments = self.segments.clone();
+        runtime::unblock(runtime, move || lock(&segments).push(&message)).await
+    }
+
+    /// Read the oldest segment that has not expired.
+    pub(crate) async fn front(
+        &self,
+        runtime: &dyn Runtime,
+    ) -> io::Result<Option<capnp::message::Reader<capnp::serialize::OwnedSegments>>> {
+        let segments = self.segments.clone();
+        runtime::unblock(runtime, move || lock(&segments).front()).await
+    }
+
+    /// Delete the oldest segment, e.g. once the receiver has acknowledged it.
+    pub(crate) async fn pop_front(&self, runtime: &dyn Runtime) -> io::Result<()> {
+        let segments = self.segments.clone();
+        runtime::unblock(runtime, move || lock(&segments).pop_front()).await
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Segments> {
+        lock(&self.segments)
+    }
+}
+
+fn lock(segments: &Mutex<Segments>) -> MutexGuard<'_, Segments> {
+    segments.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The state of a [PersistentQueue], whose methods do blocking file I/O.
+#[derive(Debug)]
+struct Segments {
+    config: PersistentQueueConfig,
+    segments: VecDeque<Segment>,
+    size_bytes: u64,
+    next_sequence: u64,
+}
+
+impl Segments {
+    fn open(config: PersistentQueueConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&config.directory)? {
+            let path = entry?.path();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                // left over by a crash while writing; never acknowledged to the caller
+                Some(TEMPORARY_EXTENSION) => fs::remove_file(&path)?,
+                Some(SEGMENT_EXTENSION) => {
+                    let Some(sequence) = path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .and_then(|stem| stem.parse::<u64>().ok())
+                    else {
+                        continue;
+                    };
+                    let metadata = fs::metadata(&path)?;
+                    segments.push(Segment {
+                        sequence,
+                        path,
+                        size_bytes: metadata.len(),
+                        created: metadata.modified()?,
+                    });
+                }
+                _ => {}
+            }
+        }
+        segments.sort_by_key(|segment| segment.sequence);
+        let next_sequence = segments.last().map_or(0, |segment| segment.sequence + 1);
+        let size_bytes = segments.iter().map(|segment| segment.size_bytes).sum();
+        Ok(Self {
+            config,
+            segments: segments.into(),
+            size_bytes,
+            next_sequence,
+        })
+    }
+
+    fn push<A: capnp::message::Allocator>(
+        &mut self,
+        message: &capnp::message::Builder<A>,
+    ) -> io::Result<()> {
+        self.remove_expired()?;
+        let bytes = capnp::serialize::write_message_to_words(message);
+        let size_bytes = bytes.len() as u64;
+        if size_bytes > self.config.max_size_bytes {
+            return Err(io::Error::other(format!(
+                "Span batch of {size_bytes} bytes exceeds the persistent queue size limit of {} bytes",
+                self.config.max_size_bytes
+            )));
+        }
+        while self.size_bytes + size_bytes > self.config.max_size_bytes {
+            match self.config.overflow_policy {
+                OverflowPolicy::DropOldest => self.pop_front()?,
+                OverflowPolicy::DropNewest => {
+                    return Err(io::Error::other(format!(
+                        "Persistent queue is full ({} bytes); dropped the newest span batch",
+                        self.size_bytes
+                    )))
+                }
+            }
+        }
+
+        let sequence = self.next_sequence;
+        let path = self.segment_path(sequence);
+        let temporary_path = path.with_extension(TEMPORARY_EXTENSION);
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, &path)?;
+        sync_directory(&self.config.directory)?;
+
+        self.next_sequence += 1;
+        self.size_bytes += size_bytes;
+        self.segments.push_back(Segment {
+            sequence,
+            path,
+            size_bytes,
+            created: SystemTime::now(),
+        });
+        Ok(())
+    }
+
+    fn front(
+        &mut self,
+    ) -> io::Result<Option<capnp::message::Reader<capnp::serialize::OwnedSegments>>> {
+        self.remove_expired()?;
+        let Some(segment) = self.segments.front() else {
+            return Ok(None);
+        };
+        let file = io::BufReader::new(fs::File::open(&segment.path)?);
+        capnp::serialize::read_message(file, segment_reader_options(segment.size_bytes))
+            .map(Some)
+            .map_err(io::Error::other)
+    }
+
+    fn pop_front(&mut self) -> io::Result<()> {
+        if let Some(segment) = self.segments.pop_front() {
+            self.size_bytes -= segment.size_bytes;
+            remove_segment_file(&segment.path)?;
+        }
+        Ok(())
+    }
+
+    fn remove_expired(&mut self) -> io::Result<()> {
+        let now = SystemTime::now();
+        while let Some(segment) = self.segments.front() {
+            let age = now.duration_since(segment.created).unwrap_or_default();
+            if age <= self.config.max_age {
+                break;
+            }
+            self.pop_front()?;
+        }
+        Ok(())
+    }
+
+    fn segment_path(&self, sequence: u64) -> PathBuf {
+        self.config
+            .directory
+            .join(format!("{sequence:020}.{SEGMENT_EXTENSION}"))
+    }
+}
+
+/// Reader options with a traversal limit derived from the `size_bytes` of a
+/// segment. The default limit of 64 MiB is below the default size cap, so a
+/// large batch could be written but never read back.
+fn segment_reader_options(size_bytes: u64) -> capnp::message::ReaderOptions {
+    let mut options = capnp::message::ReaderOptions::new();
+    let size_words = usize::try_from(size_bytes.div_ceil(8)).unwrap_or(usize::MAX);
+    if let Some(default_limit) = options.traversal_limit_in_words {
+        let limit = size_words.saturating_mul(SEGMENT_TRAVERSALS);
+        options.traversal_limit_in_words(Some(limit.max(default_limit)));
+    }
+    options
+}
+
+/// Persist the entries of `directory`, e.g. a segment renamed into it.
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    fs::File::open(directory)?.sync_all()
+}
+
+/// Directories cannot be opened as files on other platforms, where a rename
+/// is as durable as the file system makes it.
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn remove_segment_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
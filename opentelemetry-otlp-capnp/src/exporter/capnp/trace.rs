@@ -1,6 +1,10 @@
-// TODO:
-// remove the clones for better performance
-use crate::retry::RetryPolicy;
+use super::metrics::{ExporterMetrics, ExporterMetricsSnapshot};
+use super::persistent_queue::PersistentQueue;
+use super::{BackpressurePolicy, LoadBalancingStrategy};
+use crate::retry::{retry_with_backoff, RetryErrorType, RetryPolicy};
+use crate::runtime::{self, ExporterTask, LocalTask, Runtime, TaskFactory};
+use crate::transport::{AsyncStream, Connector, VatNetwork};
+use crate::{ExporterBuildError, Interceptor, Metadata};
 use core::fmt;
 // the following path is different than the OTLP because this crate doesn't use an extra module
 // indrection for the rpc layer since it is all capnp
@@ -10,73 +14,342 @@ use opentelemetry_sdk::{
     Resource,
 };
 
-use crate::connect_with_retry;
-use futures::io::AsyncReadExt;
 use opentelemetry_capnp::{
-    capnp::capnp_rpc::trace_service,
+    capnp::capnp_rpc::{export_trace_service_request, trace_service},
     transform::trace::{
-        populate_resource, populate_scope_spans, ResourceSpans, ScopeSpans, SpanRequest,
+        populate_export_request, populate_export_request_from_batch, ResourceSpans, ScopeSpans,
+        SpanRequest,
     },
 };
 use std::io;
-use std::io::Write;
 use std::time::Duration;
 
 use capnp_rpc::{rpc_twoparty_capnp, twoparty, RpcSystem};
-use std::collections::HashMap;
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::sync::Arc;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc;
-use tokio::task::LocalSet;
-
-// pub const OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION: &str = "OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION";
-// pub const OTEL_EXPORTER_CAPNP_TRACES_HEADERS: &str = "OTEL_EXPORTER_CAPNP_TRACES_HEADERS";
-pub const SPAN_EXPORTER_TIMEOUT: u64 = 30_000;
-/// Buffer size is the count of Vec<SpanData>:
+use futures::future::LocalBoxFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, Notify};
+
+/// Default capacity of the queue to the exporter thread, which is the count of Vec<SpanData>:
 /// Batch size = 512
 /// Span size = 2KB
 /// Max memory footprint for buffer: SpanSize x BatchSize x BufferSize = 2KB x 512 x 32 ~ 32MB
 pub const SPAN_EXPORTER_MPSC_CHANNEL_BUFFER_SIZE: usize = 32;
-pub const CAPNP_EXPORTER_RPC_TRACES_TIMEOUT: u64 = 10;
+/// Count of Vec<SpanData> held by the exporter thread while the receiver is
+/// unreachable. With the sizes above this is another ~32MB at most. The
+/// callers of these batches wait until they are delivered or dropped.
+pub const SPAN_EXPORTER_DISCONNECTED_BUFFER_SIZE: usize = 32;
+/// Count of export requests awaiting the receiver's response at the same time
+/// when the builder does not set one.
+pub const SPAN_EXPORTER_MAX_IN_FLIGHT_REQUESTS: usize = 1;
+/// Delay before the first attempt to re-establish a dropped connection; it is
+/// doubled after every failed attempt.
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+/// Upper bound for the delay between two reconnection attempts.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Max waiting time for [CapnpTracesClient::shutdown] and
+/// [CapnpTracesClient::force_flush] when the caller does not provide one.
+pub const SPAN_EXPORTER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a control message is offered again while the channel to the
+/// exporter thread is full.
+const CONTROL_SEND_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// Capacity of the channel for flushes and shutdowns, which callers wait on
+/// one at a time.
+const CONTROL_CHANNEL_BUFFER_SIZE: usize = 4;
+/// Capacity of the channel to the blocking exporter thread, i.e. how many
+/// callers of [CapnpBlockingTracesClient::export] can wait at once before
+/// the next one polls for room.
+const BLOCKING_EXPORT_CHANNEL_BUFFER_SIZE: usize = 64;
+/// How long past its deadline a blocking caller waits for the exporter
+/// thread, which times the export out at the deadline, to report and record
+/// that outcome before giving up on its own.
+const BLOCKING_EXPORT_REPLY_GRACE: Duration = Duration::from_millis(100);
 
 #[derive(Clone)]
-#[allow(dead_code)]
 pub(crate) struct CapnpTracesClient {
     inner: Option<ClientInner>,
-    retry_policy: RetryPolicy,
     resource: Resource,
+    // outlives the exporter thread so the final values can still be read
+    metrics: Arc<ExporterMetrics>,
+    encode_on_caller: bool,
+}
+
+/// The settings of the exporter thread, resolved by the builder.
+pub(crate) struct ClientConfig {
+    pub(crate) load_balancing_strategy: LoadBalancingStrategy,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) persistent_queue: Option<PersistentQueue>,
+    pub(crate) request_metadata: RequestMetadata,
+    pub(crate) max_in_flight_requests: usize,
+    /// Capacity of the queue to the exporter thread.
+    pub(crate) queue_capacity: usize,
+    pub(crate) backpressure_policy: BackpressurePolicy,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) metrics: Arc<ExporterMetrics>,
+    /// Encode span batches in [CapnpTracesClient::export] rather than on the
+    /// exporter thread.
+    pub(crate) encode_on_caller: bool,
+    pub(crate) runtime: Arc<dyn Runtime>,
+}
+
+/// Where the task performing the exports runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Execution {
+    /// On a thread of its own, started by the [Runtime].
+    Thread,
+    /// On an executor of the app, which is handed the [ExporterTask].
+    Local,
 }
 
 impl CapnpTracesClient {
-    pub(super) fn new(endpoint: SocketAddr, retry_policy: Option<RetryPolicy>) -> Self {
-        let client = CapnpMessageClient::new(&endpoint);
+    /// Build the client, and with [Execution::Local] the task the app has to
+    /// run for it.
+    pub(super) fn new(
+        connectors: Vec<Connector>,
+        config: ClientConfig,
+        execution: Execution,
+    ) -> Result<(Self, Option<ExporterTask>), ExporterBuildError> {
+        let metrics = config.metrics.clone();
+        let encode_on_caller = config.encode_on_caller;
+        // failed exports are retried on the exporter thread
+        let (client, task) = CapnpMessageClient::new(connectors, config, execution)?;
         let resource = Resource::builder().build();
-        Self {
+        let client = Self {
             inner: Some(ClientInner { client }),
-            retry_policy: retry_policy.unwrap_or(RetryPolicy {
-                max_retries: 3,
-                initial_delay_ms: 100,
-                max_delay_ms: 1600,
-                jitter_ms: 100,
-            }),
             resource,
-        }
+            metrics,
+            encode_on_caller,
+        };
+        Ok((client, task))
+    }
+
+    pub(crate) fn metrics_snapshot(&self) -> ExporterMetricsSnapshot {
+        self.metrics.snapshot()
     }
 }
 
 #[derive(Clone)]
 struct ClientInner {
     client: CapnpMessageClient,
-    // include an interceptor
 }
 
 #[derive(Clone)]
-#[allow(dead_code)]
 struct CapnpMessageClient {
-    // TODO
-    // make this generic over the channel so that flume can also be used
-    tx_export: tokio::sync::mpsc::Sender<SpanRequest>,
+    export_queue: Arc<ExportQueue>,
+    // runs the timers of callers waiting for room in the export queue
+    runtime: Arc<dyn Runtime>,
+    // flushes and shutdowns, ordered after the batches queued before them
+    tx_control: tokio::sync::mpsc::Sender<ExporterMessage>,
+    // joined on shutdown; shared because every clone talks to the same thread
+    exporter_thread: Arc<Mutex<Option<std::thread::JoinHandle<()>>>>,
+}
+
+/// Control messages for the exporter thread, which take the span batches
+/// queued before them into account.
+enum ExporterMessage {
+    /// Deliver every batch accepted so far, then report the outcome.
+    Flush(ControlRequest),
+    /// Deliver every batch accepted so far, report the outcome and stop the thread.
+    Shutdown(ControlRequest),
+}
+
+/// A [SpanPayload] queued for the exporter thread together with the channel
+/// on which the thread reports the outcome of exporting it.
+struct ExportRequest {
+    payload: SpanPayload,
+    reply: oneshot::Sender<OTelSdkResult>,
+}
+
+/// A span batch on its way to the receiver.
+enum SpanPayload {
+    /// The spans themselves, encoded by the exporter thread for every attempt.
+    Spans(SpanRequest),
+    /// An export request the caller has already encoded. The exporter thread
+    /// only copies its segments into the RPC request.
+    Encoded {
+        message: capnp::message::Builder<capnp::message::HeapAllocator>,
+        span_count: usize,
+    },
+}
+
+impl SpanPayload {
+    /// Encode `batch` into an export request on the calling thread, borrowing
+    /// the spans instead of copying them.
+    fn encode(batch: &[SpanData], resource: &Resource) -> Result<Self, ExportError> {
+        let mut message = capnp::message::Builder::new_default();
+        populate_export_request_from_batch(message.init_root(), resource, batch)
+            .map_err(|e| ExportError::Encode(e.to_string()))?;
+        Ok(SpanPayload::Encoded {
+            message,
+            span_count: batch.len(),
+        })
+    }
+
+    fn span_count(&self) -> usize {
+        match self {
+            SpanPayload::Spans(span_request) => span_request.batch.len(),
+            SpanPayload::Encoded { span_count, .. } => *span_count,
+        }
+    }
+}
+
+/// The span batches waiting for the exporter thread, oldest first.
+///
+/// Unlike a channel, the queue lets a caller drop the oldest batch, and a full
+/// queue is handled by the [BackpressurePolicy] so that a receiver that stopped
+/// answering cannot stall the span processor indefinitely.
+struct ExportQueue {
+    state: Mutex<ExportQueueState>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    /// Wakes the exporter thread when a batch was queued.
+    queued: Notify,
+    /// Wakes the callers waiting for room.
+    dequeued: Notify,
+    metrics: Arc<ExporterMetrics>,
+}
+
+struct ExportQueueState {
+    requests: VecDeque<ExportRequest>,
+    // set once the exporter thread stopped taking batches
+    closed: bool,
+}
+
+impl ExportQueue {
+    fn new(capacity: usize, policy: BackpressurePolicy, metrics: Arc<ExporterMetrics>) -> Self {
+        Self {
+            state: Mutex::new(ExportQueueState {
+                requests: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            capacity,
+            policy,
+            queued: Notify::new(),
+            dequeued: Notify::new(),
+            metrics,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ExportQueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue a span batch for the exporter thread, applying the backpressure
+    /// policy if the queue is full.
+    ///
+    /// The deadline of [BackpressurePolicy::Block] is kept by a timer of
+    /// `runtime`, which works whichever executor polls the export.
+    async fn push(&self, request: ExportRequest, runtime: &dyn Runtime) -> OTelSdkResult {
+        let mut deadline: Option<futures::future::BoxFuture<'static, ()>> = None;
+        loop {
+            // registered before looking at the queue so that no wakeup is missed
+            let room = self.dequeued.notified();
+            tokio::pin!(room);
+            room.as_mut().enable();
+            let timeout = {
+                let mut state = self.state();
+                if state.closed {
+                    return Err(OTelSdkError::InternalFailure(
+                        "Cap'n Proto Exporter Thread is not running".to_string(),
+                    ));
+                }
+                if state.requests.len() < self.capacity {
+                    state.requests.push_back(request);
+                    drop(state);
+                    self.metrics.queue_changed(1);
+                    self.queued.notify_one();
+                    return Ok(());
+                }
+                match self.policy {
+                    BackpressurePolicy::Block { timeout } => timeout,
+                    BackpressurePolicy::DropNewest => {
+                        drop(state);
+                        return Err(self.drop_spans(request.payload.span_count()));
+                    }
+                    BackpressurePolicy::DropOldest => {
+                        let oldest = state.requests.pop_front();
+                        state.requests.push_back(request);
+                        drop(state);
+                        self.queued.notify_one();
+                        if let Some(oldest) = oldest {
+                            let error = self.drop_spans(oldest.payload.span_count());
+                            let _ = oldest.reply.send(Err(error));
+                        }
+                        return Ok(());
+                    }
+                }
+            };
+            let deadline = deadline.get_or_insert_with(|| runtime.delay(timeout));
+            // a stopped exporter thread closes the queue, which also wakes up `room`
+            if let futures::future::Either::Right(_) = futures::future::select(room, deadline).await
+            {
+                return Err(self.drop_spans(request.payload.span_count()));
+            }
+        }
+    }
+
+    /// Take the oldest batch, making room for the callers waiting on the queue.
+    fn pop(&self) -> Option<ExportRequest> {
+        let request = self.state().requests.pop_front();
+        if request.is_some() {
+            self.metrics.queue_changed(-1);
+            self.dequeued.notify_waiters();
+        }
+        request
+    }
+
+    fn len(&self) -> usize {
+        self.state().requests.len()
+    }
+
+    /// Turn away further batches. The spans of the batches still queued count
+    /// as dropped and their callers are told that they were dropped without a
+    /// result.
+    fn close(&self) {
+        let abandoned = {
+            let mut state = self.state();
+            state.closed = true;
+            std::mem::take(&mut state.requests)
+        };
+        self.metrics.queue_changed(-(abandoned.len() as i64));
+        let mut dropped_spans = 0;
+        for request in abandoned {
+            let span_count = request.payload.span_count();
+            self.metrics.spans_dropped(span_count);
+            dropped_spans += span_count;
+        }
+        self.dequeued.notify_waiters();
+        if dropped_spans > 0 {
+            opentelemetry::otel_warn!(
+                name: "CapnpSpanExporter.SpansDropped",
+                dropped_spans = dropped_spans,
+                message = "Spans were dropped without an export attempt",
+            );
+        }
+    }
+
+    /// Count the spans of a dropped batch and describe the loss to its caller.
+    fn drop_spans(&self, count: usize) -> OTelSdkError {
+        let total = self.metrics.spans_dropped(count);
+        OTelSdkError::InternalFailure(format!(
+            "Export queue of the Cap'n Proto Exporter Thread is full: dropped {count} spans, \
+             {total} since the exporter was built"
+        ))
+    }
+}
+
+/// A flush or shutdown requested by the caller.
+///
+/// The caller waits synchronously and may be inside an async runtime, so the
+/// reply travels over a std channel rather than a tokio one.
+struct ControlRequest {
+    timeout: Duration,
+    deadline: std::time::Instant,
+    reply: std::sync::mpsc::SyncSender<OTelSdkResult>,
 }
 
 impl fmt::Debug for CapnpTracesClient {
@@ -87,37 +360,72 @@ impl fmt::Debug for CapnpTracesClient {
 
 impl opentelemetry_sdk::trace::SpanExporter for CapnpTracesClient {
     async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
-        // TODO
-        // use retry policy for trying to export
         match &self.inner {
             Some(inner_client) => {
-                inner_client
-                    .clone()
-                    .client
-                    .tx_export
-                    .send(SpanRequest {
+                let payload = if self.encode_on_caller {
+                    SpanPayload::encode(&batch, &self.resource)?
+                } else {
+                    SpanPayload::Spans(SpanRequest {
                         batch,
                         resource: self.resource.clone(),
                     })
-                    .await
-                    .map_err(|e| {
-                        OTelSdkError::InternalFailure(format!(
-                        "Failed to send span batch over MPSC to Cap'n Proto Exporter Thread: {e}"
-                    ))
-                    })?;
-                // TODO
-                // Need to surface errors returned from tx_export.send()
-                Ok(())
+                };
+                let (reply, outcome) = oneshot::channel();
+                let client = &inner_client.client;
+                client
+                    .export_queue
+                    .push(ExportRequest { payload, reply }, client.runtime.as_ref())
+                    .await?;
+                // The exporter thread answers once the RPC has completed, so the
+                // BatchSpanProcessor sees the real outcome of the export.
+                outcome.await.map_err(|_| {
+                    OTelSdkError::InternalFailure(
+                        "Cap'n Proto Exporter Thread dropped the span batch without a result"
+                            .to_string(),
+                    )
+                })?
             }
             None => OTelSdkResult::Err(OTelSdkError::AlreadyShutdown),
         }
     }
+
+    /// Export every batch handed to the exporter so far, then stop and join
+    /// the exporter thread. Gives up with [OTelSdkError::Timeout] if the
+    /// receiver has not acknowledged everything within `timeout`.
+    fn shutdown_with_timeout(&mut self, timeout: Duration) -> OTelSdkResult {
+        let Some(inner) = self.inner.take() else {
+            return Err(OTelSdkError::AlreadyShutdown);
+        };
+        inner
+            .client
+            .send_control(timeout, ExporterMessage::Shutdown)?;
+        // the thread stops right after replying, so joining does not block for long
+        let exporter_thread = inner
+            .client
+            .exporter_thread
+            .lock()
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))?
+            .take();
+        match exporter_thread.map(std::thread::JoinHandle::join) {
+            Some(Err(_)) => Err(OTelSdkError::InternalFailure(
+                "Cap'n Proto Exporter Thread panicked".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     fn shutdown(&mut self) -> OTelSdkResult {
-        // TODO
-        // check that this is correct
-        match self.inner.take() {
-            Some(_) => Ok(()), // Successfully took `inner`, indicating a successful shutdown.
-            None => Err(OTelSdkError::AlreadyShutdown), // `inner` was already `None`, meaning it's already shut down.
+        self.shutdown_with_timeout(SPAN_EXPORTER_SHUTDOWN_TIMEOUT)
+    }
+
+    /// Export every batch handed to the exporter so far, waiting at most
+    /// [SPAN_EXPORTER_SHUTDOWN_TIMEOUT] for the receiver to acknowledge them.
+    fn force_flush(&mut self) -> OTelSdkResult {
+        match &self.inner {
+            Some(inner) => inner
+                .client
+                .send_control(SPAN_EXPORTER_SHUTDOWN_TIMEOUT, ExporterMessage::Flush),
+            None => Err(OTelSdkError::AlreadyShutdown),
         }
     }
 
@@ -126,136 +434,1367 @@ impl opentelemetry_sdk::trace::SpanExporter for CapnpTracesClient {
     }
 }
 
-impl CapnpMessageClient {
-    // TODO
-    // should boot up in unconnected state and be able to cache span data
-    // when are able to connect, then do so and start exporting
-    // need to handle disconnect and cache full
-    // TODO
-    // handle errors for
-    // - mpsc channel creation
-    // - endpoint parsing
-    // - spawning current thread
-    // - etc
-    pub fn new(endpoint: &SocketAddr) -> Self {
-        // switch to bounded channels; careful to not have channel-loops
-        let (tx_export, rx_export) =
-            mpsc::channel::<SpanRequest>(SPAN_EXPORTER_MPSC_CHANNEL_BUFFER_SIZE);
-
-        let addr = endpoint
-            .to_socket_addrs()
-            .unwrap()
-            .next()
-            .expect("parse to socket address");
-
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Should be able to create a current thread runtime.");
-
-            let local = LocalSet::new();
-
-            local.block_on(&rt, async {
-                let Ok(stream) = connect_with_retry(&addr, SPAN_EXPORTER_TIMEOUT).await else {
-                    writeln!(io::stdout(), "Could not build Span Exporter").ok();
-                    return;
-                };
-                stream.set_nodelay(true).expect("no delay set");
+/// Exports every span batch while the thread calling
+/// [CapnpBlockingTracesClient::export] waits, returning once the receiver has
+/// answered or the export timeout has passed.
+///
+/// Cap'n Proto RPC needs an event loop, so the caller encodes the batch and
+/// hands it over a bounded channel to a thread of the [Runtime] that keeps
+/// one connection to the receivers for every export. The calling thread may
+/// run an async runtime or none. The export timeout is a deadline for the
+/// caller, including the wait for room in the channel: the caller stops
+/// waiting once it has passed, whatever the exporter thread is doing.
+#[derive(Clone)]
+pub(crate) struct CapnpBlockingTracesClient {
+    worker: Arc<BlockingWorker>,
+    rpc_timeout: Duration,
+    resource: Resource,
+    metrics: Arc<ExporterMetrics>,
+}
 
-                let mut rpc_system = build_capnp_rpc_system(stream);
+/// The thread making the RPCs of a [CapnpBlockingTracesClient] and the
+/// channel to it, both taken on shutdown.
+struct BlockingWorker {
+    tx_export: Mutex<Option<mpsc::Sender<BlockingExportRequest>>>,
+    thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
 
-                let client: trace_service::Client =
-                    rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
-                tokio::task::spawn_local(rpc_system);
+/// A [SpanPayload] for the blocking exporter thread together with the
+/// caller's deadline and the channel on which it reports the outcome.
+///
+/// The caller waits synchronously and may be inside an async runtime, so the
+/// reply travels over a std channel rather than a tokio one.
+struct BlockingExportRequest {
+    payload: SpanPayload,
+    deadline: Instant,
+    reply: std::sync::mpsc::SyncSender<OTelSdkResult>,
+}
 
-                export_loop(client, rx_export).await;
-            });
+impl CapnpBlockingTracesClient {
+    /// Build the client and start its thread; the queue and persistence
+    /// settings of `config` do not apply to blocking exports.
+    pub(super) fn new(
+        connectors: Vec<Connector>,
+        config: ClientConfig,
+    ) -> Result<Self, ExporterBuildError> {
+        let (tx_export, rx_export) = mpsc::channel(BLOCKING_EXPORT_CHANNEL_BUFFER_SIZE);
+        let rpc_timeout = config.timeouts.rpc;
+        let metrics = config.metrics.clone();
+        let runtime = config.runtime.clone();
+        let task: TaskFactory = Box::new(move || {
+            let task = blocking_export_loop(
+                Connection::new(
+                    connectors,
+                    config.load_balancing_strategy,
+                    config.request_metadata,
+                    config.timeouts,
+                    config.metrics,
+                    config.runtime,
+                ),
+                config.retry_policy,
+                rx_export,
+            );
+            // whatever the exporter does must not produce telemetry that
+            // would be exported again
+            Box::pin(opentelemetry::context::FutureExt::with_context(
+                task,
+                opentelemetry::Context::current().with_telemetry_suppressed(),
+            ))
+        });
+        let thread = runtime.spawn_thread("capnp-blocking-span-exporter", task)?;
+        Ok(Self {
+            worker: Arc::new(BlockingWorker {
+                tx_export: Mutex::new(Some(tx_export)),
+                thread: Mutex::new(Some(thread)),
+            }),
+            rpc_timeout,
+            resource: Resource::builder().build(),
+            metrics,
+        })
+    }
+
+    pub(crate) fn metrics_snapshot(&self) -> ExporterMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Encode `batch` and export it, blocking the calling thread for at most
+    /// the RPC timeout, which covers waiting for the exporter thread,
+    /// connecting and retries as well.
+    ///
+    /// Like [CapnpTracesClient::send_control], a full channel is polled
+    /// because the blocking tokio channel APIs panic inside an async runtime.
+    pub(crate) fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let deadline = Instant::now() + self.rpc_timeout;
+        let payload = SpanPayload::encode(&batch, &self.resource)?;
+        drop(batch);
+        let tx_export = self
+            .worker
+            .tx_export
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .ok_or(OTelSdkError::AlreadyShutdown)?;
+        let (reply, outcome) = std::sync::mpsc::sync_channel(1);
+        let mut request = BlockingExportRequest {
+            payload,
+            deadline,
+            reply,
+        };
+        loop {
+            match tx_export.try_send(request) {
+                Ok(()) => break,
+                Err(TrySendError::Full(_)) if Instant::now() >= deadline => {
+                    return Err(OTelSdkError::Timeout(self.rpc_timeout));
+                }
+                Err(TrySendError::Full(returned)) => {
+                    request = returned;
+                    std::thread::sleep(CONTROL_SEND_POLL_INTERVAL);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    return Err(OTelSdkError::InternalFailure(
+                        "Cap'n Proto blocking exporter thread is not running".to_string(),
+                    ));
+                }
+            }
+        }
+        drop(tx_export);
+        let reply_deadline = deadline + BLOCKING_EXPORT_REPLY_GRACE;
+        match outcome.recv_timeout(reply_deadline.saturating_duration_since(Instant::now())) {
+            Ok(result) => result,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                Err(OTelSdkError::Timeout(self.rpc_timeout))
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                Err(OTelSdkError::InternalFailure(
+                    "Blocking export stopped without a result".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Every export has completed by the time it returns, so there is
+    /// nothing to flush.
+    pub(crate) fn force_flush(&self) -> OTelSdkResult {
+        match &*self
+            .worker
+            .tx_export
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(_) => Ok(()),
+            None => Err(OTelSdkError::AlreadyShutdown),
+        }
+    }
+
+    /// Close the connection and join the exporter thread once the exports
+    /// still running have completed.
+    pub(crate) fn shutdown(&self) -> OTelSdkResult {
+        let tx_export = self
+            .worker
+            .tx_export
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if tx_export.is_none() {
+            return Err(OTelSdkError::AlreadyShutdown);
+        }
+        drop(tx_export);
+        let thread = self
+            .worker
+            .thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match thread.map(std::thread::JoinHandle::join) {
+            Some(Err(_)) => Err(OTelSdkError::InternalFailure(
+                "Cap'n Proto blocking exporter thread panicked".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.clone();
+    }
+}
+
+impl fmt::Debug for CapnpBlockingTracesClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CapnpBlockingTracesClient")
+    }
+}
+
+impl CapnpMessageClient {
+    // The exporter task boots up unconnected and connects in the background,
+    // so building the exporter never waits for the receiver.
+    pub fn new(
+        connectors: Vec<Connector>,
+        config: ClientConfig,
+        execution: Execution,
+    ) -> Result<(Self, Option<ExporterTask>), ExporterBuildError> {
+        let export_queue = Arc::new(ExportQueue::new(
+            config.queue_capacity,
+            config.backpressure_policy,
+            config.metrics.clone(),
+        ));
+        let (tx_control, rx_control) =
+            mpsc::channel::<ExporterMessage>(CONTROL_CHANNEL_BUFFER_SIZE);
+
+        let runtime = config.runtime.clone();
+        let task_queue = export_queue.clone();
+        let task: TaskFactory = Box::new(move || {
+            let task = async move {
+                export_loop(
+                    Connection::new(
+                        connectors,
+                        config.load_balancing_strategy,
+                        config.request_metadata,
+                        config.timeouts,
+                        config.metrics,
+                        config.runtime,
+                    ),
+                    &task_queue,
+                    rx_control,
+                    config.retry_policy,
+                    config.persistent_queue,
+                    config.max_in_flight_requests,
+                )
+                .await;
+                task_queue.close();
+            };
+            // whatever the exporter does must not produce telemetry that
+            // would be exported again
+            Box::pin(opentelemetry::context::FutureExt::with_context(
+                task,
+                opentelemetry::Context::current().with_telemetry_suppressed(),
+            ))
+        });
+        let (exporter_thread, task) = match execution {
+            Execution::Thread => (
+                Some(runtime.spawn_thread("capnp-span-exporter", task)?),
+                None,
+            ),
+            Execution::Local => (None, Some(ExporterTask::new(runtime.run_local(task())))),
+        };
+        let client = Self {
+            export_queue,
+            runtime,
+            tx_control,
+            exporter_thread: Arc::new(Mutex::new(exporter_thread)),
+        };
+        Ok((client, task))
+    }
+
+    /// Hand a flush or shutdown to the exporter thread and wait up to
+    /// `timeout` for its outcome.
+    ///
+    /// The blocking tokio channel APIs panic inside an async runtime, which is
+    /// where e.g. a `SimpleSpanProcessor` calls from, so a full channel is
+    /// polled instead.
+    fn send_control(
+        &self,
+        timeout: Duration,
+        message: impl FnOnce(ControlRequest) -> ExporterMessage,
+    ) -> OTelSdkResult {
+        let deadline = std::time::Instant::now() + timeout;
+        let (reply, outcome) = std::sync::mpsc::sync_channel(1);
+        let mut message = message(ControlRequest {
+            timeout,
+            deadline,
+            reply,
         });
-        Self { tx_export }
+        loop {
+            match self.tx_control.try_send(message) {
+                Ok(()) => break,
+                Err(TrySendError::Full(_)) if std::time::Instant::now() >= deadline => {
+                    return Err(OTelSdkError::Timeout(timeout));
+                }
+                Err(TrySendError::Full(returned)) => {
+                    message = returned;
+                    std::thread::sleep(CONTROL_SEND_POLL_INTERVAL);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    return Err(OTelSdkError::InternalFailure(
+                        "Cap'n Proto Exporter Thread is not running".to_string(),
+                    ));
+                }
+            }
+        }
+        match outcome.recv_timeout(deadline.saturating_duration_since(std::time::Instant::now())) {
+            Ok(result) => result,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Err(OTelSdkError::Timeout(timeout)),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                Err(OTelSdkError::InternalFailure(
+                    "Cap'n Proto Exporter Thread stopped without a result".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+/// The Cap'n Proto connections to the receivers.
+///
+/// Every receiver endpoint is a [Backend] with its own RPC system. A request
+/// goes to a connected backend picked by the [LoadBalancingStrategy], skipping
+/// backends whose recent requests failed until their health backoff has
+/// elapsed. Backends that lost their connection are reconnected by
+/// [Connection::reconnect].
+struct Connection {
+    backends: Vec<Backend>,
+    strategy: LoadBalancingStrategy,
+    // the backend the next round-robin request starts at
+    next_backend: Cell<usize>,
+    request_metadata: RequestMetadata,
+    rpc_timeout: Duration,
+    metrics: Arc<ExporterMetrics>,
+    runtime: Arc<dyn Runtime>,
+}
+
+/// How long the exporter thread waits on the receivers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timeouts {
+    /// Max waiting time for the response to an export request.
+    pub(crate) rpc: Duration,
+    /// Max waiting time for a single connection attempt.
+    pub(crate) connect: Duration,
+}
+
+/// The [Metadata] sent with every export request.
+#[derive(Clone)]
+pub(crate) struct RequestMetadata {
+    pub(crate) headers: Metadata,
+    pub(crate) interceptor: Option<Arc<dyn Interceptor>>,
+}
+
+impl RequestMetadata {
+    /// Run the interceptor on a copy of the headers and add the result to `params`.
+    fn apply(&self, params: trace_service::export_params::Builder<'_>) -> Result<(), ExportError> {
+        let mut metadata = self.headers.clone();
+        if let Some(interceptor) = &self.interceptor {
+            interceptor
+                .call(&mut metadata)
+                .map_err(ExportError::Interceptor)?;
+        }
+        metadata.write_to(params.init_metadata(metadata.len() as u32));
+        Ok(())
     }
 }
 
-fn build_capnp_rpc_system(stream: TcpStream) -> RpcSystem<twoparty::VatId> {
-    let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+impl Connection {
+    fn new(
+        connectors: Vec<Connector>,
+        strategy: LoadBalancingStrategy,
+        request_metadata: RequestMetadata,
+        timeouts: Timeouts,
+        metrics: Arc<ExporterMetrics>,
+        runtime: Arc<dyn Runtime>,
+    ) -> Self {
+        Self {
+            backends: connectors
+                .into_iter()
+                .map(|connector| {
+                    Backend::new(
+                        connector,
+                        timeouts.connect,
+                        metrics.clone(),
+                        runtime.clone(),
+                    )
+                })
+                .collect(),
+            strategy,
+            next_backend: Cell::new(0),
+            request_metadata,
+            rpc_timeout: timeouts.rpc,
+            metrics,
+            runtime,
+        }
+    }
+
+    /// Whether at least one backend is connected.
+    fn is_connected(&self) -> bool {
+        self.backends.iter().any(Backend::is_connected)
+    }
+
+    fn is_fully_connected(&self) -> bool {
+        self.backends.iter().all(Backend::is_connected)
+    }
+
+    /// The backends in the order the next request prefers them.
+    fn candidates(&self) -> impl Iterator<Item = &Backend> {
+        let start = match self.strategy {
+            LoadBalancingStrategy::Failover => 0,
+            LoadBalancingStrategy::RoundRobin => {
+                let start = self.next_backend.get();
+                self.next_backend.set((start + 1) % self.backends.len());
+                start
+            }
+        };
+        self.backends[start..].iter().chain(&self.backends[..start])
+    }
+
+    /// Pick the backend for the next request: a connected healthy backend if
+    /// there is one, else a connected unhealthy one, else the first backend
+    /// that can be reconnected.
+    async fn client(&self) -> Result<(&Backend, trace_service::Client), capnp::Error> {
+        let now = Instant::now();
+        let candidates: Vec<&Backend> = self.candidates().collect();
+        let connected = |healthy: bool| {
+            candidates.iter().find_map(|backend| {
+                backend
+                    .connected_client()
+                    .filter(|_| !healthy || backend.is_healthy(now))
+                    .map(|client| (*backend, client))
+            })
+        };
+        if let Some(picked) = connected(true).or_else(|| connected(false)) {
+            return Ok(picked);
+        }
+        let mut last_error = None;
+        for backend in candidates {
+            match backend.client().await {
+                Ok(client) => return Ok((backend, client)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("the exporter has at least one backend"))
+    }
 
-    let rpc_network = Box::new(twoparty::VatNetwork::new(
-        futures::io::BufReader::new(reader),
-        futures::io::BufWriter::new(writer),
-        rpc_twoparty_capnp::Side::Client,
-        Default::default(),
-    ));
+    /// Keep trying to connect the disconnected backends, backing off between
+    /// failed attempts, until one of them is up.
+    async fn reconnect(&self) {
+        loop {
+            let Some(backend) = self
+                .backends
+                .iter()
+                .filter(|backend| !backend.is_connected())
+                .min_by_key(|backend| backend.state.borrow().next_attempt)
+            else {
+                return;
+            };
+            let next_attempt = backend.state.borrow().next_attempt;
+            self.runtime
+                .delay(next_attempt.saturating_duration_since(Instant::now()))
+                .await;
+            if backend.client().await.is_ok() {
+                return;
+            }
+        }
+    }
+}
+
+/// The Cap'n Proto connection to a single receiver.
+///
+/// The [RpcSystem] runs as a [LocalTask] and finishes once the connection is
+/// gone, e.g. because the collector restarted. The next call to
+/// [Backend::client] then rebuilds the `twoparty::VatNetwork` and
+/// bootstraps a new `trace_service::Client`. Failed reconnection attempts back
+/// off exponentially so a collector that stays down is not hammered.
+struct Backend {
+    connector: Connector,
+    connect_timeout: Duration,
+    state: RefCell<ConnectionState>,
+    metrics: Arc<ExporterMetrics>,
+    runtime: Arc<dyn Runtime>,
+}
 
-    let _ = writeln!(io::stdout(), "rpc network established for exporter");
-    RpcSystem::new(rpc_network, None)
+struct ConnectionState {
+    rpc: Option<(trace_service::Client, LocalTask)>,
+    reconnect_delay: Duration,
+    next_attempt: Instant,
+    // after a failed request the backend only gets requests again once this
+    // has passed, or when no other backend is connected
+    unhealthy_until: Instant,
+    health_delay: Duration,
+    // distinguishes reconnections from the first connection
+    was_connected: bool,
 }
 
-async fn export_loop(client: trace_service::Client, mut rx_export: mpsc::Receiver<SpanRequest>) {
+impl Backend {
+    fn new(
+        connector: Connector,
+        connect_timeout: Duration,
+        metrics: Arc<ExporterMetrics>,
+        runtime: Arc<dyn Runtime>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            connector,
+            connect_timeout,
+            state: RefCell::new(ConnectionState {
+                rpc: None,
+                reconnect_delay: RECONNECT_INITIAL_DELAY,
+                next_attempt: now,
+                unhealthy_until: now,
+                health_delay: RECONNECT_INITIAL_DELAY,
+                was_connected: false,
+            }),
+            metrics,
+            runtime,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected_client().is_some()
+    }
+
+    fn connected_client(&self) -> Option<trace_service::Client> {
+        match &self.state.borrow().rpc {
+            Some((client, rpc_task)) if !rpc_task.is_finished() => Some(client.clone()),
+            _ => None,
+        }
+    }
+
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.state.borrow().unhealthy_until <= now
+    }
+
+    /// Track the outcome of a request to route the next ones away from a
+    /// backend that keeps failing.
+    fn record_outcome(&self, result: &Result<(), ExportError>) {
+        let mut state = self.state.borrow_mut();
+        match result {
+            Err(e) if e.retry_type() == RetryErrorType::Retryable => {
+                state.unhealthy_until = Instant::now() + state.health_delay;
+                state.health_delay = (state.health_delay * 2).min(RECONNECT_MAX_DELAY);
+            }
+            // the backend answered, so a rejected or malformed request is not its fault
+            _ => state.health_delay = RECONNECT_INITIAL_DELAY,
+        }
+    }
+
+    /// Run the RPC system over `stream` and bootstrap the `trace_service` client.
+    fn attach(&self, stream: Box<dyn AsyncStream>) -> io::Result<trace_service::Client> {
+        let mut rpc_system = build_capnp_rpc_system(self.connector.vat_network(stream)?);
+        let client: trace_service::Client = rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+        let rpc_task = LocalTask::spawn(self.runtime.as_ref(), rpc_system);
+
+        let mut state = self.state.borrow_mut();
+        if let Some((_, stale_rpc_task)) = state.rpc.replace((client.clone(), rpc_task)) {
+            stale_rpc_task.abort();
+        }
+        state.reconnect_delay = RECONNECT_INITIAL_DELAY;
+        Ok(client)
+    }
+
+    /// Drop the current RPC system so the next [Backend::client] call reconnects.
+    fn disconnect(&self) {
+        if let Some((_, rpc_task)) = self.state.borrow_mut().rpc.take() {
+            rpc_task.abort();
+        }
+    }
+
+    /// Return the client of the live connection, reconnecting first if the
+    /// connection has dropped and the reconnection backoff has elapsed.
+    async fn client(&self) -> Result<trace_service::Client, capnp::Error> {
+        if let Some(client) = self.connected_client() {
+            return Ok(client);
+        }
+        {
+            let mut state = self.state.borrow_mut();
+            let now = Instant::now();
+            if now < state.next_attempt {
+                return Err(capnp::Error::disconnected(format!(
+                    "Not connected to {}; next reconnection attempt in {:?}",
+                    self.connector,
+                    state.next_attempt - now
+                )));
+            }
+            // requests in flight that ask for the client meanwhile wait for
+            // this attempt instead of opening more connections
+            state.next_attempt = now + state.reconnect_delay;
+        }
+        self.disconnect();
+        let attempt = runtime::timeout(self.runtime.as_ref(), self.connect_timeout, self.connect())
+            .await
+            .unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection attempt timed out",
+                ))
+            });
+        match attempt {
+            Ok(client) => {
+                opentelemetry::otel_debug!(
+                    name: "CapnpSpanExporter.Connected",
+                    endpoint = self.connector.to_string(),
+                );
+                if std::mem::replace(&mut self.state.borrow_mut().was_connected, true) {
+                    self.metrics.reconnect();
+                }
+                Ok(client)
+            }
+            Err(e) => {
+                opentelemetry::otel_debug!(
+                    name: "CapnpSpanExporter.ConnectFailed",
+                    endpoint = self.connector.to_string(),
+                    error = e.to_string(),
+                );
+                let mut state = self.state.borrow_mut();
+                state.next_attempt = Instant::now() + state.reconnect_delay;
+                state.reconnect_delay = (state.reconnect_delay * 2).min(RECONNECT_MAX_DELAY);
+                Err(capnp::Error::disconnected(format!(
+                    "Reconnection to {} failed: {e}",
+                    self.connector
+                )))
+            }
+        }
+    }
+
+    /// Open a stream to the endpoint and bootstrap a client over it.
+    async fn connect(&self) -> io::Result<trace_service::Client> {
+        let stream = self.connector.connect(self.runtime.as_ref()).await?;
+        self.attach(stream)
+    }
+}
+
+fn build_capnp_rpc_system(rpc_network: VatNetwork) -> RpcSystem<twoparty::VatId> {
+    RpcSystem::new(Box::new(rpc_network), None)
+}
+
+async fn export_loop(
+    connection: Connection,
+    export_queue: &ExportQueue,
+    mut rx_control: mpsc::Receiver<ExporterMessage>,
+    retry_policy: RetryPolicy,
+    persistent_queue: Option<PersistentQueue>,
+    max_in_flight_requests: usize,
+) {
+    let mut in_flight = InFlightExports::new(max_in_flight_requests);
+    // span batches accepted while the receiver was unreachable, oldest first
+    let mut disconnected_buffer: VecDeque<ExportRequest> =
+        VecDeque::with_capacity(SPAN_EXPORTER_DISCONNECTED_BUFFER_SIZE);
+    // set when the receiver is reachable but did not take the oldest persisted
+    // batch; draining resumes after the retry policy's maximum delay
+    let mut persistent_queue_stalled = false;
+    // A replay step makes a single attempt at a single batch and a failed one
+    // stalls the replay, so flushes, shutdowns and new batches never wait for
+    // more than one RPC of a long replay.
+    let replay_policy = RetryPolicy {
+        max_retries: 0,
+        ..retry_policy.clone()
+    };
     loop {
+        if connection.is_connected() {
+            export_buffered(&connection, &retry_policy, &mut disconnected_buffer).await;
+            if let Some(queue) = persistent_queue
+                .as_ref()
+                .filter(|_| !persistent_queue_stalled)
+            {
+                persistent_queue_stalled = export_persisted(&connection, &replay_policy, queue, 1)
+                    .await
+                    == Replay::Stalled;
+            }
+        }
+        // A full window leaves the batches in the export queue, which pushes
+        // back on the callers.
+        dispatch_queued(
+            export_queue,
+            usize::MAX,
+            &connection,
+            &retry_policy,
+            &mut in_flight,
+            &mut disconnected_buffer,
+            persistent_queue.as_ref(),
+        )
+        .await;
         tokio::select! {
+            // Notified futures keep a pending notification, so no batch queued
+            // while another branch was running is missed.
+            _ = export_queue.queued.notified(), if !in_flight.is_full() => {},
             // The recv method is cancel safe: if the other branch completes first,
             // then no messages will have been received.
-            Some(span_request) = rx_export.recv() => {
-                if let Err(e) = export_batch(&client, span_request).await {
-                    let _ = writeln!(io::stdout(), "Export failed: {}", e);
+            message = rx_control.recv() => {
+                match message {
+                    Some(ExporterMessage::Flush(control)) => {
+                        let result = flush(
+                            &connection,
+                            &retry_policy,
+                            export_queue,
+                            &mut in_flight,
+                            &mut disconnected_buffer,
+                            persistent_queue.as_ref(),
+                            &control,
+                        )
+                        .await;
+                        let _ = control.reply.send(result);
+                    }
+                    Some(ExporterMessage::Shutdown(control)) => {
+                        let result = flush(
+                            &connection,
+                            &retry_policy,
+                            export_queue,
+                            &mut in_flight,
+                            &mut disconnected_buffer,
+                            persistent_queue.as_ref(),
+                            &control,
+                        )
+                        .await;
+                        let _ = control.reply.send(result);
+                        break;
+                    }
+                    None => {
+                        in_flight.drain().await;
+                        break;
+                    }
                 }
             },
-            else => { break;}
+            Some(()) = in_flight.next(), if !in_flight.is_empty() => {},
+            // dropping this future when a request arrives is fine: the backoff
+            // state lives in the connection and the next iteration resumes it
+            _ = connection.reconnect(), if !connection.is_fully_connected() => {
+                persistent_queue_stalled = false;
+            },
+            _ = connection.runtime.delay(Duration::from_millis(retry_policy.max_delay_ms)),
+                if persistent_queue_stalled && connection.is_connected() => {
+                persistent_queue_stalled = false;
+            },
+            // replays the next persisted batch; the branches are polled in
+            // random order, so the other ones get their turn between steps
+            _ = std::future::ready(()), if !persistent_queue_stalled
+                && connection.is_connected()
+                && persistent_queue.as_ref().is_some_and(|queue| !queue.is_empty()) => {},
+        }
+    }
+    abandon_buffered(&mut disconnected_buffer, &connection.metrics);
+}
+
+/// Make the RPCs of a [CapnpBlockingTracesClient] over `connection` until the
+/// client shuts down, answering each caller as soon as its export completes.
+async fn blocking_export_loop(
+    connection: Connection,
+    retry_policy: RetryPolicy,
+    mut rx_export: mpsc::Receiver<BlockingExportRequest>,
+) {
+    let mut exports = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            request = rx_export.recv() => match request {
+                Some(request) => exports.push(blocking_export(&connection, &retry_policy, request)),
+                None => break,
+            },
+            Some(()) = exports.next(), if !exports.is_empty() => {},
+        }
+    }
+    while exports.next().await.is_some() {}
+}
+
+/// Export the batch of a blocking caller by the caller's deadline, which
+/// covers connecting and retries as well, and report the outcome.
+async fn blocking_export(
+    connection: &Connection,
+    retry_policy: &RetryPolicy,
+    request: BlockingExportRequest,
+) {
+    let rpc_timeout = connection.rpc_timeout;
+    let result = runtime::timeout(
+        connection.runtime.as_ref(),
+        request.deadline.saturating_duration_since(Instant::now()),
+        export_batch(connection, retry_policy, &request.payload),
+    )
+    .await
+    .unwrap_or(Err(ExportError::Timeout(rpc_timeout)));
+    record_export(&connection.metrics, request.payload.span_count(), &result);
+    let _ = request.reply.send(result.map_err(OTelSdkError::from));
+}
+
+/// The export requests awaiting the receiver's response, at most `window` of
+/// them.
+///
+/// Cap'n Proto RPC multiplexes calls over one connection, so up to `window`
+/// batches are on the wire at once instead of one per round-trip. Each caller
+/// is answered as soon as the response to its own batch arrives, whatever
+/// order the responses come back in.
+struct InFlightExports<'a> {
+    window: usize,
+    requests: FuturesUnordered<LocalBoxFuture<'a, ()>>,
+}
+
+impl<'a> InFlightExports<'a> {
+    fn new(window: usize) -> Self {
+        Self {
+            window,
+            requests: FuturesUnordered::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.requests.len() >= self.window
+    }
+
+    fn push(&mut self, request: impl std::future::Future<Output = ()> + 'a) {
+        self.requests.push(Box::pin(request));
+    }
+
+    /// Wait for the next request to be answered.
+    async fn next(&mut self) -> Option<()> {
+        self.requests.next().await
+    }
+
+    /// Wait until every request has been answered.
+    async fn drain(&mut self) {
+        while self.requests.next().await.is_some() {}
+    }
+}
+
+/// Hand up to `limit` queued span batches to the persistent queue, the
+/// disconnected buffer or the window of requests in flight, stopping early
+/// when the window is full. Returns the count of batches taken.
+async fn dispatch_queued<'a>(
+    export_queue: &ExportQueue,
+    limit: usize,
+    connection: &'a Connection,
+    retry_policy: &'a RetryPolicy,
+    in_flight: &mut InFlightExports<'a>,
+    disconnected_buffer: &mut VecDeque<ExportRequest>,
+    persistent_queue: Option<&PersistentQueue>,
+) -> usize {
+    let mut dispatched = 0;
+    while dispatched < limit && !in_flight.is_full() {
+        let Some(ExportRequest { payload, reply }) = export_queue.pop() else {
+            break;
+        };
+        dispatched += 1;
+        // the caller may have given up waiting; the outcome is then only logged
+        if let Some(queue) = persistent_queue {
+            // one at a time, so the batches are persisted in order
+            let _ = reply.send(persist_batch(connection, queue, payload).await);
+        } else if !connection.is_connected() {
+            buffer_while_disconnected(
+                disconnected_buffer,
+                ExportRequest { payload, reply },
+                &connection.metrics,
+            );
+        } else {
+            in_flight.push(export_and_reply(connection, retry_policy, payload, reply));
+        }
+    }
+    dispatched
+}
+
+/// Export a span batch and report the outcome to the caller waiting on `reply`.
+async fn export_and_reply(
+    connection: &Connection,
+    retry_policy: &RetryPolicy,
+    payload: SpanPayload,
+    reply: oneshot::Sender<OTelSdkResult>,
+) {
+    let result = export_batch(connection, retry_policy, &payload).await;
+    record_export(&connection.metrics, payload.span_count(), &result);
+    let result = result.map_err(OTelSdkError::from);
+    if let Err(e) = &result {
+        opentelemetry::otel_warn!(
+            name: "CapnpSpanExporter.ExportFailed",
+            span_count = payload.span_count(),
+            error = e.to_string(),
+        );
+    }
+    let _ = reply.send(result);
+}
+
+/// Export the batches queued so far and wait for the requests in flight, then
+/// deliver the batches held back while the receiver was unreachable,
+/// reconnecting as needed, until none are left or the deadline of `control`
+/// has passed.
+///
+/// Every batch accepted before the flush has been acknowledged once this
+/// returns `Ok`. Batches queued while it runs are left for the export loop.
+async fn flush<'a>(
+    connection: &'a Connection,
+    retry_policy: &'a RetryPolicy,
+    export_queue: &ExportQueue,
+    in_flight: &mut InFlightExports<'a>,
+    disconnected_buffer: &mut VecDeque<ExportRequest>,
+    persistent_queue: Option<&PersistentQueue>,
+    control: &ControlRequest,
+) -> OTelSdkResult {
+    let drain = async {
+        let mut queued = export_queue.len();
+        while queued > 0 {
+            let dispatched = dispatch_queued(
+                export_queue,
+                queued,
+                connection,
+                retry_policy,
+                in_flight,
+                disconnected_buffer,
+                persistent_queue,
+            )
+            .await;
+            if dispatched == 0 && in_flight.is_empty() {
+                // callers dropped the batches counted above to make room
+                break;
+            }
+            queued -= dispatched;
+            if queued > 0 {
+                in_flight.next().await;
+            }
+        }
+        in_flight.drain().await;
+        loop {
+            if connection.is_connected() {
+                export_buffered(connection, retry_policy, disconnected_buffer).await;
+                if let Some(queue) = persistent_queue {
+                    export_persisted(connection, retry_policy, queue, usize::MAX).await;
+                }
+            }
+            if disconnected_buffer.is_empty()
+                && persistent_queue.is_none_or(PersistentQueue::is_empty)
+            {
+                return;
+            }
+            if connection.is_connected() {
+                // the receiver is up but did not take the persisted batches
+                connection
+                    .runtime
+                    .delay(Duration::from_millis(retry_policy.max_delay_ms))
+                    .await;
+            } else {
+                connection.reconnect().await;
+            }
+        }
+    };
+    let remaining = control
+        .deadline
+        .saturating_duration_since(std::time::Instant::now());
+    runtime::timeout(connection.runtime.as_ref(), remaining, drain)
+        .await
+        .ok_or(OTelSdkError::Timeout(control.timeout))
+}
+
+/// Hold a span batch until the receiver is reachable again. Its caller keeps
+/// waiting for the outcome, unless the buffer is full and the batch is
+/// rejected at once so the caller can account for the drop.
+fn buffer_while_disconnected(
+    disconnected_buffer: &mut VecDeque<ExportRequest>,
+    request: ExportRequest,
+    metrics: &ExporterMetrics,
+) {
+    if disconnected_buffer.len() >= SPAN_EXPORTER_DISCONNECTED_BUFFER_SIZE {
+        let span_count = request.payload.span_count();
+        metrics.spans_dropped(span_count);
+        let _ = request
+            .reply
+            .send(Err(OTelSdkError::InternalFailure(format!(
+            "Receiver is unreachable and the exporter buffer is full; dropped {span_count} spans"
+        ))));
+        return;
+    }
+    disconnected_buffer.push_back(request);
+}
+
+/// Export the batches buffered while disconnected, in the order they arrived,
+/// and report the outcome of each to its caller. Stops early and keeps the
+/// remaining batches if the connection drops again.
+async fn export_buffered(
+    connection: &Connection,
+    retry_policy: &RetryPolicy,
+    disconnected_buffer: &mut VecDeque<ExportRequest>,
+) {
+    while let Some(request) = disconnected_buffer.pop_front() {
+        let span_count = request.payload.span_count();
+        let result = export_batch(connection, retry_policy, &request.payload).await;
+        if result.is_err() && !connection.is_connected() {
+            disconnected_buffer.push_front(request);
+            return;
+        }
+        record_export(&connection.metrics, span_count, &result);
+        let result = result.map_err(OTelSdkError::from);
+        if let Err(e) = &result {
+            let error = e.to_string();
+            opentelemetry::otel_warn!(
+                name: "CapnpSpanExporter.BufferedExportFailed",
+                span_count = span_count,
+                error = error.as_str(),
+            );
+        }
+        let _ = request.reply.send(result);
+    }
+}
+
+/// Drop the batches still buffered when the exporter thread stops and tell
+/// their callers that the receiver never became reachable.
+fn abandon_buffered(disconnected_buffer: &mut VecDeque<ExportRequest>, metrics: &ExporterMetrics) {
+    for request in disconnected_buffer.drain(..) {
+        let span_count = request.payload.span_count();
+        metrics.spans_dropped(span_count);
+        let _ = request
+            .reply
+            .send(Err(OTelSdkError::InternalFailure(format!(
+                "Exporter stopped before the receiver was reachable; dropped {span_count} spans"
+            ))));
+    }
+}
+
+/// Encode a span batch, unless the caller already has, and append it to the
+/// persistent queue. The batch counts as exported once it is on disk; the
+/// exporter thread delivers it later.
+async fn persist_batch(
+    connection: &Connection,
+    queue: &PersistentQueue,
+    payload: SpanPayload,
+) -> OTelSdkResult {
+    let message = match payload {
+        SpanPayload::Spans(span_request) => {
+            let resource_spans = group_spans_by_resource_and_scope(&span_request);
+            let mut message = capnp::message::Builder::new_default();
+            populate_export_request(message.init_root(), &resource_spans)
+                .map_err(|e| OTelSdkError::from(ExportError::Encode(e.to_string())))?;
+            message
+        }
+        SpanPayload::Encoded { message, .. } => message,
+    };
+    queue
+        .push(connection.runtime.as_ref(), message)
+        .await
+        .map_err(|e| OTelSdkError::InternalFailure(format!("Failed to persist span batch: {e}")))
+}
+
+/// Where replaying the persistent queue stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Replay {
+    /// Every persisted batch has been delivered or given up on.
+    Drained,
+    /// The limit of batches was reached with more of them left on disk.
+    Paused,
+    /// The receiver did not take the oldest batch for now.
+    Stalled,
+}
+
+/// Export up to `max_batches` persisted batches, oldest first, deleting each
+/// one once the receiver has acknowledged it. Batches the receiver can never
+/// accept are dropped.
+async fn export_persisted(
+    connection: &Connection,
+    retry_policy: &RetryPolicy,
+    queue: &PersistentQueue,
+    max_batches: usize,
+) -> Replay {
+    for _ in 0..max_batches {
+        let message = match queue.front(connection.runtime.as_ref()).await {
+            Ok(Some(message)) => message,
+            Ok(None) => return Replay::Drained,
+            Err(e) => {
+                opentelemetry::otel_warn!(
+                    name: "CapnpSpanExporter.PersistedBatchUnreadable",
+                    error = e.to_string(),
+                    message = "Dropping the unreadable persisted span batch",
+                );
+                remove_persisted_batch(connection, queue).await;
+                continue;
+            }
+        };
+        let mut attempts = 0;
+        let result = retry_with_backoff(
+            connection.runtime.as_ref(),
+            retry_policy,
+            ExportError::retry_type,
+            || {
+                count_retry(connection, &mut attempts);
+                send_persisted_request(connection, &message)
+            },
+        )
+        .await;
+        if matches!(&result, Err(e) if e.retry_type() == RetryErrorType::Retryable) {
+            return Replay::Stalled;
+        }
+        let span_count = persisted_span_count(&message);
+        record_export(&connection.metrics, span_count, &result);
+        if let Err(e) = result {
+            let error = OTelSdkError::from(e).to_string();
+            opentelemetry::otel_warn!(
+                name: "CapnpSpanExporter.PersistedBatchDropped",
+                span_count = span_count,
+                error = error.as_str(),
+            );
+        }
+        remove_persisted_batch(connection, queue).await;
+    }
+    if queue.is_empty() {
+        Replay::Drained
+    } else {
+        Replay::Paused
+    }
+}
+
+/// Delete the oldest persisted batch once it has been delivered or given up on.
+async fn remove_persisted_batch(connection: &Connection, queue: &PersistentQueue) {
+    if let Err(e) = queue.pop_front(connection.runtime.as_ref()).await {
+        opentelemetry::otel_error!(
+            name: "CapnpSpanExporter.PersistedBatchRemovalFailed",
+            error = e.to_string(),
+        );
+    }
+}
+
+/// Failure of a single `trace_service` export call.
+#[derive(Debug)]
+enum ExportError {
+    /// The span batch could not be encoded into a Cap'n Proto message.
+    Encode(String),
+    /// The receiver did not answer within the RPC timeout.
+    Timeout(Duration),
+    /// The RPC itself failed, e.g. the connection dropped or the receiver
+    /// could not process the request.
+    Rpc(capnp::Error),
+    /// The receiver processed the request but rejected some of its spans.
+    Rejected {
+        rejected_spans: i64,
+        error_message: String,
+    },
+    /// The [Interceptor] refused to send the request.
+    Interceptor(String),
+}
+
+impl ExportError {
+    /// Disconnects, timeouts and an overloaded receiver may go away on their
+    /// own; anything else, e.g. a malformed request, fails the same way again.
+    fn retry_type(&self) -> RetryErrorType {
+        match self {
+            ExportError::Timeout(_) => RetryErrorType::Retryable,
+            ExportError::Rpc(e) => match e.kind {
+                capnp::ErrorKind::Disconnected | capnp::ErrorKind::Overloaded => {
+                    RetryErrorType::Retryable
+                }
+                _ => RetryErrorType::NonRetryable,
+            },
+            // the receiver already accepted the other spans of the batch
+            ExportError::Encode(_) | ExportError::Rejected { .. } | ExportError::Interceptor(_) => {
+                RetryErrorType::NonRetryable
+            }
+        }
+    }
+}
+
+impl From<ExportError> for OTelSdkError {
+    fn from(error: ExportError) -> Self {
+        match error {
+            ExportError::Encode(e) => {
+                OTelSdkError::InternalFailure(format!("Failed to encode span batch: {e}"))
+            }
+            ExportError::Timeout(timeout) => OTelSdkError::Timeout(timeout),
+            ExportError::Rpc(e) => {
+                OTelSdkError::InternalFailure(format!("Cap'n Proto export failed: {e}"))
+            }
+            ExportError::Rejected {
+                rejected_spans,
+                error_message,
+            } if error_message.is_empty() => {
+                OTelSdkError::InternalFailure(format!("Receiver rejected {rejected_spans} spans"))
+            }
+            ExportError::Rejected {
+                rejected_spans,
+                error_message,
+            } => OTelSdkError::InternalFailure(format!(
+                "Receiver rejected {rejected_spans} spans: {error_message}"
+            )),
+            ExportError::Interceptor(e) => {
+                OTelSdkError::InternalFailure(format!("Interceptor rejected export request: {e}"))
+            }
         }
     }
 }
 
 // TODO
-// - add retry with exponential backoff; use Arc::new(batch) and clone it for retries
-// - add partial success handling
-// - allow some kind of interceptor so users can inject metadata and context
-// - put resource spans as message into a Request that includes metadata, extensions, and the message
-// - need to return Success or Error for SpanExporter export without blocking or causing resource bloat
 // - switch types to be impl traits? impl Iter<SpanData> etc
 async fn export_batch(
-    client: &trace_service::Client,
-    span_request: SpanRequest,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut resource_spans = group_spans_by_resource_and_scope(span_request);
-    let resource: Arc<Resource> = resource_spans[0].resource.clone();
-    // currently assuming that group_spans_by_resource_and_scope returns a vec of length 1
-    // with a single resource
-    let mut request = client.export_request();
-    {
-        let export_trace_service_request_builder = request.get().init_request();
-        // we have a single resource, so our resource spans is a vec of length one
-        let mut resource_spans_builder =
-            export_trace_service_request_builder.init_resource_spans(1u32);
-        let mut builder_for_resource_spans = resource_spans_builder.reborrow().get(0);
-        {
-            let resource_builder = builder_for_resource_spans.reborrow().init_resource();
-            populate_resource(resource_builder, resource)?;
-        }
-        // let scope_spans_collection: Vec<ScopeSpans> = resource_spans[0].scope_spans;
-        let scope_spans_collection_length = resource_spans[0].scope_spans.len();
-        let mut scope_spans_builder = builder_for_resource_spans
-            .reborrow()
-            .init_scope_spans(scope_spans_collection_length as u32);
-        {
-            for (idx, scope_spans) in resource_spans[0].scope_spans.drain(0..1).enumerate() {
-                let builder_for_scope_spans = scope_spans_builder.reborrow().get(idx as u32);
-                populate_scope_spans(builder_for_scope_spans, scope_spans)?;
-            }
+    connection: &Connection,
+    retry_policy: &RetryPolicy,
+    payload: &SpanPayload,
+) -> Result<(), ExportError> {
+    let mut attempts = 0;
+    match payload {
+        SpanPayload::Spans(span_request) => {
+            // Every attempt encodes a fresh request from the shared batch, so
+            // retries do not clone the SpanData again.
+            let resource_spans = Arc::new(group_spans_by_resource_and_scope(span_request));
+            retry_with_backoff(
+                connection.runtime.as_ref(),
+                retry_policy,
+                ExportError::retry_type,
+                || {
+                    count_retry(connection, &mut attempts);
+                    send_export_request(connection, resource_spans.clone())
+                },
+            )
+            .await
         }
+        SpanPayload::Encoded { message, .. } => {
+            let encoded_request = message
+                .get_root_as_reader::<export_trace_service_request::Reader>()
+                .map_err(|e| ExportError::Encode(e.to_string()))?;
+            retry_with_backoff(
+                connection.runtime.as_ref(),
+                retry_policy,
+                ExportError::retry_type,
+                || {
+                    count_retry(connection, &mut attempts);
+                    send_encoded_request(connection, encoded_request)
+                },
+            )
+            .await
+        }
+    }
+}
+
+/// Count the spans of a finished export as exported, rejected by the
+/// receiver or failed.
+fn record_export(metrics: &ExporterMetrics, span_count: usize, result: &Result<(), ExportError>) {
+    match result {
+        Ok(()) => metrics.spans_exported(span_count),
+        Err(ExportError::Rejected { rejected_spans, .. }) => {
+            let rejected = usize::try_from(*rejected_spans)
+                .unwrap_or(span_count)
+                .min(span_count);
+            metrics.spans_exported(span_count - rejected);
+            metrics.spans_rejected(rejected);
+        }
+        Err(_) => metrics.spans_failed(span_count),
     }
-    // need to make OTEL complient by returning SUCCESS or FAILURE to SpanExporter.export()
-    tokio::time::timeout(
-        Duration::from_secs(CAPNP_EXPORTER_RPC_TRACES_TIMEOUT),
-        request.send().promise,
+}
+
+/// Count every attempt after the first one as a retry.
+fn count_retry(connection: &Connection, attempts: &mut u32) {
+    if *attempts > 0 {
+        connection.metrics.retry();
+    }
+    *attempts += 1;
+}
+
+/// The count of spans in a persisted export request.
+fn persisted_span_count(
+    message: &capnp::message::Reader<capnp::serialize::OwnedSegments>,
+) -> usize {
+    let count = || -> capnp::Result<usize> {
+        let mut spans = 0;
+        let request = message.get_root::<export_trace_service_request::Reader>()?;
+        for resource_spans in request.get_resource_spans()? {
+            for scope_spans in resource_spans.get_scope_spans()? {
+                spans += scope_spans.get_spans()?.len() as usize;
+            }
+        }
+        Ok(spans)
+    };
+    count().unwrap_or_default()
+}
+
+async fn send_export_request(
+    connection: &Connection,
+    resource_spans: Arc<Vec<ResourceSpans>>,
+) -> Result<(), ExportError> {
+    let (backend, client) = connection.client().await.map_err(ExportError::Rpc)?;
+    let request = build_export_request(&client, &resource_spans)
+        .map_err(|e| ExportError::Encode(e.to_string()))?;
+    send_request(connection, backend, request).await
+}
+
+async fn send_persisted_request(
+    connection: &Connection,
+    message: &capnp::message::Reader<capnp::serialize::OwnedSegments>,
+) -> Result<(), ExportError> {
+    let persisted_request = message
+        .get_root::<export_trace_service_request::Reader>()
+        .map_err(|e| ExportError::Encode(e.to_string()))?;
+    send_encoded_request(connection, persisted_request).await
+}
+
+/// Send an export request that was encoded ahead of time, copying it into the
+/// RPC request.
+async fn send_encoded_request(
+    connection: &Connection,
+    encoded_request: export_trace_service_request::Reader<'_>,
+) -> Result<(), ExportError> {
+    let (backend, client) = connection.client().await.map_err(ExportError::Rpc)?;
+    let mut request = client.export_request();
+    request
+        .get()
+        .set_request(encoded_request)
+        .map_err(|e| ExportError::Encode(e.to_string()))?;
+    send_request(connection, backend, request).await
+}
+
+async fn send_request(
+    connection: &Connection,
+    backend: &Backend,
+    mut request: TraceServiceRequest,
+) -> Result<(), ExportError> {
+    connection.request_metadata.apply(request.get())?;
+    let start = Instant::now();
+    let result = receive_response(
+        connection.runtime.as_ref(),
+        backend,
+        request,
+        connection.rpc_timeout,
     )
-    .await??;
-    // let reply = response.get()?.get_reply()?.get_count();
-    // writeln!(std::io::stdout(), "{}", reply)?;
+    .await;
+    connection.metrics.rpc_completed(start.elapsed());
+    backend.record_outcome(&result);
+    result
+}
+
+/// Send `request` to `backend` and check the receiver's response.
+async fn receive_response(
+    runtime: &dyn Runtime,
+    backend: &Backend,
+    request: TraceServiceRequest,
+    timeout: Duration,
+) -> Result<(), ExportError> {
+    let response = runtime::timeout(runtime, timeout, request.send().promise)
+        .await
+        .ok_or(ExportError::Timeout(timeout))?
+        .map_err(|e| {
+            if e.kind == capnp::ErrorKind::Disconnected {
+                backend.disconnect();
+            }
+            ExportError::Rpc(e)
+        })?;
+    let partial_success = response
+        .get()
+        .and_then(|results| results.get_response())
+        .and_then(|response| response.get_partial_success())
+        .map_err(ExportError::Rpc)?;
+    let rejected_spans = partial_success.get_rejected_spans();
+    let error_message = match partial_success.get_error_message() {
+        Ok(error_message) => error_message.to_string().unwrap_or_default(),
+        Err(_) => String::new(),
+    };
+    // Like OTLP, a message without rejected spans is a warning from a receiver
+    // that accepted the whole batch.
+    if rejected_spans > 0 || !error_message.is_empty() {
+        opentelemetry::otel_warn!(
+            name: "CapnpSpanExporter.PartialSuccess",
+            rejected_spans = rejected_spans,
+            error_message = error_message.as_str(),
+        );
+    }
+    if rejected_spans > 0 {
+        return Err(ExportError::Rejected {
+            rejected_spans,
+            error_message,
+        });
+    }
     Ok(())
 }
 
-pub fn group_spans_by_resource_and_scope(span_request: SpanRequest) -> Vec<ResourceSpans> {
-    let resource = span_request.resource;
+type TraceServiceRequest = capnp::capability::Request<
+    trace_service::export_params::Owned,
+    trace_service::export_results::Owned,
+>;
+
+fn build_export_request(
+    client: &trace_service::Client,
+    resource_spans: &[ResourceSpans],
+) -> Result<TraceServiceRequest, Box<dyn std::error::Error>> {
+    let mut request = client.export_request();
+    populate_export_request(request.get().init_request(), resource_spans)?;
+    Ok(request)
+}
+
+pub fn group_spans_by_resource_and_scope(span_request: &SpanRequest) -> Vec<ResourceSpans> {
+    let resource = span_request.resource.clone();
     let scope_map = span_request.batch.iter().fold(
         HashMap::new(),
         |mut scope_map: HashMap<&opentelemetry::InstrumentationScope, Vec<&SpanData>>, span| {
@@ -286,3 +1825,95 @@ pub fn group_spans_by_resource_and_scope(span_request: SpanRequest) -> Vec<Resou
         schema_url,
     }]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::RecordingRuntime;
+
+    fn rpc_error(kind: capnp::ErrorKind) -> ExportError {
+        ExportError::Rpc(capnp::Error {
+            kind,
+            extra: String::new(),
+        })
+    }
+
+    #[test]
+    fn transient_failures_are_retryable() {
+        for error in [
+            ExportError::Timeout(Duration::from_secs(1)),
+            rpc_error(capnp::ErrorKind::Disconnected),
+            rpc_error(capnp::ErrorKind::Overloaded),
+        ] {
+            assert_eq!(error.retry_type(), RetryErrorType::Retryable, "{error:?}");
+        }
+    }
+
+    #[test]
+    fn permanent_failures_are_not_retryable() {
+        for error in [
+            ExportError::Encode("bad span".to_string()),
+            rpc_error(capnp::ErrorKind::Failed),
+            rpc_error(capnp::ErrorKind::Unimplemented),
+            ExportError::Rejected {
+                rejected_spans: 1,
+                error_message: String::new(),
+            },
+            ExportError::Interceptor("missing API key".to_string()),
+        ] {
+            assert_eq!(
+                error.retry_type(),
+                RetryErrorType::NonRetryable,
+                "{error:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_rejected_batch_is_not_sent_again() {
+        let runtime = RecordingRuntime::default();
+        let mut attempts = 0;
+        let result = futures::executor::block_on(retry_with_backoff(
+            &runtime,
+            &RetryPolicy::default(),
+            ExportError::retry_type,
+            || {
+                attempts += 1;
+                futures::future::ready(Err::<(), _>(ExportError::Rejected {
+                    rejected_spans: 2,
+                    error_message: "invalid trace ID".to_string(),
+                }))
+            },
+        ));
+
+        assert!(matches!(result, Err(ExportError::Rejected { .. })));
+        assert_eq!(attempts, 1);
+        assert!(runtime.delays().is_empty());
+    }
+
+    #[test]
+    fn a_disconnect_is_retried_with_backoff() {
+        let runtime = RecordingRuntime::default();
+        let policy = RetryPolicy {
+            jitter_ms: 0,
+            ..RetryPolicy::default()
+        };
+        let mut attempts = 0;
+        let result = futures::executor::block_on(retry_with_backoff(
+            &runtime,
+            &policy,
+            ExportError::retry_type,
+            || {
+                attempts += 1;
+                futures::future::ready(Err::<(), _>(rpc_error(capnp::ErrorKind::Disconnected)))
+            },
+        ));
+
+        assert!(matches!(result, Err(ExportError::Rpc(_))));
+        assert_eq!(attempts, policy.max_retries + 1);
+        assert_eq!(
+            runtime.delays(),
+            [100, 200, 400].map(Duration::from_millis).to_vec()
+        );
+    }
+}
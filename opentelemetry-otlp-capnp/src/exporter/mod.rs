@@ -1,5 +1,7 @@
 use crate::exporter::capnp::CapnpExporterBuilder;
 use crate::Protocol;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::time::Duration;
 use thiserror::Error;
 
@@ -13,10 +15,57 @@ const OTEL_EXPORTER_CAPNP_PROTOCOL_RPC: &str = "capnp";
 pub const OTEL_EXPORTER_CAPNP_PROTOCOL: &str = "OTEL_EXPORTER_CAPNP_PROTOCOL";
 pub const OTEL_EXPORTER_CAPNP_PROTOCOL_DEFAULT: &str = OTEL_EXPORTER_CAPNP_PROTOCOL_RPC;
 
-/// Max waiting time for the backend to process each signal batch, defaults to 10 seconds.
+/// Max waiting time in milliseconds for the backend to process each signal batch, defaults to 10 seconds.
 pub const OTEL_EXPORTER_CAPNP_TIMEOUT: &str = "OTEL_EXPORTER_CAPNP_TIMEOUT";
 /// Default max waiting time for the backend to process each signal batch.
 pub const OTEL_EXPORTER_CAPNP_TIMEOUT_DEFAULT: Duration = Duration::from_millis(10000);
+/// Max waiting time in milliseconds for a single attempt to connect to the
+/// receiver, including the TLS handshake. Defaults to 30 seconds.
+pub const OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT: &str = "OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT";
+/// Default max waiting time for a single attempt to connect to the receiver.
+pub const OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT_DEFAULT: Duration = Duration::from_millis(30000);
+/// Count of span batches waiting for the exporter thread before the
+/// backpressure policy applies. Defaults to 32.
+pub const OTEL_EXPORTER_CAPNP_QUEUE_CAPACITY: &str = "OTEL_EXPORTER_CAPNP_QUEUE_CAPACITY";
+/// What an export does when the queue to the exporter thread is full, one of
+/// `block`, `drop_newest` or `drop_oldest`. Defaults to `block`.
+pub const OTEL_EXPORTER_CAPNP_BACKPRESSURE_POLICY: &str = "OTEL_EXPORTER_CAPNP_BACKPRESSURE_POLICY";
+/// Max waiting time in milliseconds for room in the queue to the exporter
+/// thread under the `block` policy. Defaults to 10 seconds.
+pub const OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT: &str =
+    "OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT";
+/// Default max waiting time for room in the queue to the exporter thread.
+pub const OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT_DEFAULT: Duration = Duration::from_millis(10000);
+/// Max count of retries of a failed export request. Defaults to 3.
+pub const OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES: &str = "OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES";
+/// Delay in milliseconds before the first retry. Defaults to 100.
+pub const OTEL_EXPORTER_CAPNP_RETRY_INITIAL_DELAY: &str = "OTEL_EXPORTER_CAPNP_RETRY_INITIAL_DELAY";
+/// Max delay in milliseconds between two retries. Defaults to 1600.
+pub const OTEL_EXPORTER_CAPNP_RETRY_MAX_DELAY: &str = "OTEL_EXPORTER_CAPNP_RETRY_MAX_DELAY";
+/// Max random jitter in milliseconds added to the retry delay. Defaults to 100.
+pub const OTEL_EXPORTER_CAPNP_RETRY_JITTER: &str = "OTEL_EXPORTER_CAPNP_RETRY_JITTER";
+/// Max count of 8-byte words read from a single Cap'n Proto message the
+/// receiver sends back. Defaults to the `capnp` crate's limit of 64MiB.
+pub const OTEL_EXPORTER_CAPNP_TRAVERSAL_LIMIT: &str = "OTEL_EXPORTER_CAPNP_TRAVERSAL_LIMIT";
+/// Max nesting depth of pointers in a Cap'n Proto message the receiver sends
+/// back. Defaults to the `capnp` crate's limit of 64.
+pub const OTEL_EXPORTER_CAPNP_NESTING_LIMIT: &str = "OTEL_EXPORTER_CAPNP_NESTING_LIMIT";
+/// `true` or `false` to enable or disable Nagle's algorithm on TCP connections
+/// to the receiver. Defaults to `true`, i.e. small messages are sent right away.
+pub const OTEL_EXPORTER_CAPNP_TCP_NODELAY: &str = "OTEL_EXPORTER_CAPNP_TCP_NODELAY";
+/// Compression of the Cap'n Proto message stream, one of `packed`, `zstd` or
+/// `lz4`. Defaults to no compression.
+pub const OTEL_EXPORTER_CAPNP_COMPRESSION: &str = "OTEL_EXPORTER_CAPNP_COMPRESSION";
+/// Metadata sent with every export request, as `key1=value1,key2=value2` with
+/// percent-encoded values.
+pub const OTEL_EXPORTER_CAPNP_HEADERS: &str = "OTEL_EXPORTER_CAPNP_HEADERS";
+/// Path to the PEM file with the certificate authorities trusted to verify the
+/// collector's certificate. Setting it enables TLS (requires the `tls` feature).
+pub const OTEL_EXPORTER_CAPNP_CERTIFICATE: &str = "OTEL_EXPORTER_CAPNP_CERTIFICATE";
+/// Path to the PEM file with the client certificate chain used for mutual TLS.
+pub const OTEL_EXPORTER_CAPNP_CLIENT_CERTIFICATE: &str = "OTEL_EXPORTER_CAPNP_CLIENT_CERTIFICATE";
+/// Path to the PEM file with the client private key used for mutual TLS.
+pub const OTEL_EXPORTER_CAPNP_CLIENT_KEY: &str = "OTEL_EXPORTER_CAPNP_CLIENT_KEY";
 
 pub(crate) mod capnp;
 /// Configuration for the CAPNP exporter.
@@ -59,27 +108,44 @@ impl Default for ExportConfig {
 // This could be refined after polishing and finalizing the errors.
 #[non_exhaustive]
 pub enum ExporterBuildError {
-    /// Spawning a new thread failed.
-    #[error("Spawning a new thread failed. Unable to create Reqwest-Blocking client.")]
-    ThreadSpawnFailed,
+    /// The endpoint is neither a `host:port` socket address nor a
+    /// `unix:///path/to.sock` Unix domain socket.
+    #[error("invalid endpoint '{endpoint}': {reason}")]
+    InvalidEndpoint { endpoint: String, reason: String },
 
-    /// Feature required to use the specified compression algorithm.
-    // #[cfg(any(not(feature = "gzip-tonic"), not(feature = "zstd-tonic")))]
-    // #[error("feature '{0}' is required to use the compression algorithm '{1}'")]
-    // FeatureRequiredForCompressionAlgorithm(&'static str, Compression),
+    /// The host of the endpoint does not resolve to a socket address.
+    #[error("unable to resolve the host of endpoint '{endpoint}': {reason}")]
+    UnresolvableHost { endpoint: String, reason: String },
+
+    /// Spawning the exporter thread failed.
+    #[error("spawning the exporter thread failed: {0}")]
+    ThreadSpawnFailed(#[source] std::io::Error),
+
+    /// Creating the async runtime the exporter thread runs on failed.
+    #[error("creating the exporter runtime failed: {0}")]
+    RuntimeCreationFailed(#[source] std::io::Error),
 
-    /// No Http client specified.
-    #[error("no http client specified")]
-    NoHttpClient,
+    /// No async runtime was set and neither the `rt-tokio` nor the `rt-smol`
+    /// feature is enabled.
+    #[error("no async runtime: enable the 'rt-tokio' or 'rt-smol' feature or set one with `with_runtime`")]
+    NoRuntime,
+
+    /// Feature required to use the specified compression algorithm.
+    #[error("feature '{0}' is required to use the compression algorithm '{1}'")]
+    FeatureRequiredForCompressionAlgorithm(&'static str, Compression),
 
     /// Unsupported compression algorithm.
     #[error("unsupported compression algorithm '{0}'")]
     UnsupportedCompressionAlgorithm(String),
 
-    /// Invalid URI.
-    // #[cfg(any(feature = "rpc-capnp", feature = "http-proto", feature = "http-json"))]
-    // #[error("invalid URI {0}. Reason {1}")]
-    // InvalidUri(String, String),
+    /// An `OTEL_EXPORTER_CAPNP_*` environment variable holds a value that
+    /// does not parse.
+    #[error("invalid value '{value}' of environment variable {name}: {reason}")]
+    InvalidEnvValue {
+        name: String,
+        value: String,
+        reason: String,
+    },
 
     /// Failed due to an internal error.
     /// The error message is intended for logging purposes only and should not
@@ -90,6 +156,66 @@ pub enum ExporterBuildError {
     InternalFailure(String),
 }
 
+/// The compression applied to the Cap'n Proto message stream between the
+/// exporter and the [crate::SpanReceiver].
+///
+/// Both ends of a connection must use the same compression. Unlike OTLP over
+/// gRPC, which compresses each request separately, the whole stream is
+/// compressed, so the zstd and lz4 dictionaries carry over from one span
+/// batch to the next.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// Cap'n Proto packed encoding, which strips the zero bytes of the
+    /// word-aligned wire format. Cheap on CPU and always available.
+    Packed,
+    /// zstd stream compression (requires the `zstd` feature).
+    Zstd,
+    /// lz4 stream compression (requires the `lz4` feature).
+    Lz4,
+}
+
+impl Compression {
+    /// Fail if the crate was built without the feature this compression needs.
+    pub(crate) fn ensure_supported(self) -> Result<Self, ExporterBuildError> {
+        match self {
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => Err(ExporterBuildError::FeatureRequiredForCompressionAlgorithm(
+                "zstd", self,
+            )),
+            #[cfg(not(feature = "lz4"))]
+            Compression::Lz4 => Err(ExporterBuildError::FeatureRequiredForCompressionAlgorithm(
+                "lz4", self,
+            )),
+            _ => Ok(self),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Packed => write!(f, "packed"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ExporterBuildError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "packed" => Ok(Compression::Packed),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(ExporterBuildError::UnsupportedCompressionAlgorithm(
+                s.to_string(),
+            )),
+        }
+    }
+}
+
 /// Provide access to the [ExportConfig] field within the exporter builders.
 pub trait HasExportConfig {
     /// Return a mutable reference to the [ExportConfig] within the exporter builders.
@@ -123,6 +249,12 @@ fn default_protocol() -> Protocol {
 pub trait WithExportConfig {
     /// Set the address of the CAPNP collector. If not set or set to empty string, the default address is used.
     ///
+    /// Either a TCP socket address such as `127.0.0.1:4317` or, on Unix, a
+    /// Unix domain socket such as `unix:///run/otel/collector.sock`. A
+    /// comma-separated list of endpoints, or a host name that resolves to
+    /// several addresses, spreads the export over all of them. Since commas
+    /// separate the endpoints, a Unix domain socket path cannot contain one.
+    ///
     /// Note: Programmatically setting this will override any value set via the environment variable.
     fn with_endpoint<T: Into<String>>(self, endpoint: T) -> Self;
     /// Set the protocol to use when communicating with the collector.
@@ -133,7 +265,7 @@ pub trait WithExportConfig {
     /// ## Note
     /// All exporters in this crate only support one protocol, thus choosing the protocol is a no-op at the moment.
     fn with_protocol(self, protocol: Protocol) -> Self;
-    /// Set the timeout to the collector.
+    /// Set the max waiting time for the collector to answer each export request.
     ///
     /// Note: Programmatically setting this will override any value set via the environment variable.
     fn with_timeout(self, timeout: Duration) -> Self;
@@ -1,13 +1,46 @@
 mod exporter;
+mod metadata;
+#[cfg(feature = "rt-tokio")]
 mod receiver;
 pub mod retry;
+pub mod runtime;
 mod span;
-pub use crate::exporter::capnp::{connect_with_retry, CapnpConfig, CapnpExporterBuilder};
-pub use crate::exporter::ExporterBuildError;
-pub use crate::receiver::SpanReceiver;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
+#[cfg(feature = "rt-tokio")]
+pub use crate::exporter::capnp::connect_with_retry;
+pub use crate::exporter::capnp::metrics::ExporterMetricsSnapshot;
+pub use crate::exporter::capnp::persistent_queue::{OverflowPolicy, PersistentQueueConfig};
+pub use crate::exporter::capnp::{
+    BackpressurePolicy, CapnpConfig, CapnpExporterBuilder, HasCapnpConfig, LoadBalancingStrategy,
+    WithCapnpConfig,
+};
+pub use crate::exporter::{Compression, ExporterBuildError};
+pub use crate::metadata::{Interceptor, Metadata};
+#[cfg(feature = "rt-tokio")]
+pub use crate::receiver::{SpanReceiver, SpanReceiverHandle};
+pub use crate::runtime::ExporterTask;
 pub use crate::span::{
-    SpanExporter, OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT, OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT,
+    SpanExporter, OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY,
+    OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_TIMEOUT, OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE,
+    OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE, OTEL_EXPORTER_CAPNP_TRACES_CLIENT_KEY,
+    OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION, OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT,
+    OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT, OTEL_EXPORTER_CAPNP_TRACES_HEADERS,
+    OTEL_EXPORTER_CAPNP_TRACES_NESTING_LIMIT, OTEL_EXPORTER_CAPNP_TRACES_QUEUE_CAPACITY,
+    OTEL_EXPORTER_CAPNP_TRACES_RETRY_INITIAL_DELAY, OTEL_EXPORTER_CAPNP_TRACES_RETRY_JITTER,
+    OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_DELAY, OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_RETRIES,
+    OTEL_EXPORTER_CAPNP_TRACES_TCP_NODELAY, OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT,
+    OTEL_EXPORTER_CAPNP_TRACES_TRAVERSAL_LIMIT,
 };
+#[cfg(feature = "tls")]
+pub use crate::tls::ClientTlsConfig;
+#[cfg(all(feature = "tls", feature = "rt-tokio"))]
+pub use crate::tls::ServerTlsConfig;
+#[cfg(feature = "rt-tokio")]
+pub use crate::transport::Acceptor;
+pub use crate::transport::{MessageReader, VatNetwork};
+pub use capnp::message::ReaderOptions;
 pub use exporter::ExportConfig;
 
 pub struct ShutDown;
@@ -20,9 +53,18 @@ pub enum Protocol {
 }
 
 pub use crate::exporter::{
-    WithExportConfig, OTEL_EXPORTER_CAPNP_ENDPOINT, OTEL_EXPORTER_CAPNP_ENDPOINT_DEFAULT,
-    OTEL_EXPORTER_CAPNP_PROTOCOL, OTEL_EXPORTER_CAPNP_PROTOCOL_DEFAULT,
-    OTEL_EXPORTER_CAPNP_TIMEOUT, OTEL_EXPORTER_CAPNP_TIMEOUT_DEFAULT,
+    WithExportConfig, OTEL_EXPORTER_CAPNP_BACKPRESSURE_POLICY,
+    OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT, OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT_DEFAULT,
+    OTEL_EXPORTER_CAPNP_CERTIFICATE, OTEL_EXPORTER_CAPNP_CLIENT_CERTIFICATE,
+    OTEL_EXPORTER_CAPNP_CLIENT_KEY, OTEL_EXPORTER_CAPNP_COMPRESSION,
+    OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT, OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT_DEFAULT,
+    OTEL_EXPORTER_CAPNP_ENDPOINT, OTEL_EXPORTER_CAPNP_ENDPOINT_DEFAULT,
+    OTEL_EXPORTER_CAPNP_HEADERS, OTEL_EXPORTER_CAPNP_NESTING_LIMIT, OTEL_EXPORTER_CAPNP_PROTOCOL,
+    OTEL_EXPORTER_CAPNP_PROTOCOL_DEFAULT, OTEL_EXPORTER_CAPNP_QUEUE_CAPACITY,
+    OTEL_EXPORTER_CAPNP_RETRY_INITIAL_DELAY, OTEL_EXPORTER_CAPNP_RETRY_JITTER,
+    OTEL_EXPORTER_CAPNP_RETRY_MAX_DELAY, OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES,
+    OTEL_EXPORTER_CAPNP_TCP_NODELAY, OTEL_EXPORTER_CAPNP_TIMEOUT,
+    OTEL_EXPORTER_CAPNP_TIMEOUT_DEFAULT, OTEL_EXPORTER_CAPNP_TRAVERSAL_LIMIT,
 };
 
 /// Type to hold the [CapnpExporterBuilder] and indicate it has been set.
@@ -0,0 +1,202 @@
+//! Request metadata carried alongside every `trace_service` export call.
+//!
+//! This is the Cap'n Proto counterpart of gRPC metadata or HTTP headers in
+//! OTLP: key-value pairs such as a tenant ID or an API key that describe the
+//! request rather than the spans in it.
+use opentelemetry_capnp::capnp::capnp_rpc::metadata_entry;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Key-value pairs sent with an export request.
+///
+/// ```
+/// use opentelemetry_otlp_capnp::Metadata;
+///
+/// let mut metadata = Metadata::new();
+/// metadata.insert("x-tenant-id", "tenant-1");
+/// assert_eq!(metadata.get("x-tenant-id"), Some("tenant-1"));
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    entries: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// Create empty metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key` to `value`, returning the previous value of `key`.
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> Option<String> {
+        self.entries.insert(key.into(), value.into())
+    }
+
+    /// The value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    /// Remove `key`, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.entries.remove(key)
+    }
+
+    /// The entries ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Parse the `key1=value1,key2=value2` format of the
+    /// `OTEL_EXPORTER_CAPNP_HEADERS` environment variables, where values are
+    /// percent-encoded. Malformed pairs are skipped.
+    pub(crate) fn parse_header_string(value: &str) -> Self {
+        value
+            .split(',')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.trim(), percent_decode(value.trim())))
+            .filter(|(key, _)| !key.is_empty())
+            .collect()
+    }
+
+    pub(crate) fn write_to(
+        &self,
+        mut builder: capnp::struct_list::Builder<'_, metadata_entry::Owned>,
+    ) {
+        for (idx, (key, value)) in self.iter().enumerate() {
+            let mut entry = builder.reborrow().get(idx as u32);
+            entry.set_key(key);
+            entry.set_value(value);
+        }
+    }
+
+    #[cfg(feature = "rt-tokio")]
+    pub(crate) fn read_from(
+        reader: capnp::struct_list::Reader<'_, metadata_entry::Owned>,
+    ) -> capnp::Result<Self> {
+        reader
+            .iter()
+            .map(|entry| {
+                Ok((
+                    entry.get_key()?.to_string()?,
+                    entry.get_value()?.to_string()?,
+                ))
+            })
+            .collect()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Metadata {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        Self {
+            entries: iter
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        }
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for Metadata {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        self.entries.extend(
+            iter.into_iter()
+                .map(|(key, value)| (key.into(), value.into())),
+        );
+    }
+}
+
+/// Adds or modifies the [Metadata] of every export request before it is sent,
+/// e.g. to attach a short-lived access token.
+///
+/// It runs on the exporter thread for every attempt, including retries. An
+/// error fails the export without sending it.
+///
+/// ```no_run
+/// use opentelemetry_otlp_capnp::{Metadata, SpanExporter};
+///
+/// let exporter = SpanExporter::builder()
+///     .with_capnp()
+///     .with_interceptor(|metadata: &mut Metadata| {
+///         metadata.insert("authorization", "Bearer secret");
+///         Ok(())
+///     })
+///     .build();
+/// ```
+pub trait Interceptor: Send + Sync + 'static {
+    fn call(&self, metadata: &mut Metadata) -> Result<(), String>;
+}
+
+impl<F> Interceptor for F
+where
+    F: Fn(&mut Metadata) -> Result<(), String> + Send + Sync + 'static,
+{
+    fn call(&self, metadata: &mut Metadata) -> Result<(), String> {
+        self(metadata)
+    }
+}
+
+impl fmt::Debug for dyn Interceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Interceptor")
+    }
+}
+
+/// Decode `%XX` escapes, keeping anything that is not a valid escape as is.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = (bytes[idx] == b'%')
+            .then(|| value.get(idx + 1..idx + 3))
+            .flatten()
+            // `from_str_radix` alone would also take a sign, e.g. `%+1`
+            .filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_are_decoded() {
+        assert_eq!(percent_decode("abc%20def%2c%2C"), "abc def,,");
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn signs_are_not_hex_digits() {
+        assert_eq!(percent_decode("%+1"), "%+1");
+        assert_eq!(percent_decode("%-1"), "%-1");
+    }
+}
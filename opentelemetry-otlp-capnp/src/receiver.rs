@@ -1,68 +1,259 @@
 use capnp::capability::Promise;
-use capnp_rpc::{pry, rpc_twoparty_capnp, twoparty, RpcSystem};
-use futures::io::AsyncReadExt;
+use capnp_rpc::{pry, RpcSystem};
 use opentelemetry_capnp::capnp::capnp_rpc::trace_service;
-use std::io::Write;
-use std::net::{SocketAddr, ToSocketAddrs};
+
+use crate::transport::{Acceptor, AsyncStream, Endpoint, VatNetwork};
+use crate::{Compression, ExporterBuildError, Metadata};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long the receiver waits after failing to accept a connection, e.g.
+/// because the process ran out of file descriptors, before accepting again.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Inspects the [Metadata] of every export request, see [SpanReceiver::with_interceptor].
+type ReceiverInterceptor = Box<dyn Fn(&Metadata) -> Result<(), String> + Send>;
 
 /// A Span receiver for Cap'n Proto RPC. This is a sketch and needs to be
 /// developed.
 ///
+/// It listens on a TCP socket address or, on Unix, on a Unix domain socket
+/// given as `unix:///path/to.sock`.
+///
 /// ```rust, no_run
 /// use opentelemetry_otlp_capnp::SpanReceiver;
 /// const TEST_ADDRESS: &str = "127.0.0.1:8080";
 ///
 /// #[tokio::main]
 /// pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
-///     let span_receiver = SpanReceiver::new(TEST_ADDRESS)
+///     let span_receiver = SpanReceiver::new(TEST_ADDRESS)?
 ///         .start()
 ///         .map_err(|e| format!("Failed to start SpanReceiver: {e}"))?;
 ///     Ok(())
 /// }
 /// ```
 pub struct SpanReceiver {
-    addr: SocketAddr,
+    endpoint: Endpoint,
+    compression: Option<Compression>,
+    interceptor: Option<ReceiverInterceptor>,
+    quiet: bool,
+    #[cfg(unix)]
+    unix_socket_permissions: Option<u32>,
+    #[cfg(feature = "tls")]
+    tls_config: Option<crate::ServerTlsConfig>,
 }
 
 /// To demonstrate using Cap'n Proto over the wire we need a receiver that
 /// can handle Cap'n Proto client requests. This is a mini-server that does that.
 impl SpanReceiver {
-    pub fn new(addr: &str) -> Self {
-        let addr = addr
-            .to_socket_addrs()
-            .expect("Valid socket address")
-            .next()
-            .expect("At least one address");
-        Self { addr }
+    /// Create a receiver for `addr`, failing with
+    /// [ExporterBuildError::InvalidEndpoint] or
+    /// [ExporterBuildError::UnresolvableHost] if it is not an endpoint to
+    /// listen on.
+    pub fn new(addr: &str) -> Result<Self, ExporterBuildError> {
+        let endpoint = Endpoint::parse(addr)?;
+        Ok(Self {
+            endpoint,
+            compression: None,
+            interceptor: None,
+            quiet: false,
+            #[cfg(unix)]
+            unix_socket_permissions: None,
+            #[cfg(feature = "tls")]
+            tls_config: None,
+        })
+    }
+
+    /// Expect the Cap'n Proto message stream to be compressed with
+    /// `compression`, which must match the exporters' [Compression].
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
     }
 
-    pub fn start(self) -> std::io::Result<std::thread::JoinHandle<()>> {
+    /// Run `interceptor` on the [Metadata] the exporter sent with each export
+    /// request, e.g. to check an API key. An error rejects the request and is
+    /// returned to the exporter.
+    pub fn with_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&Metadata) -> Result<(), String> + Send + 'static,
+    {
+        self.interceptor = Some(Box::new(interceptor));
+        self
+    }
+
+    /// Emit no diagnostics at all, not even through OpenTelemetry's internal
+    /// logging. By default the receiver logs each request and its spans as
+    /// `SpanReceiver.*` debug events.
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Accept only TLS connections.
+    #[cfg(feature = "tls")]
+    pub fn with_tls_config(mut self, tls_config: crate::ServerTlsConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Set the permissions of the Unix domain socket file, e.g. `0o660` to
+    /// only let the owner and group connect. Ignored for TCP endpoints.
+    ///
+    /// A socket file left behind by a previous receiver is replaced on start
+    /// and the socket file is removed when the receiver is stopped with
+    /// [SpanReceiverHandle::stop].
+    #[cfg(unix)]
+    pub fn with_unix_socket_permissions(mut self, mode: u32) -> Self {
+        self.unix_socket_permissions = Some(mode);
+        self
+    }
+
+    /// Listen on the receiver's endpoint and serve export requests on a new
+    /// thread until [SpanReceiverHandle::stop] is called. Fails if the
+    /// endpoint cannot be bound, e.g. because another process listens on it.
+    pub fn start(self) -> std::io::Result<SpanReceiverHandle> {
         // TODO
         // integrate into the OTEL API/SDK. There appears to be no SpanReceiver!
         //
         // TODO
         // this uses the minimal span_export interface; implement the full trace_service interface.
-        let handle = std::thread::spawn(move || {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
-            let local = tokio::task::LocalSet::new();
-
-            local.block_on(&rt, async {
-                let listener = tokio::net::TcpListener::bind(self.addr).await.unwrap();
-                // let client: trace_service::Client = capnp_rpc::new_client(SpanReceiver);
-                let client: trace_service::Client = capnp_rpc::new_client(self);
-
-                loop {
-                    let (stream, _) = listener.accept().await.unwrap();
-                    stream.set_nodelay(true).unwrap();
-
-                    spawn_local_rpc_system_to_handle_stream(stream, client.clone()).await;
+        let compression = self
+            .compression
+            .map(Compression::ensure_supported)
+            .transpose()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Unsupported, e))?;
+        let acceptor = Acceptor::default().with_compression(compression);
+        #[cfg(feature = "tls")]
+        let acceptor = match &self.tls_config {
+            Some(tls_config) => acceptor.with_tls(tls_config.acceptor()?),
+            None => acceptor,
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let listener = {
+            let _guard = rt.enter();
+            Listener::bind(
+                &self.endpoint,
+                #[cfg(unix)]
+                self.unix_socket_permissions,
+            )?
+        };
+        let shutdown = Arc::new(Notify::new());
+        let stop = shutdown.clone();
+        let thread = std::thread::Builder::new()
+            .name("capnp-span-receiver".to_string())
+            .spawn(move || {
+                let local = tokio::task::LocalSet::new();
+
+                local.block_on(&rt, async {
+                    let quiet = self.quiet;
+                    // let client: trace_service::Client = capnp_rpc::new_client(SpanReceiver);
+                    let client: trace_service::Client = capnp_rpc::new_client(self);
+
+                    loop {
+                        let accepted = tokio::select! {
+                            accepted = listener.accept(quiet) => accepted,
+                            _ = stop.notified() => break,
+                        };
+                        match accepted {
+                            Ok(stream) => {
+                                handle_connection(&acceptor, stream, client.clone(), quiet)
+                            }
+                            Err(e) => {
+                                if !quiet {
+                                    let error = e.to_string();
+                                    opentelemetry::otel_warn!(
+                                        name: "SpanReceiver.AcceptFailed",
+                                        error = error.as_str(),
+                                    );
+                                }
+                                // errors like EMFILE persist for a while, so
+                                // do not retry in a hot loop
+                                tokio::select! {
+                                    _ = tokio::time::sleep(ACCEPT_ERROR_BACKOFF) => {}
+                                    _ = stop.notified() => break,
+                                }
+                            }
+                        }
+                    }
+                    // dropping the runtime's tasks closes the open connections
+                    // and dropping the listener removes a Unix socket file
+                })
+            })?;
+        Ok(SpanReceiverHandle { shutdown, thread })
+    }
+}
+
+/// A [SpanReceiver] serving export requests on its own thread, see
+/// [SpanReceiver::start].
+///
+/// Dropping the handle leaves the receiver running until the process exits.
+#[derive(Debug)]
+pub struct SpanReceiverHandle {
+    shutdown: Arc<Notify>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl SpanReceiverHandle {
+    /// Stop listening, close the open connections and wait for the receiver's
+    /// thread to exit. Fails if that thread panicked.
+    pub fn stop(self) -> std::thread::Result<()> {
+        self.shutdown.notify_one();
+        self.thread.join()
+    }
+}
+
+/// The socket a [SpanReceiver] listens on, bound before its thread starts so
+/// that [SpanReceiver::start] can report a failure to bind.
+enum Listener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(crate::transport::UnixSocketListener),
+}
+
+impl Listener {
+    /// Bind `endpoint`; must be called within the receiver's runtime.
+    fn bind(
+        endpoint: &Endpoint,
+        #[cfg(unix)] unix_socket_permissions: Option<u32>,
+    ) -> std::io::Result<Self> {
+        match endpoint {
+            Endpoint::Tcp(addr) => {
+                let listener = std::net::TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(Listener::Tcp(tokio::net::TcpListener::from_std(listener)?))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Listener::Unix(crate::transport::UnixSocketListener::bind(
+                path,
+                unix_socket_permissions,
+            )?)),
+        }
+    }
+
+    /// Accept the next connection. Failing to disable Nagle's algorithm on a
+    /// TCP connection is logged rather than dropping the connection.
+    async fn accept(&self, quiet: bool) -> std::io::Result<Box<dyn AsyncStream>> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                if let Err(e) = stream.set_nodelay(true) {
+                    if !quiet {
+                        let error = e.to_string();
+                        opentelemetry::otel_warn!(
+                            name: "SpanReceiver.SetNodelayFailed",
+                            error = error.as_str(),
+                        );
+                    }
                 }
-            })
-        });
-        Ok(handle)
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Box::new(listener.accept().await?)),
+        }
     }
 }
 
@@ -78,20 +269,41 @@ impl trace_service::Server for SpanReceiver {
         mut results: trace_service::ExportResults,
     ) -> impl futures::Future<Output = Result<(), capnp::Error>> + 'static {
         let request = pry!(params.get());
+        if let Some(interceptor) = &self.interceptor {
+            let metadata = pry!(request.get_metadata().and_then(Metadata::read_from));
+            if let Err(e) = interceptor(&metadata) {
+                return Promise::err(capnp::Error::failed(format!("Request rejected: {e}")));
+            }
+        }
         let request_data = pry!(request.get_request());
-        let resource_spans = pry!(request_data.get_resource_spans());
-        let first_resource_span = resource_spans.get(0);
-        let scope_spans = first_resource_span.get_scope_spans();
-        pry!(writeln!(
-            std::io::stdout(),
-            "received {} spans on {}",
-            scope_spans.iter().count(),
-            std::process::id()
-        ));
-        for span in scope_spans.iter() {
-            pry!(writeln!(std::io::stdout(), "{:#?}", span));
+        if !self.quiet {
+            let resource_spans = pry!(request_data.get_resource_spans());
+            let mut span_count = 0;
+            for resource_spans in resource_spans.iter() {
+                for scope_spans in pry!(resource_spans.get_scope_spans()).iter() {
+                    span_count += pry!(scope_spans.get_spans()).len();
+                }
+            }
+            opentelemetry::otel_debug!(
+                name: "SpanReceiver.SpansReceived",
+                span_count = span_count,
+                pid = std::process::id(),
+            );
+            for resource_spans in resource_spans.iter() {
+                for scope_spans in pry!(resource_spans.get_scope_spans()).iter() {
+                    for span in pry!(scope_spans.get_spans()).iter() {
+                        opentelemetry::otel_debug!(
+                            name: "SpanReceiver.Span",
+                            span = format!("{span:?}"),
+                        );
+                    }
+                }
+            }
+            opentelemetry::otel_debug!(
+                name: "SpanReceiver.ExportFinished",
+                span_count = span_count,
+            );
         }
-        pry!(writeln!(std::io::stdout(), "finished receiving spans"));
 
         let response_builder = results.get().init_response();
         let mut partial_success_builder = response_builder.init_partial_success();
@@ -104,19 +316,37 @@ impl trace_service::Server for SpanReceiver {
     }
 }
 
-async fn spawn_local_rpc_system_to_handle_stream(
-    stream: tokio::net::TcpStream,
+/// Finish the handshake, e.g. TLS, off the accept loop so a slow client does
+/// not hold up others, then serve the `trace_service` on the stream.
+fn handle_connection(
+    acceptor: &Acceptor,
+    stream: Box<dyn AsyncStream>,
     client: trace_service::Client,
+    quiet: bool,
 ) {
-    let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
-
-    let rpc_network = twoparty::VatNetwork::new(
-        futures::io::BufReader::new(reader),
-        futures::io::BufWriter::new(writer),
-        rpc_twoparty_capnp::Side::Server,
-        Default::default(),
-    );
+    let acceptor = acceptor.clone();
+    tokio::task::spawn_local(async move {
+        match acceptor
+            .accept(stream)
+            .await
+            .and_then(|stream| acceptor.vat_network(stream))
+        {
+            Ok(rpc_network) => spawn_local_rpc_system_to_handle_stream(rpc_network, client).await,
+            Err(e) if !quiet => {
+                opentelemetry::otel_warn!(
+                    name: "SpanReceiver.AcceptFailed",
+                    error = e.to_string(),
+                );
+            }
+            Err(_) => {}
+        }
+    });
+}
 
+async fn spawn_local_rpc_system_to_handle_stream(
+    rpc_network: VatNetwork,
+    client: trace_service::Client,
+) {
     let rpc_system = RpcSystem::new(Box::new(rpc_network), Some(client.clone().client));
     tokio::task::spawn_local(rpc_system);
 }
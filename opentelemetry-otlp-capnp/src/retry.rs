@@ -1,3 +1,7 @@
+use crate::runtime::Runtime;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 /// Configuration for retry policy.
 #[derive(Debug, Clone)]
 pub struct RetryPolicy {
@@ -10,3 +14,221 @@ pub struct RetryPolicy {
     /// Maximum jitter in milliseconds to add to the delay.
     pub jitter_ms: u64,
 }
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay_ms: 100,
+            max_delay_ms: 1600,
+            jitter_ms: 100,
+        }
+    }
+}
+
+/// Whether a failed operation is worth attempting again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RetryErrorType {
+    /// The failure is permanent, e.g. the request is malformed.
+    NonRetryable,
+    /// The failure is transient, e.g. a disconnect, timeout or overloaded receiver.
+    Retryable,
+}
+
+/// Run `operation` until it succeeds, fails with a non-retryable error or the
+/// policy's retries are exhausted.
+///
+/// The delay starts at `initial_delay_ms`, doubles after every attempt and is
+/// capped at `max_delay_ms`. Up to `jitter_ms` of jitter is added to each delay
+/// so that many exporters do not retry in lockstep.
+pub(crate) async fn retry_with_backoff<F, Fut, T, E, C>(
+    runtime: &dyn Runtime,
+    policy: &RetryPolicy,
+    classify: C,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    C: Fn(&E) -> RetryErrorType,
+{
+    let mut attempt = 0;
+    let mut delay_ms = policy.initial_delay_ms;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                if attempt >= policy.max_retries || classify(&e) == RetryErrorType::NonRetryable {
+                    return Err(e);
+                }
+                attempt += 1;
+                let delay_with_jitter = delay_ms
+                    .saturating_add(generate_jitter(policy.jitter_ms))
+                    .min(policy.max_delay_ms);
+                runtime
+                    .delay(Duration::from_millis(delay_with_jitter))
+                    .await;
+                delay_ms = (delay_ms.saturating_mul(2)).min(policy.max_delay_ms);
+            }
+        }
+    }
+}
+
+/// Cheap jitter in `0..=max_jitter_ms` derived from the clock, which avoids
+/// pulling in a random number generator for this alone.
+fn generate_jitter(max_jitter_ms: u64) -> u64 {
+    if max_jitter_ms == 0 {
+        return 0;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    nanos as u64 % max_jitter_ms.saturating_add(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::RecordingRuntime;
+    use std::cell::Cell;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Failure {
+        Transient,
+        Permanent,
+    }
+
+    fn classify(failure: &Failure) -> RetryErrorType {
+        match failure {
+            Failure::Transient => RetryErrorType::Retryable,
+            Failure::Permanent => RetryErrorType::NonRetryable,
+        }
+    }
+
+    fn policy(max_retries: usize, jitter_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_delay_ms: 100,
+            max_delay_ms: 400,
+            jitter_ms,
+        }
+    }
+
+    /// Run `retry_with_backoff` over an operation failing with `failures` in
+    /// turn, then succeeding. Returns the result, the count of attempts and
+    /// the delays waited between them.
+    fn run(
+        policy: &RetryPolicy,
+        failures: Vec<Failure>,
+    ) -> (Result<(), Failure>, usize, Vec<Duration>) {
+        let runtime = RecordingRuntime::default();
+        let attempts = Cell::new(0);
+        let mut failures = failures.into_iter();
+        let result =
+            futures::executor::block_on(retry_with_backoff(&runtime, policy, classify, || {
+                attempts.set(attempts.get() + 1);
+                futures::future::ready(failures.next().map_or(Ok(()), Err))
+            }));
+        (result, attempts.get(), runtime.delays())
+    }
+
+    fn millis(delays: &[u64]) -> Vec<Duration> {
+        delays.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn succeeds_once_a_retry_does() {
+        let (result, attempts, delays) =
+            run(&policy(3, 0), vec![Failure::Transient, Failure::Transient]);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(attempts, 3);
+        assert_eq!(delays, millis(&[100, 200]));
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let (result, attempts, delays) =
+            run(&policy(3, 0), (0..10).map(|_| Failure::Transient).collect());
+
+        assert_eq!(result, Err(Failure::Transient));
+        assert_eq!(attempts, 4);
+        assert_eq!(delays.len(), 3);
+    }
+
+    #[test]
+    fn delays_double_up_to_the_max_delay() {
+        let (_, _, delays) = run(&policy(5, 0), (0..10).map(|_| Failure::Transient).collect());
+
+        assert_eq!(delays, millis(&[100, 200, 400, 400, 400]));
+    }
+
+    #[test]
+    fn jitter_is_added_within_bounds() {
+        let policy = RetryPolicy {
+            max_delay_ms: 10_000,
+            ..policy(4, 50)
+        };
+        let (_, _, delays) = run(&policy, (0..10).map(|_| Failure::Transient).collect());
+
+        for (delay, base) in delays.iter().zip([100, 200, 400, 800]) {
+            assert!(
+                (base..=base + 50).contains(&(delay.as_millis() as u64)),
+                "{delay:?} is not within 50ms of jitter over {base}ms"
+            );
+        }
+    }
+
+    #[test]
+    fn jitter_never_exceeds_the_delay_cap() {
+        let (_, _, delays) = run(
+            &policy(5, 1_000),
+            (0..10).map(|_| Failure::Transient).collect(),
+        );
+
+        assert!(delays
+            .iter()
+            .all(|delay| *delay <= Duration::from_millis(400)));
+    }
+
+    #[test]
+    fn huge_delays_and_jitter_do_not_overflow() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_delay_ms: u64::MAX,
+            max_delay_ms: u64::MAX,
+            jitter_ms: u64::MAX,
+        };
+        let (result, attempts, delays) = run(&policy, vec![Failure::Transient]);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(attempts, 2);
+        assert_eq!(delays, millis(&[u64::MAX]));
+    }
+
+    #[test]
+    fn non_retryable_errors_stop_at_once() {
+        let (result, attempts, delays) =
+            run(&policy(3, 0), vec![Failure::Transient, Failure::Permanent]);
+
+        assert_eq!(result, Err(Failure::Permanent));
+        assert_eq!(attempts, 2);
+        assert_eq!(delays, millis(&[100]));
+    }
+
+    #[test]
+    fn no_retries_means_a_single_attempt() {
+        let (result, attempts, delays) = run(&policy(0, 0), vec![Failure::Transient]);
+
+        assert_eq!(result, Err(Failure::Transient));
+        assert_eq!(attempts, 1);
+        assert!(delays.is_empty());
+    }
+
+    #[test]
+    fn generated_jitter_is_bounded() {
+        assert_eq!(generate_jitter(0), 0);
+        assert!((0..100).all(|_| generate_jitter(7) <= 7));
+    }
+}
@@ -0,0 +1,532 @@
+//! The async runtime the span exporter runs on.
+//!
+//! Cap'n Proto RPC is not `Send`, so the exporter performs its exports in a
+//! task on a local, single-threaded executor. By default that task gets a
+//! thread of its own; [crate::SpanExporterBuilder::build_local] instead hands
+//! it to the app as an [ExporterTask] to run on an executor of its choosing.
+//!
+//! The [Runtime] trait is what the exporter needs from the async runtime:
+//! spawning local tasks, timers and connecting to the receiver. [Tokio] is
+//! used unless [crate::WithCapnpConfig::with_runtime] picks another one, e.g.
+//! [Smol]. Other runtimes can be supported by implementing [Runtime].
+//!
+//! | Feature    | Runtime   |
+//! |------------|-----------|
+//! | `rt-tokio` | [Tokio] (default) |
+//! | `rt-smol`  | [Smol]    |
+use crate::ExporterBuildError;
+use futures::future::{AbortHandle, BoxFuture, LocalBoxFuture};
+use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+pub use crate::transport::AsyncStream;
+
+/// Creates the task that performs the exports of a span exporter, on the
+/// thread that will run it.
+pub type TaskFactory = Box<dyn FnOnce() -> LocalBoxFuture<'static, ()> + Send>;
+
+/// The async runtime services the span exporter uses.
+///
+/// Everything except [Runtime::delay] is only called from within the
+/// exporter's task.
+pub trait Runtime: fmt::Debug + Send + Sync + 'static {
+    /// Run the task created by `task` to completion on a new thread called
+    /// `name`, wrapped in [Runtime::run_local].
+    fn spawn_thread(
+        &self,
+        name: &str,
+        task: TaskFactory,
+    ) -> Result<std::thread::JoinHandle<()>, ExporterBuildError>;
+
+    /// Wrap the exporter's task so that [Runtime::spawn_local] can be called
+    /// while it runs. The default returns `task` as it is.
+    fn run_local(&self, task: LocalBoxFuture<'static, ()>) -> LocalBoxFuture<'static, ()> {
+        task
+    }
+
+    /// Run `future` on the local executor that runs the exporter's task,
+    /// e.g. the RPC system of a connection.
+    fn spawn_local(&self, future: LocalBoxFuture<'static, ()>);
+
+    /// Complete after `duration`.
+    ///
+    /// Unlike the other methods, this is also called from the threads calling
+    /// [crate::SpanExporter]'s `export`, which may not run this runtime.
+    fn delay(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Run the blocking `work`, e.g. the file I/O of the persistent queue,
+    /// off the executor that runs the exporter's task, which may be the app's
+    /// own. The default runs `work` in place.
+    fn spawn_blocking(&self, work: Box<dyn FnOnce() + Send>) -> BoxFuture<'static, ()> {
+        work();
+        Box::pin(std::future::ready(()))
+    }
+
+    /// Open a TCP stream to `addr`, disabling Nagle's algorithm if `nodelay`.
+    fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        nodelay: bool,
+    ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>>;
+
+    /// Open a Unix domain socket stream to `path`.
+    #[cfg(unix)]
+    fn connect_unix(
+        &self,
+        path: PathBuf,
+    ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>>;
+}
+
+/// The runtime used when the builder does not set one: [Tokio] if the
+/// `rt-tokio` feature is enabled, else [Smol] if `rt-smol` is.
+pub(crate) fn default_runtime() -> Option<Arc<dyn Runtime>> {
+    #[cfg(feature = "rt-tokio")]
+    return Some(Arc::new(Tokio::new()));
+    #[cfg(all(not(feature = "rt-tokio"), feature = "rt-smol"))]
+    return Some(Arc::new(Smol));
+    #[cfg(not(any(feature = "rt-tokio", feature = "rt-smol")))]
+    None
+}
+
+/// Drives the exports of a [crate::SpanExporter] built with
+/// [crate::SpanExporterBuilder::build_local].
+///
+/// Run it on a local executor of the app, e.g. with
+/// `tokio::task::LocalSet::spawn_local` for [Tokio] or
+/// `smol::LocalExecutor::spawn` for [Smol]. It completes once the exporter has
+/// been shut down or every clone of it has been dropped.
+///
+/// `force_flush` and `shutdown` of the exporter block the calling thread until
+/// this task has handled them, so call them from a different thread than the
+/// one running the task.
+#[must_use = "the exporter does not export anything unless its task runs"]
+pub struct ExporterTask(LocalBoxFuture<'static, ()>);
+
+impl ExporterTask {
+    pub(crate) fn new(task: LocalBoxFuture<'static, ()>) -> Self {
+        Self(task)
+    }
+}
+
+impl fmt::Debug for ExporterTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExporterTask")
+    }
+}
+
+impl Future for ExporterTask {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// A task started with [Runtime::spawn_local] that is cancelled when dropped,
+/// so it does not outlive the exporter's task.
+pub(crate) struct LocalTask {
+    abort: AbortHandle,
+    finished: Rc<Cell<bool>>,
+}
+
+impl LocalTask {
+    /// Spawn `future` with telemetry suppressed, like the exporter's task.
+    pub(crate) fn spawn<F>(runtime: &dyn Runtime, future: F) -> Self
+    where
+        F: Future + 'static,
+    {
+        let future = opentelemetry::context::FutureExt::with_context(
+            future,
+            opentelemetry::Context::current().with_telemetry_suppressed(),
+        );
+        let (future, abort) = futures::future::abortable(future);
+        let finished = Rc::new(Cell::new(false));
+        let done = finished.clone();
+        runtime.spawn_local(Box::pin(async move {
+            let _ = future.await;
+            done.set(true);
+        }));
+        Self { abort, finished }
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished.get() || self.abort.is_aborted()
+    }
+
+    pub(crate) fn abort(&self) {
+        self.abort.abort();
+    }
+}
+
+impl Drop for LocalTask {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+/// Run the blocking `work` with [Runtime::spawn_blocking] and return its result.
+pub(crate) async fn unblock<T, F>(runtime: &dyn Runtime, work: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    let (done, result) = futures::channel::oneshot::channel();
+    runtime
+        .spawn_blocking(Box::new(move || {
+            let _ = done.send(work());
+        }))
+        .await;
+    result.await.unwrap_or_else(|_| {
+        Err(io::Error::other(
+            "blocking work stopped without a result".to_string(),
+        ))
+    })
+}
+
+/// Run `future` for at most `duration`; `None` if it did not complete in time.
+pub(crate) async fn timeout<F: Future>(
+    runtime: &dyn Runtime,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    let future = std::pin::pin!(future);
+    match futures::future::select(future, runtime.delay(duration)).await {
+        futures::future::Either::Left((output, _)) => Some(output),
+        futures::future::Either::Right(_) => None,
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+pub use self::tokio_runtime::Tokio;
+
+#[cfg(feature = "rt-tokio")]
+mod tokio_runtime {
+    use super::*;
+    use std::sync::OnceLock;
+    use tokio::runtime::Handle;
+
+    /// Runs the exporter on tokio.
+    ///
+    /// The exporter's own thread runs a current-thread runtime with a
+    /// `LocalSet`. An [ExporterTask] must run inside a `LocalSet` as well.
+    #[derive(Debug, Clone, Default)]
+    pub struct Tokio {
+        // runs the timers of callers outside any tokio runtime
+        handle: Arc<OnceLock<Handle>>,
+    }
+
+    impl Tokio {
+        /// Run the timers of exports called outside of a tokio runtime on the
+        /// runtime of the exporter's own thread.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Run the timers of exports called outside of a tokio runtime on
+        /// `handle`. Defaults to the runtime of the exporter's own thread, so
+        /// this is only needed with [crate::SpanExporterBuilder::build_local].
+        pub fn with_handle(handle: Handle) -> Self {
+            Self {
+                handle: Arc::new(OnceLock::from(handle)),
+            }
+        }
+    }
+
+    impl Runtime for Tokio {
+        fn spawn_thread(
+            &self,
+            name: &str,
+            task: TaskFactory,
+        ) -> Result<std::thread::JoinHandle<()>, ExporterBuildError> {
+            // built here so a failure is reported by the builder
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(ExporterBuildError::RuntimeCreationFailed)?;
+            let _ = self.handle.set(rt.handle().clone());
+            std::thread::Builder::new()
+                .name(name.to_string())
+                .spawn(move || {
+                    tokio::task::LocalSet::new().block_on(&rt, task());
+                })
+                .map_err(ExporterBuildError::ThreadSpawnFailed)
+        }
+
+        fn spawn_local(&self, future: LocalBoxFuture<'static, ()>) {
+            tokio::task::spawn_local(future);
+        }
+
+        fn delay(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            if Handle::try_current().is_ok() {
+                return Box::pin(tokio::time::sleep(duration));
+            }
+            // with no runtime of the exporter's own yet, e.g. before the
+            // exporter thread has started, the timer runs on a shared one
+            match self.handle.get().or_else(|| shared_timer_handle()) {
+                Some(handle) => {
+                    // the sleep is created on the runtime, as it needs its timer
+                    let timer = handle.spawn(async move { tokio::time::sleep(duration).await });
+                    let cancel = timer.abort_handle();
+                    Box::pin(async move {
+                        // stops the timer if the caller gives up waiting first
+                        let _cancel = AbortOnDrop(cancel);
+                        let _ = timer.await;
+                    })
+                }
+                None => Box::pin(std::future::ready(())),
+            }
+        }
+
+        fn spawn_blocking(&self, work: Box<dyn FnOnce() + Send>) -> BoxFuture<'static, ()> {
+            match Handle::try_current() {
+                Ok(handle) => {
+                    let task = handle.spawn_blocking(work);
+                    Box::pin(async move {
+                        let _ = task.await;
+                    })
+                }
+                // the exporter's task runs inside a runtime, so this is only
+                // a fallback
+                Err(_) => {
+                    work();
+                    Box::pin(std::future::ready(()))
+                }
+            }
+        }
+
+        fn connect_tcp(
+            &self,
+            addr: SocketAddr,
+            nodelay: bool,
+        ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>> {
+            Box::pin(async move {
+                let stream = tokio::net::TcpStream::connect(addr).await?;
+                stream.set_nodelay(nodelay)?;
+                Ok(Box::new(stream) as Box<dyn AsyncStream>)
+            })
+        }
+
+        #[cfg(unix)]
+        fn connect_unix(
+            &self,
+            path: PathBuf,
+        ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>> {
+            Box::pin(async move {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                Ok(Box::new(stream) as Box<dyn AsyncStream>)
+            })
+        }
+    }
+
+    /// A runtime on a thread of its own that runs the timers of every [Tokio]
+    /// without a runtime to run them on, started on first use.
+    ///
+    /// One thread serves all of them, and a timer is cancelled as soon as its
+    /// caller stops waiting, so callers never pile up sleeping threads. `None`
+    /// if the thread could not be started, in which case delays end at once.
+    fn shared_timer_handle() -> Option<&'static Handle> {
+        static TIMERS: OnceLock<Option<Handle>> = OnceLock::new();
+        TIMERS
+            .get_or_init(|| {
+                let started = tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .and_then(|rt| {
+                        let handle = rt.handle().clone();
+                        std::thread::Builder::new()
+                            .name("capnp-exporter-timers".to_string())
+                            .spawn(move || rt.block_on(std::future::pending::<()>()))?;
+                        Ok(handle)
+                    });
+                match started {
+                    Ok(handle) => Some(handle),
+                    Err(e) => {
+                        let error = e.to_string();
+                        opentelemetry::otel_warn!(
+                            name: "CapnpSpanExporter.TimerThreadFailed",
+                            error = error.as_str(),
+                        );
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+
+    struct AbortOnDrop(tokio::task::AbortHandle);
+
+    impl Drop for AbortOnDrop {
+        fn drop(&mut self) {
+            self.0.abort();
+        }
+    }
+}
+
+#[cfg(feature = "rt-smol")]
+pub use self::smol_runtime::Smol;
+
+#[cfg(feature = "rt-smol")]
+mod smol_runtime {
+    use super::*;
+    use std::cell::RefCell;
+    use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+    thread_local! {
+        // the executor of the exporter task being polled on this thread
+        static LOCAL_EXECUTOR: RefCell<Option<Rc<smol::LocalExecutor<'static>>>> =
+            const { RefCell::new(None) };
+    }
+
+    /// Runs the exporter on smol.
+    ///
+    /// The exporter's task brings its own `smol::LocalExecutor` for the tasks
+    /// it spawns, so an [ExporterTask] can run on any executor.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Smol;
+
+    impl Runtime for Smol {
+        fn spawn_thread(
+            &self,
+            name: &str,
+            task: TaskFactory,
+        ) -> Result<std::thread::JoinHandle<()>, ExporterBuildError> {
+            let runtime = *self;
+            std::thread::Builder::new()
+                .name(name.to_string())
+                .spawn(move || smol::block_on(runtime.run_local(task())))
+                .map_err(ExporterBuildError::ThreadSpawnFailed)
+        }
+
+        fn run_local(&self, task: LocalBoxFuture<'static, ()>) -> LocalBoxFuture<'static, ()> {
+            let executor = Rc::new(smol::LocalExecutor::new());
+            let run = {
+                let executor = executor.clone();
+                Box::pin(async move { executor.run(task).await })
+            };
+            Box::pin(WithLocalExecutor { executor, run })
+        }
+
+        fn spawn_local(&self, future: LocalBoxFuture<'static, ()>) {
+            LOCAL_EXECUTOR.with(|executor| {
+                executor
+                    .borrow()
+                    .as_ref()
+                    .expect("spawn_local is called from within the exporter task")
+                    .spawn(future)
+                    .detach();
+            });
+        }
+
+        fn delay(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            Box::pin(async move {
+                smol::Timer::after(duration).await;
+            })
+        }
+
+        fn spawn_blocking(&self, work: Box<dyn FnOnce() + Send>) -> BoxFuture<'static, ()> {
+            Box::pin(smol::unblock(work))
+        }
+
+        fn connect_tcp(
+            &self,
+            addr: SocketAddr,
+            nodelay: bool,
+        ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>> {
+            Box::pin(async move {
+                let stream = smol::net::TcpStream::connect(addr).await?;
+                stream.set_nodelay(nodelay)?;
+                Ok(Box::new(stream.compat()) as Box<dyn AsyncStream>)
+            })
+        }
+
+        #[cfg(unix)]
+        fn connect_unix(
+            &self,
+            path: PathBuf,
+        ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>> {
+            Box::pin(async move {
+                let stream = smol::net::unix::UnixStream::connect(path).await?;
+                Ok(Box::new(stream.compat()) as Box<dyn AsyncStream>)
+            })
+        }
+    }
+
+    /// Makes `executor` the target of [Smol::spawn_local] while `run` is polled.
+    struct WithLocalExecutor {
+        executor: Rc<smol::LocalExecutor<'static>>,
+        run: LocalBoxFuture<'static, ()>,
+    }
+
+    impl Future for WithLocalExecutor {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let previous =
+                LOCAL_EXECUTOR.with(|executor| executor.replace(Some(self.executor.clone())));
+            let poll = self.run.as_mut().poll(cx);
+            LOCAL_EXECUTOR.with(|executor| executor.replace(previous));
+            poll
+        }
+    }
+}
+
+/// A [Runtime] for unit tests whose timers complete at once and are recorded
+/// instead of waited on.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct RecordingRuntime {
+    pub(crate) delays: std::sync::Mutex<Vec<Duration>>,
+}
+
+#[cfg(test)]
+impl RecordingRuntime {
+    pub(crate) fn delays(&self) -> Vec<Duration> {
+        self.delays.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Runtime for RecordingRuntime {
+    fn spawn_thread(
+        &self,
+        _name: &str,
+        _task: TaskFactory,
+    ) -> Result<std::thread::JoinHandle<()>, ExporterBuildError> {
+        unimplemented!("RecordingRuntime only runs timers")
+    }
+
+    fn spawn_local(&self, _future: LocalBoxFuture<'static, ()>) {
+        unimplemented!("RecordingRuntime only runs timers")
+    }
+
+    fn delay(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.delays.lock().unwrap().push(duration);
+        Box::pin(futures::future::ready(()))
+    }
+
+    fn connect_tcp(
+        &self,
+        _addr: SocketAddr,
+        _nodelay: bool,
+    ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>> {
+        unimplemented!("RecordingRuntime only runs timers")
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(
+        &self,
+        _path: PathBuf,
+    ) -> LocalBoxFuture<'static, io::Result<Box<dyn AsyncStream>>> {
+        unimplemented!("RecordingRuntime only runs timers")
+    }
+}
@@ -3,19 +3,86 @@
 //! Defines a [SpanExporter] to send trace data via an extended
 //! OpenTelemetry Protocol using Cap'n Proto.
 
-use crate::exporter::capnp::trace::CapnpTracesClient;
+use crate::exporter::capnp::trace::{CapnpBlockingTracesClient, CapnpTracesClient, Execution};
+use crate::runtime::ExporterTask;
 use crate::{
     exporter::capnp::{CapnpExporterBuilder, HasCapnpConfig},
     CapnpExporterBuilderSet,
 };
-use crate::{exporter::HasExportConfig, ExporterBuildError, NoExporterBuilderSet};
+use crate::{
+    exporter::HasExportConfig, ExporterBuildError, NoExporterBuilderSet, PersistentQueueConfig,
+};
 use opentelemetry_sdk::error::OTelSdkResult;
 use opentelemetry_sdk::trace::SpanData;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub const OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT: &str = "OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT";
-/// Max waiting time for the backend to process each spans batch, defaults to 10s.
+/// Max waiting time in milliseconds for the backend to process each spans batch, defaults to 10s.
+/// Takes precedence over `OTEL_EXPORTER_CAPNP_TIMEOUT`.
 pub const OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT: &str = "OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT";
+/// Max waiting time in milliseconds for a single attempt to connect to the
+/// span receiver, defaults to 30s. Takes precedence over
+/// `OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT";
+/// Count of span batches waiting for the exporter thread before the
+/// backpressure policy applies. Takes precedence over
+/// `OTEL_EXPORTER_CAPNP_QUEUE_CAPACITY`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_QUEUE_CAPACITY: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_QUEUE_CAPACITY";
+/// What a span export does when the queue to the exporter thread is full, one
+/// of `block`, `drop_newest` or `drop_oldest`. Takes precedence over
+/// `OTEL_EXPORTER_CAPNP_BACKPRESSURE_POLICY`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY";
+/// Max waiting time in milliseconds for room in the queue to the exporter
+/// thread under the `block` policy. Takes precedence over
+/// `OTEL_EXPORTER_CAPNP_BACKPRESSURE_TIMEOUT`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_TIMEOUT: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_TIMEOUT";
+/// Max count of retries of a failed span export request. Takes precedence over
+/// `OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_RETRIES: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_RETRIES";
+/// Delay in milliseconds before the first retry of a span export request.
+/// Takes precedence over `OTEL_EXPORTER_CAPNP_RETRY_INITIAL_DELAY`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_RETRY_INITIAL_DELAY: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_RETRY_INITIAL_DELAY";
+/// Max delay in milliseconds between two retries of a span export request.
+/// Takes precedence over `OTEL_EXPORTER_CAPNP_RETRY_MAX_DELAY`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_DELAY: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_DELAY";
+/// Max random jitter in milliseconds added to the retry delay of span export
+/// requests. Takes precedence over `OTEL_EXPORTER_CAPNP_RETRY_JITTER`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_RETRY_JITTER: &str = "OTEL_EXPORTER_CAPNP_TRACES_RETRY_JITTER";
+/// Max count of 8-byte words read from a single message of the span receiver.
+/// Takes precedence over `OTEL_EXPORTER_CAPNP_TRAVERSAL_LIMIT`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_TRAVERSAL_LIMIT: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_TRAVERSAL_LIMIT";
+/// Max nesting depth of pointers in a message of the span receiver. Takes
+/// precedence over `OTEL_EXPORTER_CAPNP_NESTING_LIMIT`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_NESTING_LIMIT: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_NESTING_LIMIT";
+/// `true` or `false` to enable or disable Nagle's algorithm on TCP connections
+/// to the span receiver. Takes precedence over `OTEL_EXPORTER_CAPNP_TCP_NODELAY`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_TCP_NODELAY: &str = "OTEL_EXPORTER_CAPNP_TRACES_TCP_NODELAY";
+/// Compression of the Cap'n Proto message stream for traces, one of `packed`,
+/// `zstd` or `lz4`. Defaults to no compression.
+pub const OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION: &str = "OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION";
+/// Metadata sent with every span export request, as `key1=value1,key2=value2`
+/// with percent-encoded values. Takes precedence over `OTEL_EXPORTER_CAPNP_HEADERS`.
+pub const OTEL_EXPORTER_CAPNP_TRACES_HEADERS: &str = "OTEL_EXPORTER_CAPNP_TRACES_HEADERS";
+/// Path to the PEM file with the certificate authorities trusted to verify the
+/// collector's certificate for traces. Setting it enables TLS (requires the `tls` feature).
+pub const OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE: &str = "OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE";
+/// Path to the PEM file with the client certificate chain used for mutual TLS for traces.
+pub const OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE: &str =
+    "OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE";
+/// Path to the PEM file with the client private key used for mutual TLS for traces.
+pub const OTEL_EXPORTER_CAPNP_TRACES_CLIENT_KEY: &str = "OTEL_EXPORTER_CAPNP_TRACES_CLIENT_KEY";
 
 /// Target to which the exporter is going to send spans, defaults to https://localhost:4317/v1/traces.
 /// Learn about the relationship between this constant and default/metrics/logs at
@@ -41,12 +108,165 @@ impl SpanExporterBuilder<NoExporterBuilderSet> {
 }
 
 impl SpanExporterBuilder<CapnpExporterBuilderSet> {
-    /// Build the [SpanExporter] with the Cap'n Proto transport.
+    /// Build the [SpanExporter] with the Cap'n Proto transport. Its exports
+    /// run on a thread of its own.
     pub fn build(self) -> Result<SpanExporter, ExporterBuildError> {
-        let span_exporter = self.client.0.build_span_exporter()?;
+        let (span_exporter, _) = self.client.0.build_span_exporter(Execution::Thread)?;
         // opentelemetry::otel_debug!(name: "SpanExporterBuilt");
         Ok(span_exporter)
     }
+
+    /// Build the [SpanExporter] without a thread of its own. Its exports are
+    /// performed by the returned [ExporterTask], which the app runs on a local
+    /// executor, e.g. a `tokio::task::LocalSet` or a `smol::LocalExecutor`
+    /// matching the [crate::runtime::Runtime] of the exporter.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "rt-tokio")]
+    /// # async fn run() -> Result<(), opentelemetry_otlp_capnp::ExporterBuildError> {
+    /// use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+    ///
+    /// let (exporter, task) = SpanExporter::builder()
+    ///     .with_capnp()
+    ///     .with_endpoint("127.0.0.1:4317")
+    ///     .build_local()?;
+    /// let local = tokio::task::LocalSet::new();
+    /// local.spawn_local(task);
+    /// // hand `exporter` to a span processor and run the app inside `local`
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn build_local(self) -> Result<(SpanExporter, ExporterTask), ExporterBuildError> {
+        let (span_exporter, task) = self.client.0.build_span_exporter(Execution::Local)?;
+        Ok((
+            span_exporter,
+            task.expect("a local exporter comes with its task"),
+        ))
+    }
+
+    /// Build a [SpanExporter] whose `export` encodes the batch and blocks the
+    /// calling thread until the receiver has answered or the export timeout
+    /// has passed. Meant for a `SimpleSpanProcessor` in programs without an
+    /// async runtime, e.g. command line tools.
+    ///
+    /// The RPCs are made on a thread of the exporter's own, over one
+    /// connection kept for every export, and the caller stops waiting for it
+    /// once the export timeout has passed. Batches are never held past that
+    /// timeout, so the queue, backpressure, in-flight and persistent queue
+    /// settings do not apply.
+    ///
+    /// ```no_run
+    /// use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+    /// use opentelemetry_sdk::trace::SdkTracerProvider;
+    ///
+    /// let exporter = SpanExporter::builder()
+    ///     .with_capnp()
+    ///     .with_endpoint("127.0.0.1:4317")
+    ///     .build_blocking()?;
+    /// let provider = SdkTracerProvider::builder()
+    ///     .with_simple_exporter(exporter)
+    ///     .build();
+    /// # Ok::<(), opentelemetry_otlp_capnp::ExporterBuildError>(())
+    /// ```
+    pub fn build_blocking(self) -> Result<SpanExporter, ExporterBuildError> {
+        self.client.0.build_blocking_span_exporter()
+    }
+
+    /// Write span batches to an on-disk queue before exporting them.
+    ///
+    /// Batches are replayed in order once the receiver is reachable and only
+    /// deleted after it has acknowledged them, so they survive receiver outages
+    /// and process restarts. With the queue enabled, [SpanExporter] reports a
+    /// batch as exported as soon as it is persisted.
+    pub fn with_persistent_queue(mut self, config: PersistentQueueConfig) -> Self {
+        self.client.0.capnp_config.persistent_queue = Some(config);
+        self
+    }
+
+    /// Export to several receivers, e.g. a pool of collectors, spreading the
+    /// span batches over them with the [crate::LoadBalancingStrategy].
+    ///
+    /// Equivalent to a comma-separated list passed to
+    /// [crate::WithExportConfig::with_endpoint], so none of the endpoints may
+    /// contain a comma.
+    pub fn with_endpoints<I, T>(mut self, endpoints: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let endpoints: Vec<String> = endpoints.into_iter().map(Into::into).collect();
+        self.client.0.exporter_config.endpoint = Some(endpoints.join(","));
+        self
+    }
+
+    /// Set how span batches are spread over several receivers. Defaults to
+    /// [crate::LoadBalancingStrategy::Failover].
+    pub fn with_load_balancing_strategy(mut self, strategy: crate::LoadBalancingStrategy) -> Self {
+        self.client.0.capnp_config.load_balancing_strategy = strategy;
+        self
+    }
+
+    /// Allow up to `max` export requests to await the receiver's response at
+    /// the same time, so concurrent exports are not limited to one RPC
+    /// round-trip each. Defaults to 1; 0 is treated as 1.
+    ///
+    /// The window only fills up when [SpanExporter::export] is called again
+    /// before the previous call has returned, e.g. from several tasks holding
+    /// clones of the exporter. Further exports wait until a request in the
+    /// window has been answered.
+    pub fn with_max_in_flight_requests(mut self, max: usize) -> Self {
+        self.client.0.capnp_config.max_in_flight_requests = Some(max);
+        self
+    }
+
+    /// Encode every span batch into a Cap'n Proto export request inside
+    /// [SpanExporter::export], straight from the borrowed spans, and hand only
+    /// the finished message to the exporter thread. Defaults to `false`, which
+    /// hands the spans over and lets the exporter thread encode them.
+    ///
+    /// This skips copying the spans into per-scope groups and moves the
+    /// encoding work onto the threads that export, e.g. the span processor's.
+    pub fn with_encoding_on_caller(mut self, enabled: bool) -> Self {
+        self.client.0.capnp_config.encode_on_caller = enabled;
+        self
+    }
+
+    /// Send `headers` as [crate::Metadata] with every export request, e.g. a
+    /// tenant ID or an API key.
+    ///
+    /// Merged with the `OTEL_EXPORTER_CAPNP_TRACES_HEADERS` environment
+    /// variable; on conflicting keys these headers win.
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.client.0.capnp_config.headers = Some(headers);
+        self
+    }
+
+    /// Run `interceptor` on the [crate::Metadata] of every export request
+    /// right before it is sent, after the headers have been added.
+    pub fn with_interceptor<I: crate::Interceptor>(mut self, interceptor: I) -> Self {
+        self.client.0.capnp_config.interceptor = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Compress the Cap'n Proto message stream to the receiver, which must be
+    /// configured with the same [crate::Compression].
+    ///
+    /// Overrides the `OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION` environment variable.
+    pub fn with_compression(mut self, compression: crate::Compression) -> Self {
+        self.client.0.capnp_config.compression = Some(compression);
+        self
+    }
+
+    /// Connect to the receiver over TLS.
+    ///
+    /// Overrides the `OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE`,
+    /// `OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE` and
+    /// `OTEL_EXPORTER_CAPNP_TRACES_CLIENT_KEY` environment variables.
+    #[cfg(feature = "tls")]
+    pub fn with_tls_config(mut self, tls_config: crate::ClientTlsConfig) -> Self {
+        self.client.0.capnp_config.tls_config = Some(tls_config);
+        self
+    }
 }
 
 impl HasExportConfig for SpanExporterBuilder<CapnpExporterBuilderSet> {
@@ -63,8 +283,9 @@ impl HasCapnpConfig for SpanExporterBuilder<CapnpExporterBuilderSet> {
 
 /// CAPNP exporter that sends tracing data
 ///
-/// Forwards SpanData over a tokio channel to the thread dedicated to
-/// a Cap'n Proto client for further export.
+/// Forwards SpanData over a queue to the task of a Cap'n Proto client for
+/// further export, which runs on a thread of its own or, with
+/// [SpanExporterBuilder::build_local], on an executor of the app.
 ///
 /// The internals do not parallel opentelemetry-otlp using Prost and Tonic.
 /// The change is required for Cap'n Proto because the Cap'n Proto SpanExporter
@@ -78,6 +299,7 @@ pub struct SpanExporter {
 #[derive(Debug, Clone)]
 enum SupportedTransportClient {
     Capnp(CapnpTracesClient),
+    CapnpBlocking(CapnpBlockingTracesClient),
 }
 
 impl SpanExporter {
@@ -91,18 +313,56 @@ impl SpanExporter {
             client: SupportedTransportClient::Capnp(client),
         }
     }
+
+    pub(crate) fn from_capnp_blocking(client: CapnpBlockingTracesClient) -> Self {
+        SpanExporter {
+            client: SupportedTransportClient::CapnpBlocking(client),
+        }
+    }
+
+    /// The exporter's own metrics as recorded so far, e.g. to check in tests
+    /// how many spans were exported. Also available after shutdown.
+    pub fn metrics_snapshot(&self) -> crate::ExporterMetricsSnapshot {
+        match &self.client {
+            SupportedTransportClient::Capnp(client) => client.metrics_snapshot(),
+            SupportedTransportClient::CapnpBlocking(client) => client.metrics_snapshot(),
+        }
+    }
 }
 
 impl opentelemetry_sdk::trace::SpanExporter for SpanExporter {
     async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
         match &self.client {
             SupportedTransportClient::Capnp(client) => client.export(batch).await,
+            SupportedTransportClient::CapnpBlocking(client) => client.export(batch),
+        }
+    }
+
+    fn shutdown_with_timeout(&mut self, timeout: Duration) -> OTelSdkResult {
+        match &mut self.client {
+            SupportedTransportClient::Capnp(client) => client.shutdown_with_timeout(timeout),
+            SupportedTransportClient::CapnpBlocking(client) => client.shutdown(),
+        }
+    }
+
+    fn shutdown(&mut self) -> OTelSdkResult {
+        match &mut self.client {
+            SupportedTransportClient::Capnp(client) => client.shutdown(),
+            SupportedTransportClient::CapnpBlocking(client) => client.shutdown(),
+        }
+    }
+
+    fn force_flush(&mut self) -> OTelSdkResult {
+        match &mut self.client {
+            SupportedTransportClient::Capnp(client) => client.force_flush(),
+            SupportedTransportClient::CapnpBlocking(client) => client.force_flush(),
         }
     }
 
     fn set_resource(&mut self, resource: &opentelemetry_sdk::Resource) {
         match &mut self.client {
             SupportedTransportClient::Capnp(client) => client.set_resource(resource),
+            SupportedTransportClient::CapnpBlocking(client) => client.set_resource(resource),
         }
     }
 }
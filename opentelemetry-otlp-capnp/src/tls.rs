@@ -0,0 +1,289 @@
+//! TLS for the stream underneath the Cap'n Proto twoparty VatNetwork.
+//!
+//! The stream is wrapped before it reaches the RPC layer, so nothing above
+//! the transport knows whether it runs over plaintext or TLS.
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+#[cfg(feature = "rt-tokio")]
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::exporter::{
+    ExporterBuildError, OTEL_EXPORTER_CAPNP_CERTIFICATE, OTEL_EXPORTER_CAPNP_CLIENT_CERTIFICATE,
+    OTEL_EXPORTER_CAPNP_CLIENT_KEY,
+};
+use crate::span::{
+    OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE, OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE,
+    OTEL_EXPORTER_CAPNP_TRACES_CLIENT_KEY,
+};
+use crate::transport::AsyncStream;
+
+/// TLS settings of the Cap'n Proto span exporter.
+///
+/// The receiver's certificate is verified against the PEM bundle given to
+/// [ClientTlsConfig::new]. Adding a client identity enables mutual TLS.
+///
+/// ```no_run
+/// use opentelemetry_otlp_capnp::{ClientTlsConfig, SpanExporter};
+///
+/// let exporter = SpanExporter::builder()
+///     .with_capnp()
+///     .with_tls_config(
+///         ClientTlsConfig::new("/etc/otel/ca.pem")
+///             .with_client_identity("/etc/otel/client.pem", "/etc/otel/client-key.pem")
+///             .with_domain_name("collector.example.com"),
+///     )
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientTlsConfig {
+    ca_certificate: PathBuf,
+    client_identity: Option<(PathBuf, PathBuf)>,
+    domain_name: Option<String>,
+}
+
+impl ClientTlsConfig {
+    /// Trust the certificate authorities in the PEM file at `ca_certificate`.
+    pub fn new<P: Into<PathBuf>>(ca_certificate: P) -> Self {
+        Self {
+            ca_certificate: ca_certificate.into(),
+            client_identity: None,
+            domain_name: None,
+        }
+    }
+
+    /// Authenticate to the receiver with the PEM encoded certificate chain and
+    /// private key, i.e. use mutual TLS.
+    pub fn with_client_identity<C: Into<PathBuf>, K: Into<PathBuf>>(
+        mut self,
+        certificate: C,
+        key: K,
+    ) -> Self {
+        self.client_identity = Some((certificate.into(), key.into()));
+        self
+    }
+
+    /// Set the name the receiver's certificate is verified against. Defaults to
+    /// the host of the endpoint; required for `unix://` endpoints.
+    pub fn with_domain_name<T: Into<String>>(mut self, domain_name: T) -> Self {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+
+    /// Build the config from `OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE` and its
+    /// siblings, falling back to the signal-independent variables. TLS is only
+    /// enabled from the environment if a certificate is set.
+    ///
+    /// A client certificate without a client key, or the other way round,
+    /// fails with [ExporterBuildError::InvalidEnvValue] rather than silently
+    /// connecting without mutual TLS.
+    pub(crate) fn from_env() -> Result<Option<Self>, ExporterBuildError> {
+        // the variable that is set and not empty, with its value
+        let var = |traces_var: &'static str, generic_var: &'static str| {
+            [traces_var, generic_var].into_iter().find_map(|name| {
+                env::var(name)
+                    .ok()
+                    .filter(|value| !value.is_empty())
+                    .map(|value| (name, value))
+            })
+        };
+        let Some((_, certificate)) = var(
+            OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE,
+            OTEL_EXPORTER_CAPNP_CERTIFICATE,
+        ) else {
+            return Ok(None);
+        };
+        let config = Self::new(certificate);
+        let client_certificate = var(
+            OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE,
+            OTEL_EXPORTER_CAPNP_CLIENT_CERTIFICATE,
+        );
+        let client_key = var(
+            OTEL_EXPORTER_CAPNP_TRACES_CLIENT_KEY,
+            OTEL_EXPORTER_CAPNP_CLIENT_KEY,
+        );
+        let half_set = |(name, value): (&str, String), missing: &str| {
+            Err(ExporterBuildError::InvalidEnvValue {
+                name: name.to_string(),
+                value,
+                reason: format!("mutual TLS also needs the client {missing}"),
+            })
+        };
+        match (client_certificate, client_key) {
+            (Some((_, certificate)), Some((_, key))) => {
+                Ok(Some(config.with_client_identity(certificate, key)))
+            }
+            (Some(certificate), None) => half_set(certificate, "key"),
+            (None, Some(key)) => half_set(key, "certificate"),
+            (None, None) => Ok(Some(config)),
+        }
+    }
+
+    /// Load the certificates and keys for connecting to `endpoint`.
+    pub(crate) fn connector(&self, endpoint: &str) -> io::Result<TlsConnector> {
+        let domain_name = self
+            .domain_name
+            .clone()
+            .or_else(|| endpoint_host(endpoint))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("TLS for endpoint '{endpoint}' needs a domain name"),
+                )
+            })?;
+        let server_name = ServerName::try_from(domain_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let builder = rustls::ClientConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(load_root_certificates(&self.ca_certificate)?);
+        let config = match &self.client_identity {
+            Some((certificate, key)) => builder
+                .with_client_auth_cert(load_certificates(certificate)?, load_private_key(key)?)
+                .map_err(io::Error::other)?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(TlsConnector {
+            connector: tokio_rustls::TlsConnector::from(Arc::new(config)),
+            server_name,
+        })
+    }
+}
+
+/// TLS settings of the [crate::SpanReceiver].
+///
+/// Adding client certificate authorities enables mutual TLS: exporters
+/// without a certificate signed by one of them are rejected.
+#[cfg(feature = "rt-tokio")]
+#[derive(Debug, Clone)]
+pub struct ServerTlsConfig {
+    certificate: PathBuf,
+    key: PathBuf,
+    client_ca_certificate: Option<PathBuf>,
+}
+
+#[cfg(feature = "rt-tokio")]
+impl ServerTlsConfig {
+    /// Serve the PEM encoded certificate chain and private key.
+    pub fn new<C: Into<PathBuf>, K: Into<PathBuf>>(certificate: C, key: K) -> Self {
+        Self {
+            certificate: certificate.into(),
+            key: key.into(),
+            client_ca_certificate: None,
+        }
+    }
+
+    /// Require exporters to present a certificate signed by one of the
+    /// certificate authorities in the PEM file at `client_ca_certificate`.
+    pub fn with_client_ca_certificate<P: Into<PathBuf>>(
+        mut self,
+        client_ca_certificate: P,
+    ) -> Self {
+        self.client_ca_certificate = Some(client_ca_certificate.into());
+        self
+    }
+
+    /// Load the certificates and keys for accepting connections.
+    pub(crate) fn acceptor(&self) -> io::Result<tokio_rustls::TlsAcceptor> {
+        let provider = crypto_provider();
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?;
+        let builder = match &self.client_ca_certificate {
+            Some(client_ca_certificate) => {
+                let roots = Arc::new(load_root_certificates(client_ca_certificate)?);
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider)
+                    .build()
+                    .map_err(io::Error::other)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(
+                load_certificates(&self.certificate)?,
+                load_private_key(&self.key)?,
+            )
+            .map_err(io::Error::other)?;
+        Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Wraps the exporter's streams in TLS for a fixed receiver name.
+#[derive(Clone)]
+pub(crate) struct TlsConnector {
+    connector: tokio_rustls::TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    pub(crate) async fn connect<S: AsyncStream>(
+        &self,
+        stream: S,
+    ) -> io::Result<tokio_rustls::client::TlsStream<S>> {
+        self.connector
+            .connect(self.server_name.clone(), stream)
+            .await
+    }
+}
+
+/// Pin the provider instead of relying on a process-wide default, which is
+/// ambiguous when several rustls backends are compiled in.
+fn crypto_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// The host of a `host:port` endpoint; `unix://` endpoints have none.
+fn endpoint_host(endpoint: &str) -> Option<String> {
+    if endpoint.starts_with("unix://") {
+        return None;
+    }
+    let (host, _port) = endpoint.rsplit_once(':')?;
+    Some(
+        host.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+    )
+}
+
+fn load_certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(path, e))?;
+    if certificates.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} contains no certificates", path.display()),
+        ));
+    }
+    Ok(certificates)
+}
+
+fn load_root_certificates(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots.add(certificate).map_err(io::Error::other)?;
+    }
+    Ok(roots)
+}
+
+fn load_private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| pem_error(path, e))
+}
+
+fn pem_error(path: &Path, error: rustls::pki_types::pem::Error) -> io::Error {
+    match error {
+        rustls::pki_types::pem::Error::Io(e) => {
+            io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+        }
+        e => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e:?}", path.display()),
+        ),
+    }
+}
@@ -0,0 +1,357 @@
+//! Endpoints and streams the Cap'n Proto twoparty VatNetwork runs over.
+//!
+//! Besides TCP, the exporter and [crate::SpanReceiver] can talk over a Unix
+//! domain socket, which avoids the loopback overhead when the receiver runs as
+//! a sidecar on the same host. Unix domain socket endpoints are written as
+//! `unix:///path/to.sock`. With the `tls` feature, either kind of stream can
+//! additionally be wrapped in TLS.
+//!
+//! The Cap'n Proto message stream on top can be compressed, see
+//! [crate::Compression].
+use capnp::message::ReaderOptions;
+use capnp_rpc::{rpc_twoparty_capnp, twoparty};
+use futures::io::{AsyncReadExt, BufReader, BufWriter};
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(all(unix, feature = "rt-tokio"))]
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::runtime::Runtime;
+use crate::{Compression, ExporterBuildError};
+
+#[cfg(unix)]
+const UNIX_SCHEME: &str = "unix://";
+
+/// Where the exporter sends spans to or the receiver listens on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    /// Parse `unix:///path/to.sock` as a Unix domain socket and anything else
+    /// as a TCP socket address, taking the first address a host name resolves to.
+    #[cfg(feature = "rt-tokio")]
+    pub(crate) fn parse(endpoint: &str) -> Result<Self, ExporterBuildError> {
+        Ok(Self::resolve(endpoint)?.remove(0))
+    }
+
+    /// Like [Endpoint::parse], but return every address a host name resolves to.
+    pub(crate) fn resolve(endpoint: &str) -> Result<Vec<Self>, ExporterBuildError> {
+        let invalid = |reason: String| ExporterBuildError::InvalidEndpoint {
+            endpoint: endpoint.to_string(),
+            reason,
+        };
+        #[cfg(unix)]
+        if let Some(path) = endpoint.strip_prefix(UNIX_SCHEME) {
+            if path.is_empty() {
+                return Err(invalid(
+                    "the Unix domain socket path is missing".to_string(),
+                ));
+            }
+            return Ok(vec![Endpoint::Unix(PathBuf::from(path))]);
+        }
+        if let Ok(addr) = endpoint.parse::<SocketAddr>() {
+            return Ok(vec![Endpoint::Tcp(addr)]);
+        }
+        // e.g. an OTLP/HTTP endpoint like `http://localhost:4318`
+        if endpoint.contains("://") {
+            return Err(invalid(
+                "expected host:port or unix:///path/to.sock, not a URL".to_string(),
+            ));
+        }
+        let Some((host, port)) = endpoint.rsplit_once(':') else {
+            return Err(invalid("expected host:port".to_string()));
+        };
+        if host.is_empty() {
+            return Err(invalid("the host is missing".to_string()));
+        }
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| invalid(format!("'{port}' is not a valid port")))?;
+        let unresolvable = |reason: String| ExporterBuildError::UnresolvableHost {
+            endpoint: endpoint.to_string(),
+            reason,
+        };
+        let mut endpoints = Vec::new();
+        for addr in (host, port)
+            .to_socket_addrs()
+            .map_err(|e| unresolvable(e.to_string()))?
+        {
+            if !endpoints.contains(&Endpoint::Tcp(addr)) {
+                endpoints.push(Endpoint::Tcp(addr));
+            }
+        }
+        if endpoints.is_empty() {
+            return Err(unresolvable("no addresses found".to_string()));
+        }
+        Ok(endpoints)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "{UNIX_SCHEME}{}", path.display()),
+        }
+    }
+}
+
+/// A bidirectional byte stream the twoparty VatNetwork can run over.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + 'static> AsyncStream for T {}
+
+/// The reader half of the Cap'n Proto message stream of a [VatNetwork].
+pub type MessageReader = Box<dyn futures::io::AsyncRead + Unpin>;
+
+/// The twoparty VatNetwork the exporter and receiver run Cap'n Proto RPC over.
+pub type VatNetwork = twoparty::VatNetwork<MessageReader>;
+
+/// Run the Cap'n Proto message stream of `side` over `stream`, compressed
+/// with `compression` if given.
+fn vat_network(
+    stream: Box<dyn AsyncStream>,
+    side: rpc_twoparty_capnp::Side,
+    compression: Option<Compression>,
+    reader_options: ReaderOptions,
+) -> io::Result<VatNetwork> {
+    let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+    let (reader, writer) = (BufReader::new(reader), BufWriter::new(writer));
+
+    let (reader, writer): (MessageReader, Box<dyn futures::io::AsyncWrite + Unpin>) =
+        match compression {
+            None => (Box::new(reader), Box::new(writer)),
+            Some(Compression::Packed) => (
+                Box::new(capnp_futures::serialize_packed::PackedRead::new(reader)),
+                Box::new(capnp_futures::serialize_packed::PackedWrite::new(writer)),
+            ),
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => (
+                Box::new(async_compression::futures::bufread::ZstdDecoder::new(
+                    reader,
+                )),
+                Box::new(async_compression::futures::write::ZstdEncoder::new(writer)),
+            ),
+            #[cfg(feature = "lz4")]
+            Some(Compression::Lz4) => (
+                Box::new(async_compression::futures::bufread::Lz4Decoder::new(reader)),
+                Box::new(async_compression::futures::write::Lz4Encoder::new(writer)),
+            ),
+            #[allow(unreachable_patterns)]
+            Some(compression) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("compression '{compression}' is not enabled in this build"),
+                ))
+            }
+        };
+    Ok(twoparty::VatNetwork::new(
+        reader,
+        writer,
+        side,
+        reader_options,
+    ))
+}
+
+/// Opens streams to the receiver's [Endpoint] for the exporter.
+#[derive(Clone)]
+pub(crate) struct Connector {
+    endpoint: Endpoint,
+    compression: Option<Compression>,
+    reader_options: ReaderOptions,
+    tcp_nodelay: bool,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::TlsConnector>,
+}
+
+impl Connector {
+    pub(crate) fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            compression: None,
+            reader_options: ReaderOptions::new(),
+            tcp_nodelay: true,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    pub(crate) fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub(crate) fn with_reader_options(mut self, reader_options: ReaderOptions) -> Self {
+        self.reader_options = reader_options;
+        self
+    }
+
+    /// Whether to disable Nagle's algorithm on TCP streams; ignored for Unix
+    /// domain sockets.
+    pub(crate) fn with_tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+        self.tcp_nodelay = tcp_nodelay;
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn with_tls(mut self, tls: crate::tls::TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub(crate) async fn connect(&self, runtime: &dyn Runtime) -> io::Result<Box<dyn AsyncStream>> {
+        let stream = match &self.endpoint {
+            Endpoint::Tcp(addr) => runtime.connect_tcp(*addr, self.tcp_nodelay).await?,
+            #[cfg(unix)]
+            Endpoint::Unix(path) => runtime.connect_unix(path.clone()).await?,
+        };
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return Ok(Box::new(tls.connect(stream).await?));
+        }
+        Ok(stream)
+    }
+
+    /// Run the exporter's side of the Cap'n Proto message stream over a
+    /// stream returned by [Connector::connect].
+    pub(crate) fn vat_network(&self, stream: Box<dyn AsyncStream>) -> io::Result<VatNetwork> {
+        vat_network(
+            stream,
+            rpc_twoparty_capnp::Side::Client,
+            self.compression,
+            self.reader_options,
+        )
+    }
+}
+
+impl fmt::Display for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.endpoint.fmt(f)
+    }
+}
+
+/// Finishes the handshake on streams accepted by the [crate::SpanReceiver],
+/// or by a receiver of your own that serves the Cap'n Proto trace service.
+#[cfg(feature = "rt-tokio")]
+#[derive(Clone, Default)]
+pub struct Acceptor {
+    compression: Option<Compression>,
+    #[cfg(feature = "tls")]
+    tls: Option<tokio_rustls::TlsAcceptor>,
+}
+
+#[cfg(feature = "rt-tokio")]
+impl Acceptor {
+    /// Expect the exporters to compress the message stream with `compression`.
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn with_tls(mut self, tls: tokio_rustls::TlsAcceptor) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Finish the TLS handshake on `stream` if the acceptor has a TLS config.
+    pub async fn accept(&self, stream: Box<dyn AsyncStream>) -> io::Result<Box<dyn AsyncStream>> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return Ok(Box::new(tls.accept(stream).await?));
+        }
+        Ok(stream)
+    }
+
+    /// Run the receiver's side of the Cap'n Proto message stream over a
+    /// stream returned by [Acceptor::accept].
+    ///
+    /// Fails if the crate was built without the feature the acceptor's
+    /// compression needs.
+    pub fn vat_network(&self, stream: Box<dyn AsyncStream>) -> io::Result<VatNetwork> {
+        vat_network(
+            stream,
+            rpc_twoparty_capnp::Side::Server,
+            self.compression,
+            ReaderOptions::new(),
+        )
+    }
+}
+
+/// A bound Unix domain socket that removes its socket file when dropped.
+#[cfg(all(unix, feature = "rt-tokio"))]
+pub(crate) struct UnixSocketListener {
+    listener: tokio::net::UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(all(unix, feature = "rt-tokio"))]
+impl UnixSocketListener {
+    /// Bind to `path`, replacing a socket file left behind by a previous
+    /// process, and apply `permissions` to the socket file if given.
+    ///
+    /// The socket is bound under a temporary name and only renamed to `path`
+    /// once its permissions are set, so clients never see it with the default
+    /// permissions.
+    pub(crate) fn bind(path: &Path, permissions: Option<u32>) -> io::Result<Self> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+        let temporary_path = PathBuf::from(temporary_path);
+        remove_stale_socket(path)?;
+        remove_stale_socket(&temporary_path)?;
+
+        let listener = tokio::net::UnixListener::bind(&temporary_path)?;
+        let prepared = permissions
+            .map_or(Ok(()), |mode| {
+                std::fs::set_permissions(&temporary_path, std::fs::Permissions::from_mode(mode))
+            })
+            .and_then(|_| std::fs::rename(&temporary_path, path));
+        if let Err(e) = prepared {
+            let _ = std::fs::remove_file(&temporary_path);
+            return Err(e);
+        }
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<tokio::net::UnixStream> {
+        self.listener.accept().await.map(|(stream, _)| stream)
+    }
+}
+
+/// Remove the socket file at `path` if there is one, refusing to touch
+/// anything that is not a socket.
+#[cfg(all(unix, feature = "rt-tokio"))]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(all(unix, feature = "rt-tokio"))]
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,145 @@
+use opentelemetry_otlp_capnp::{
+    BackpressurePolicy, ExporterBuildError, SpanExporter, WithCapnpConfig, WithExportConfig,
+    OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY,
+};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::{Duration, Instant};
+use utilities::capnp::fixtures::{batch, free_endpoint, ENV_LOCK};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+/// How long the receiver takes to answer every export request.
+const RESPONSE_DELAY: Duration = Duration::from_millis(400);
+/// Gap between the exports, long enough for the exporter thread to take each
+/// batch off the queue if it can.
+const EXPORT_GAP: Duration = Duration::from_millis(100);
+
+/// The outcomes of three staggered exports of 1, 2 and 3 spans through a
+/// connected exporter whose queue holds a single batch, and how long the last
+/// export took.
+///
+/// The first batch is in flight while the second waits in the queue, so the
+/// third one finds the queue full.
+async fn export_into_full_queue(
+    env: &[(&str, &str)],
+    policy: Option<BackpressurePolicy>,
+) -> ([OTelSdkResult; 3], Duration) {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(RESPONSE_DELAY)
+        .start()
+        .expect("start SpanReceiver");
+    let mut exporter = {
+        let _env = ENV_LOCK.lock().await;
+        for (var, value) in env {
+            std::env::set_var(var, value);
+        }
+        let builder = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .with_queue_capacity(1);
+        let exporter = match policy {
+            Some(policy) => builder.with_backpressure_policy(policy),
+            None => builder,
+        }
+        .build()
+        .expect("build Capnp SpanExporter");
+        for (var, _) in env {
+            std::env::remove_var(var);
+        }
+        exporter
+    };
+    exporter.export(batch(1)).await.expect("export span batch");
+    exporter.force_flush().expect("connect to SpanReceiver");
+
+    let export_after = |gaps: u32, spans: usize| {
+        let exporter = &exporter;
+        async move {
+            tokio::time::sleep(EXPORT_GAP * gaps).await;
+            let start = Instant::now();
+            let result = exporter.export(batch(spans)).await;
+            (result, start.elapsed())
+        }
+    };
+    let ((first, _), (second, _), (third, elapsed)) =
+        tokio::join!(export_after(0, 1), export_after(1, 2), export_after(2, 3));
+    ([first, second, third], elapsed)
+}
+
+fn dropped(result: &OTelSdkResult, spans: usize) -> bool {
+    matches!(result, Err(e) if e.to_string().contains(&format!("dropped {spans} spans")))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drop_newest_rejects_the_batch_that_does_not_fit() {
+    let ([first, second, third], elapsed) =
+        export_into_full_queue(&[], Some(BackpressurePolicy::DropNewest)).await;
+    assert!(first.is_ok(), "{first:?}");
+    assert!(second.is_ok(), "{second:?}");
+    assert!(dropped(&third, 3), "{third:?}");
+    assert!(elapsed < RESPONSE_DELAY, "{elapsed:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drop_oldest_makes_room_for_the_newest_batch() {
+    let ([first, second, third], _) =
+        export_into_full_queue(&[], Some(BackpressurePolicy::DropOldest)).await;
+    assert!(first.is_ok(), "{first:?}");
+    assert!(dropped(&second, 2), "{second:?}");
+    assert!(third.is_ok(), "{third:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn block_drops_the_batch_once_the_timeout_has_passed() {
+    let timeout = Duration::from_millis(100);
+    let ([first, second, third], elapsed) =
+        export_into_full_queue(&[], Some(BackpressurePolicy::Block { timeout })).await;
+    assert!(first.is_ok(), "{first:?}");
+    assert!(second.is_ok(), "{second:?}");
+    assert!(dropped(&third, 3), "{third:?}");
+    assert!(
+        elapsed >= timeout && elapsed < RESPONSE_DELAY,
+        "{elapsed:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn block_waits_for_room_by_default() {
+    let (results, _) = export_into_full_queue(&[], None).await;
+    assert!(results.iter().all(Result::is_ok), "{results:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backpressure_policy_from_the_environment() {
+    let ([_, _, third], _) = export_into_full_queue(
+        &[(
+            OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY,
+            "drop_newest",
+        )],
+        None,
+    )
+    .await;
+    assert!(dropped(&third, 3), "{third:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_backpressure_policy_fails_the_build() {
+    let result = {
+        let _env = ENV_LOCK.lock().await;
+        std::env::set_var(OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY, "drop_all");
+        let result = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(free_endpoint())
+            .build();
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY);
+        result
+    };
+    assert!(
+        matches!(
+            &result,
+            Err(ExporterBuildError::InvalidEnvValue { name, value, .. })
+                if name == OTEL_EXPORTER_CAPNP_TRACES_BACKPRESSURE_POLICY && value == "drop_all"
+        ),
+        "{result:?}"
+    );
+}
@@ -0,0 +1,174 @@
+use opentelemetry::trace::{Tracer, TracerProvider as _};
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::error::OTelSdkError;
+use opentelemetry_sdk::trace::{SdkTracerProvider, SpanExporter as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use utilities::capnp::fixtures::{batch, free_endpoint, start_receiver};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+#[test]
+fn simple_span_processor_exports_without_an_async_runtime() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter");
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .build();
+
+    let tracer = provider.tracer("cli");
+    tracer.in_span("parse arguments", |_| {});
+    tracer.in_span("run command", |_| {});
+
+    // every span was exported when it ended
+    let mut names: Vec<_> = received
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|span| span.name.clone())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["parse arguments", "run command"]);
+    provider.shutdown().expect("shut down the tracer provider");
+}
+
+#[test]
+fn blocking_export_returns_once_the_receiver_has_answered() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter");
+
+    futures::executor::block_on(exporter.export(batch(3))).expect("first export");
+    futures::executor::block_on(exporter.export(batch(2))).expect("second export");
+
+    assert_eq!(received.lock().unwrap().len(), 5);
+    assert_eq!(exporter.metrics_snapshot().spans_exported, 5);
+    exporter.shutdown().expect("shut down the exporter");
+    assert!(matches!(
+        futures::executor::block_on(exporter.export(batch(1))),
+        Err(OTelSdkError::AlreadyShutdown)
+    ));
+}
+
+#[test]
+fn blocking_exports_share_one_connection() {
+    let endpoint = free_endpoint();
+    let connections = Arc::new(AtomicU64::new(0));
+    NoOpSpanReceiver::new(&endpoint)
+        .with_connections(connections.clone())
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter");
+
+    for _ in 0..10 {
+        futures::executor::block_on(exporter.export(batch(1))).expect("export span batch");
+    }
+
+    assert_eq!(connections.load(Ordering::Relaxed), 1);
+    assert_eq!(exporter.metrics_snapshot().spans_exported, 10);
+}
+
+#[tokio::test]
+async fn blocking_export_works_within_a_tokio_runtime() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter");
+
+    exporter
+        .export(batch(2))
+        .await
+        .expect("export from within tokio");
+
+    assert_eq!(received.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn blocking_export_gives_up_at_the_deadline() {
+    // nothing listens on this endpoint
+    let endpoint = free_endpoint();
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_timeout(Duration::from_millis(300))
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter");
+
+    let started = Instant::now();
+    let result = futures::executor::block_on(exporter.export(batch(1)));
+
+    assert!(result.is_err(), "export to an unreachable receiver fails");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(exporter.metrics_snapshot().spans_failed, 1);
+}
+
+#[test]
+fn every_blocking_caller_gives_up_at_its_deadline() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(Duration::from_secs(5))
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_timeout(Duration::from_millis(300))
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter");
+
+    // more callers than the channel to the exporter thread holds
+    let started = Instant::now();
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let callers: Vec<_> = (0..100)
+            .map(|_| scope.spawn(|| futures::executor::block_on(exporter.export(batch(1)))))
+            .collect();
+        callers
+            .into_iter()
+            .map(|caller| caller.join().expect("join caller"))
+            .collect()
+    });
+
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(
+        results
+            .iter()
+            .all(|result| matches!(result, Err(OTelSdkError::Timeout(_)))),
+        "{results:?}"
+    );
+}
+
+#[cfg(feature = "rt-smol")]
+#[test]
+fn blocking_export_runs_on_smol() {
+    use opentelemetry_otlp_capnp::runtime::Smol;
+    use opentelemetry_otlp_capnp::WithCapnpConfig;
+
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_runtime(Smol)
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter on smol");
+
+    futures::executor::block_on(exporter.export(batch(4))).expect("export on smol");
+
+    assert_eq!(received.lock().unwrap().len(), 4);
+}
@@ -0,0 +1,143 @@
+use opentelemetry::KeyValue;
+use opentelemetry_otlp_capnp::{PersistentQueueConfig, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use opentelemetry_sdk::Resource;
+use std::sync::Mutex;
+use utilities::capnp::fixtures::{free_endpoint, scoped_batch, start_receiver};
+use utilities::capnp::receiver::ReceivedSpan;
+
+fn service(name: &str) -> Resource {
+    Resource::builder_empty()
+        .with_attribute(KeyValue::new("service.name", name.to_string()))
+        .build()
+}
+
+/// The received spans as sorted `(service.name, scope, name)` triples.
+fn received_spans(received: &Mutex<Vec<ReceivedSpan>>) -> Vec<(String, String, String)> {
+    let mut spans: Vec<_> = received
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|span| {
+            let service = span
+                .resource
+                .iter()
+                .find(|(key, _)| key == "service.name")
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default();
+            (service, span.scope.clone(), span.name.clone())
+        })
+        .collect();
+    spans.sort();
+    spans
+}
+
+fn expected(spans: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+    let mut spans: Vec<_> = spans
+        .iter()
+        .map(|(service, scope, name)| (service.to_string(), scope.to_string(), name.to_string()))
+        .collect();
+    spans.sort();
+    spans
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caller_encoded_batches_keep_every_scope_and_resource() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_encoding_on_caller(true)
+        .build()
+        .expect("build Capnp SpanExporter");
+
+    exporter.set_resource(&service("checkout"));
+    exporter
+        .export(scoped_batch(&[
+            ("http", "GET /cart"),
+            ("db", "SELECT"),
+            ("http", "POST /cart"),
+        ]))
+        .await
+        .expect("export checkout spans");
+    exporter.set_resource(&service("payments"));
+    exporter
+        .export(scoped_batch(&[("queue", "publish")]))
+        .await
+        .expect("export payments spans");
+    exporter.force_flush().expect("span batches are delivered");
+
+    assert_eq!(
+        received_spans(&received),
+        expected(&[
+            ("checkout", "http", "GET /cart"),
+            ("checkout", "db", "SELECT"),
+            ("checkout", "http", "POST /cart"),
+            ("payments", "queue", "publish"),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caller_encoded_batches_are_buffered_until_the_receiver_is_up() {
+    let endpoint = free_endpoint();
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_encoding_on_caller(true)
+        .build()
+        .expect("build Capnp SpanExporter");
+    exporter.set_resource(&service("checkout"));
+    // nothing listens on the endpoint yet, so the batch waits for the receiver
+    let mut export =
+        std::pin::pin!(exporter.export(scoped_batch(&[("http", "GET /cart"), ("db", "SELECT")])));
+    assert!(futures::poll!(&mut export).is_pending());
+
+    let received = start_receiver(&endpoint);
+    export.await.expect("buffered batch is delivered");
+
+    assert_eq!(
+        received_spans(&received),
+        expected(&[
+            ("checkout", "http", "GET /cart"),
+            ("checkout", "db", "SELECT"),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caller_encoded_batches_are_persisted_as_is() {
+    let endpoint = free_endpoint();
+    let directory = std::env::temp_dir().join(format!(
+        "opentelemetry-otlp-capnp-caller-encoding-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    let received = start_receiver(&endpoint);
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_encoding_on_caller(true)
+        .with_persistent_queue(PersistentQueueConfig::new(&directory))
+        .build()
+        .expect("build Capnp SpanExporter with a persistent queue");
+    exporter.set_resource(&service("checkout"));
+
+    exporter
+        .export(scoped_batch(&[("http", "GET /cart"), ("db", "SELECT")]))
+        .await
+        .expect("batch is persisted");
+    exporter
+        .force_flush()
+        .expect("persisted batch is delivered");
+
+    assert_eq!(
+        received_spans(&received),
+        expected(&[
+            ("checkout", "http", "GET /cart"),
+            ("checkout", "db", "SELECT"),
+        ])
+    );
+    let _ = std::fs::remove_dir_all(&directory);
+}
@@ -0,0 +1,134 @@
+use opentelemetry_otlp_capnp::retry::RetryPolicy;
+use opentelemetry_otlp_capnp::{
+    ReaderOptions, SpanExporter, WithCapnpConfig, WithExportConfig,
+    OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES, OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_RETRIES,
+    OTEL_EXPORTER_CAPNP_TRACES_TRAVERSAL_LIMIT,
+};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::{Duration, Instant};
+use utilities::capnp::fixtures::{batch, free_endpoint, ENV_LOCK};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+/// Build the exporter returned by `build` with `env` set.
+async fn build_with_env(
+    env: &[(&str, &str)],
+    build: impl FnOnce() -> SpanExporter,
+) -> SpanExporter {
+    let _env = ENV_LOCK.lock().await;
+    for (var, value) in env {
+        std::env::set_var(var, value);
+    }
+    let exporter = build();
+    for (var, _) in env {
+        std::env::remove_var(var);
+    }
+    exporter
+}
+
+/// Whether the receiver acknowledged a batch before the exporter shut down.
+async fn export_and_shutdown(mut exporter: SpanExporter) -> bool {
+    let timeout = Duration::from_millis(1000);
+    // a batch waits for a connection, which a broken transport never keeps up
+    let exported = tokio::time::timeout(timeout, exporter.export(batch(10))).await;
+    let flushed = exporter.shutdown_with_timeout(timeout);
+    matches!(exported, Ok(Ok(()))) && flushed.is_ok()
+}
+
+/// How long an export to a receiver that never answers in time takes to fail.
+async fn time_failing_export(env: &[(&str, &str)], policy: Option<RetryPolicy>) -> Duration {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(Duration::from_secs(5))
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = build_with_env(env, || {
+        let builder = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .with_timeout(Duration::from_millis(100));
+        match policy {
+            Some(policy) => builder.with_retry_policy(policy),
+            None => builder,
+        }
+        .build()
+        .expect("build Capnp SpanExporter")
+    })
+    .await;
+    let start = Instant::now();
+    let result = exporter.export(batch(1)).await;
+    assert!(result.is_err());
+    start.elapsed()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retry_policy_from_the_builder_overrides_the_environment() {
+    let elapsed = time_failing_export(
+        &[(OTEL_EXPORTER_CAPNP_TRACES_RETRY_MAX_RETRIES, "5")],
+        Some(RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }),
+    )
+    .await;
+    // a single attempt that times out after 100ms
+    assert!(elapsed < Duration::from_millis(500), "{elapsed:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retry_policy_is_configured_from_the_environment() {
+    let without_retries =
+        time_failing_export(&[(OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES, "0")], None).await;
+    let with_retries =
+        time_failing_export(&[(OTEL_EXPORTER_CAPNP_RETRY_MAX_RETRIES, "2")], None).await;
+    assert!(
+        without_retries < Duration::from_millis(500),
+        "{without_retries:?}"
+    );
+    // three attempts of 100ms with 100ms and 200ms backoff in between
+    assert!(
+        with_retries >= Duration::from_millis(600),
+        "{with_retries:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transport_options_are_applied() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .start()
+        .expect("start SpanReceiver");
+    let mut reader_options = ReaderOptions::new();
+    reader_options
+        .traversal_limit_in_words(Some(1024 * 1024))
+        .nesting_limit(32);
+    let exporter = build_with_env(&[], || {
+        SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .with_queue_capacity(1)
+            .with_reader_options(reader_options)
+            .with_tcp_nodelay(false)
+            .build()
+            .expect("build Capnp SpanExporter with transport options")
+    })
+    .await;
+    assert!(export_and_shutdown(exporter).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn traversal_limit_is_configured_from_the_environment() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .start()
+        .expect("start SpanReceiver");
+    // too small for any message of the receiver, so the connection never works
+    let exporter = build_with_env(&[(OTEL_EXPORTER_CAPNP_TRACES_TRAVERSAL_LIMIT, "1")], || {
+        SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .build()
+            .expect("build Capnp SpanExporter")
+    })
+    .await;
+    assert!(!export_and_shutdown(exporter).await);
+}
@@ -0,0 +1,125 @@
+use opentelemetry_otlp_capnp::{
+    Compression, ExporterBuildError, SpanExporter, WithExportConfig,
+    OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION,
+};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use utilities::capnp::fixtures::{batch, free_endpoint, ENV_LOCK};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+/// Start a receiver expecting `compression` and return the count of bytes it
+/// reads off the wire.
+fn start_receiver(endpoint: &str, compression: Option<Compression>) -> Arc<AtomicU64> {
+    let bytes_received = Arc::new(AtomicU64::new(0));
+    let receiver = NoOpSpanReceiver::new(endpoint).with_bytes_received(bytes_received.clone());
+    match compression {
+        Some(compression) => receiver.with_compression(compression),
+        None => receiver,
+    }
+    .start()
+    .expect("start SpanReceiver");
+    bytes_received
+}
+
+/// Export one batch and wait for the receiver to acknowledge it.
+async fn export_and_flush(mut exporter: SpanExporter) {
+    exporter
+        .export(batch(100))
+        .await
+        .expect("export span batch");
+    exporter.force_flush().expect("span batch is delivered");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compressed_streams_round_trip_and_shrink_the_wire_size() {
+    let endpoint = free_endpoint();
+    let bytes_received = start_receiver(&endpoint, None);
+    let exporter = {
+        let _env = ENV_LOCK.lock().await;
+        SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .build()
+            .expect("build Capnp SpanExporter")
+    };
+    export_and_flush(exporter).await;
+    let uncompressed = bytes_received.load(Ordering::Relaxed);
+
+    for compression in [
+        Compression::Packed,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4,
+    ] {
+        let endpoint = free_endpoint();
+        let bytes_received = start_receiver(&endpoint, Some(compression));
+        let exporter = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .with_compression(compression)
+            .build()
+            .expect("build Capnp SpanExporter with compression");
+        export_and_flush(exporter).await;
+        let compressed = bytes_received.load(Ordering::Relaxed);
+        assert!(
+            compressed < uncompressed,
+            "{compression} sent {compressed} bytes, uncompressed {uncompressed} bytes"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compression_is_configured_from_the_environment() {
+    let endpoint = free_endpoint();
+    start_receiver(&endpoint, Some(Compression::Packed));
+
+    let (unsupported, exporter) = {
+        let _env = ENV_LOCK.lock().await;
+        let build = || {
+            SpanExporter::builder()
+                .with_capnp()
+                .with_endpoint(&endpoint)
+                .build()
+        };
+        std::env::set_var(OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION, "gzip");
+        let unsupported = build();
+        std::env::set_var(OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION, "packed");
+        let exporter = build();
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_COMPRESSION);
+        (unsupported, exporter)
+    };
+
+    assert!(matches!(
+        unsupported,
+        Err(ExporterBuildError::UnsupportedCompressionAlgorithm(algorithm)) if algorithm == "gzip"
+    ));
+    export_and_flush(exporter.expect("build Capnp SpanExporter with packed compression")).await;
+}
+
+#[cfg(not(all(feature = "zstd", feature = "lz4")))]
+#[test]
+fn compression_without_its_feature_fails_the_build() {
+    for (feature, compression) in [
+        #[cfg(not(feature = "zstd"))]
+        ("zstd", Compression::Zstd),
+        #[cfg(not(feature = "lz4"))]
+        ("lz4", Compression::Lz4),
+    ] {
+        let result = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(free_endpoint())
+            .with_compression(compression)
+            .build();
+
+        assert!(
+            matches!(
+                result,
+                Err(ExporterBuildError::FeatureRequiredForCompressionAlgorithm(required, algorithm))
+                    if required == feature && algorithm == compression
+            ),
+            "{compression}"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use opentelemetry_otlp_capnp::{
+    ExporterBuildError, SpanExporter, SpanReceiver, WithExportConfig,
+    OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT,
+};
+use utilities::capnp::fixtures::{free_endpoint, ENV_LOCK};
+
+fn build_exporter(endpoint: &str) -> Result<SpanExporter, ExporterBuildError> {
+    SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(endpoint)
+        .build()
+}
+
+#[test]
+fn malformed_endpoints_are_invalid() {
+    for endpoint in [
+        "localhost",
+        ":4317",
+        "localhost:http",
+        "127.0.0.1:70000",
+        "http://localhost:4318",
+        "127.0.0.1:4317,",
+        #[cfg(unix)]
+        "unix://",
+    ] {
+        assert!(
+            matches!(
+                build_exporter(endpoint),
+                Err(ExporterBuildError::InvalidEndpoint { .. })
+            ),
+            "{endpoint}"
+        );
+        assert!(
+            matches!(
+                SpanReceiver::new(endpoint),
+                Err(ExporterBuildError::InvalidEndpoint { .. })
+            ),
+            "{endpoint}"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn a_comma_in_a_unix_socket_path_is_invalid() {
+    const ENDPOINT: &str = "unix:///run/otel,collector.sock";
+    let error = build_exporter(ENDPOINT).expect_err("the path is split at the comma");
+
+    assert!(
+        matches!(
+            &error,
+            ExporterBuildError::InvalidEndpoint { endpoint, reason }
+                if endpoint == ENDPOINT && reason.contains("'collector.sock'")
+        ),
+        "{error}"
+    );
+}
+
+#[test]
+fn unknown_hosts_are_unresolvable() {
+    // the .invalid top-level domain never resolves
+    const ENDPOINT: &str = "collector.invalid:4317";
+    assert!(matches!(
+        build_exporter(ENDPOINT),
+        Err(ExporterBuildError::UnresolvableHost { endpoint, .. }) if endpoint == ENDPOINT
+    ));
+    assert!(matches!(
+        SpanReceiver::new(ENDPOINT),
+        Err(ExporterBuildError::UnresolvableHost { .. })
+    ));
+}
+
+#[test]
+fn a_typo_in_the_endpoint_variable_fails_the_build() {
+    let exporter = {
+        let _env = ENV_LOCK.blocking_lock();
+        std::env::set_var(OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT, "127.0.0.1;4317");
+        let exporter = SpanExporter::builder().with_capnp().build();
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_ENDPOINT);
+        exporter
+    };
+
+    let error = exporter.expect_err("the endpoint has no port");
+    assert_eq!(
+        error.to_string(),
+        "invalid endpoint '127.0.0.1;4317': expected host:port"
+    );
+}
+
+#[test]
+fn a_receiver_fails_to_start_on_an_endpoint_in_use() {
+    let endpoint = free_endpoint();
+    let _listener = std::net::TcpListener::bind(&endpoint).expect("bind listener");
+
+    let result = SpanReceiver::new(&endpoint)
+        .expect("valid endpoint")
+        .start();
+
+    assert!(
+        matches!(&result, Err(e) if e.kind() == std::io::ErrorKind::AddrInUse),
+        "{result:?}"
+    );
+}
@@ -0,0 +1,90 @@
+use opentelemetry_otlp_capnp::{LoadBalancingStrategy, Metadata, SpanExporter, SpanReceiver};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use utilities::capnp::fixtures::{batch, free_endpoint};
+
+/// Start a receiver and return the count of export requests it handles.
+fn start_receiver(endpoint: &str) -> Arc<AtomicU64> {
+    let requests = Arc::new(AtomicU64::new(0));
+    let counter = requests.clone();
+    SpanReceiver::new(endpoint)
+        .expect("valid receiver endpoint")
+        .with_interceptor(move |_: &Metadata| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        })
+        .start()
+        .expect("start SpanReceiver");
+    requests
+}
+
+/// Export `batches` span batches, pausing in between so the exporter thread
+/// can connect the remaining receivers in the background.
+async fn export_batches(endpoints: &[&str], strategy: LoadBalancingStrategy, batches: usize) {
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoints(endpoints.iter().copied())
+        .with_load_balancing_strategy(strategy)
+        .build()
+        .expect("build Capnp SpanExporter with several endpoints");
+    for _ in 0..batches {
+        exporter.export(batch(10)).await.expect("export span batch");
+        exporter.force_flush().expect("span batch is delivered");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failover_skips_an_unreachable_receiver() {
+    let endpoint = free_endpoint();
+    let requests = start_receiver(&endpoint);
+    // nothing listens here, so connecting to it fails right away
+    let unreachable = free_endpoint();
+
+    export_batches(
+        &[&unreachable, &endpoint],
+        LoadBalancingStrategy::Failover,
+        3,
+    )
+    .await;
+
+    assert_eq!(requests.load(Ordering::Relaxed), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failover_prefers_the_first_receiver() {
+    let (primary_endpoint, secondary_endpoint) = (free_endpoint(), free_endpoint());
+    let primary = start_receiver(&primary_endpoint);
+    let secondary = start_receiver(&secondary_endpoint);
+
+    export_batches(
+        &[&primary_endpoint, &secondary_endpoint],
+        LoadBalancingStrategy::Failover,
+        5,
+    )
+    .await;
+
+    assert_eq!(primary.load(Ordering::Relaxed), 5);
+    assert_eq!(secondary.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn round_robin_spreads_batches_over_the_receivers() {
+    let (first_endpoint, second_endpoint) = (free_endpoint(), free_endpoint());
+    let first = start_receiver(&first_endpoint);
+    let second = start_receiver(&second_endpoint);
+    // nothing listens here, so connecting to it fails right away
+    let unreachable = free_endpoint();
+
+    export_batches(
+        &[&first_endpoint, &unreachable, &second_endpoint],
+        LoadBalancingStrategy::RoundRobin,
+        10,
+    )
+    .await;
+
+    assert!(first.load(Ordering::Relaxed) > 0);
+    assert!(second.load(Ordering::Relaxed) > 0);
+}
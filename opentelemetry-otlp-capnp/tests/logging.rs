@@ -0,0 +1,145 @@
+use opentelemetry_otlp_capnp::{SpanExporter, SpanReceiver, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use utilities::capnp::fixtures::{batch, free_endpoint};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+fn blocking_exporter(endpoint: &str) -> SpanExporter {
+    SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(endpoint)
+        .with_timeout(Duration::from_millis(500))
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter")
+}
+
+/// Collects what the OpenTelemetry internal logging macros write.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    /// The logs of every thread, captured by a global subscriber shared by
+    /// the tests, which run in parallel and tell their logs apart by endpoint.
+    fn global() -> &'static CapturedLogs {
+        static LOGS: OnceLock<CapturedLogs> = OnceLock::new();
+        LOGS.get_or_init(|| {
+            let logs = CapturedLogs::default();
+            tracing::subscriber::set_global_default(logs.subscriber())
+                .expect("install the global subscriber");
+            logs
+        })
+    }
+
+    /// The captured lines containing `pattern`.
+    fn lines_with(&self, pattern: &str) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.lock().unwrap())
+            .lines()
+            .filter(|line| line.contains(pattern))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// How much has been captured so far, to read what follows with
+    /// [CapturedLogs::since].
+    fn mark(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// What has been captured after `mark`.
+    fn since(&self, mark: usize) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()[mark..]).into_owned()
+    }
+
+    fn subscriber(&self) -> impl tracing::Subscriber + Send + Sync {
+        let logs = self.clone();
+        tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || logs.clone())
+            .with_ansi(false)
+            .finish()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn connecting_is_logged_with_the_endpoint() {
+    let logs = CapturedLogs::global();
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .start()
+        .expect("start SpanReceiver");
+
+    futures::executor::block_on(blocking_exporter(&endpoint).export(batch(1)))
+        .expect("export to the receiver");
+
+    let lines = logs.lines_with(&endpoint);
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("CapnpSpanExporter.Connected")),
+        "{lines:?}"
+    );
+}
+
+#[test]
+fn connection_failures_are_logged_with_the_error() {
+    let logs = CapturedLogs::global();
+    // nothing listens on this endpoint
+    let endpoint = free_endpoint();
+
+    let result = futures::executor::block_on(blocking_exporter(&endpoint).export(batch(1)));
+
+    assert!(result.is_err());
+    let lines = logs.lines_with(&endpoint);
+    assert!(
+        lines.iter().any(|line| {
+            line.contains("CapnpSpanExporter.ConnectFailed") && line.contains("error=")
+        }),
+        "{lines:?}"
+    );
+}
+
+#[test]
+fn a_quiet_receiver_logs_nothing() {
+    let logs = CapturedLogs::global();
+    let verbose_endpoint = free_endpoint();
+    let quiet_endpoint = free_endpoint();
+    SpanReceiver::new(&verbose_endpoint)
+        .expect("valid endpoint")
+        .start()
+        .expect("start SpanReceiver");
+    SpanReceiver::new(&quiet_endpoint)
+        .expect("valid endpoint")
+        .with_quiet(true)
+        .start()
+        .expect("start quiet SpanReceiver");
+
+    futures::executor::block_on(blocking_exporter(&verbose_endpoint).export(batch(2)))
+        .expect("export to the verbose receiver");
+    // only the receivers of this test log received spans
+    let received = logs.lines_with("SpanReceiver.SpansReceived");
+    assert!(
+        received.iter().any(|line| line.contains("span_count=2")),
+        "{received:?}"
+    );
+
+    // the other tests start no `SpanReceiver`, so whatever such events follow
+    // come from the quiet one
+    let mark = logs.mark();
+    futures::executor::block_on(blocking_exporter(&quiet_endpoint).export(batch(3)))
+        .expect("export to the quiet receiver");
+    let quiet_logs = logs.since(mark);
+    assert!(!quiet_logs.contains("SpanReceiver."), "{quiet_logs}");
+}
@@ -0,0 +1,126 @@
+use opentelemetry_otlp_capnp::{
+    Metadata, SpanExporter, SpanReceiver, WithExportConfig, OTEL_EXPORTER_CAPNP_TRACES_HEADERS,
+};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use utilities::capnp::fixtures::{batch, free_endpoint, ENV_LOCK};
+
+/// Start a receiver that records the metadata of every export request and
+/// rejects requests without the expected authorization.
+fn start_receiver(endpoint: &str) -> Arc<Mutex<Vec<Metadata>>> {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let recorded = received.clone();
+    SpanReceiver::new(endpoint)
+        .expect("valid receiver endpoint")
+        .with_interceptor(move |metadata: &Metadata| {
+            if metadata.get("authorization") != Some("Bearer valid") {
+                return Err("invalid authorization".to_string());
+            }
+            recorded.lock().unwrap().push(metadata.clone());
+            Ok(())
+        })
+        .start()
+        .expect("start SpanReceiver");
+    received
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn headers_and_interceptor_metadata_reach_the_receiver() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+
+    let exporter = {
+        let _env = ENV_LOCK.lock().await;
+        std::env::set_var(
+            OTEL_EXPORTER_CAPNP_TRACES_HEADERS,
+            "x-api-key=abc%20def, x-tenant-id=from-env",
+        );
+        let exporter = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .with_headers(HashMap::from([(
+                "x-tenant-id".to_string(),
+                "tenant-1".to_string(),
+            )]))
+            .with_interceptor(|metadata: &mut Metadata| {
+                metadata.insert("authorization", "Bearer valid");
+                Ok(())
+            })
+            .build();
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_HEADERS);
+        exporter
+    };
+    let mut exporter = exporter.expect("build Capnp SpanExporter with headers");
+
+    exporter.export(batch(10)).await.expect("export span batch");
+    exporter.force_flush().expect("span batch is delivered");
+
+    let received = received.lock().unwrap();
+    let expected: Metadata = [
+        ("authorization", "Bearer valid"),
+        ("x-api-key", "abc def"),
+        ("x-tenant-id", "tenant-1"),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(*received, vec![expected]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receiver_interceptor_rejects_requests() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_interceptor(|metadata: &mut Metadata| {
+            metadata.insert("authorization", "Bearer expired");
+            Ok(())
+        })
+        .build()
+        .expect("build Capnp SpanExporter");
+    let rejected = exporter.export(batch(10)).await;
+
+    assert!(rejected.is_err());
+    assert!(received.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exporter_interceptor_errors_fail_the_export() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_interceptor(|_: &mut Metadata| Err("no token available".to_string()))
+        .build()
+        .expect("build Capnp SpanExporter");
+    let failed = exporter.export(batch(10)).await;
+
+    assert!(failed.is_err());
+    assert!(received.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_batch_exported_before_the_receiver_is_up_reports_the_outcome() {
+    let endpoint = free_endpoint();
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_interceptor(|metadata: &mut Metadata| {
+            metadata.insert("authorization", "Bearer expired");
+            Ok(())
+        })
+        .build()
+        .expect("build Capnp SpanExporter");
+    // nothing listens on the endpoint yet, so the batch waits for the receiver
+    let mut rejected = std::pin::pin!(exporter.export(batch(10)));
+    assert!(futures::poll!(&mut rejected).is_pending());
+
+    let received = start_receiver(&endpoint);
+    assert!(rejected.await.is_err());
+    assert!(received.lock().unwrap().is_empty());
+}
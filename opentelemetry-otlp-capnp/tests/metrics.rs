@@ -0,0 +1,196 @@
+use futures::stream::{FuturesUnordered, StreamExt};
+use opentelemetry_otlp_capnp::retry::RetryPolicy;
+use opentelemetry_otlp_capnp::{SpanExporter, WithCapnpConfig, WithExportConfig};
+use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+use opentelemetry_sdk::metrics::{InMemoryMetricExporter, SdkMeterProvider};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::Duration;
+use utilities::capnp::fixtures::{batch, free_endpoint};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+/// Export two batches of 10 spans to a receiver, waiting for each to be acknowledged.
+async fn export_twice(exporter: &mut SpanExporter) {
+    for _ in 0..2 {
+        exporter.export(batch(10)).await.expect("export span batch");
+        exporter
+            .force_flush()
+            .expect("receiver acknowledges every batch");
+    }
+}
+
+/// The sum of the counter `name` collected by `exporter`.
+fn counter_value(exporter: &InMemoryMetricExporter, name: &str) -> Option<u64> {
+    let metrics = exporter.get_finished_metrics().expect("collected metrics");
+    let resource_metrics = metrics.last()?;
+    let metric = resource_metrics
+        .scope_metrics()
+        .filter(|scope_metrics| scope_metrics.scope().name() == "opentelemetry-otlp-capnp")
+        .flat_map(|scope_metrics| scope_metrics.metrics())
+        .find(|metric| metric.name() == name)?;
+    match metric.data() {
+        AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+            Some(sum.data_points().map(|point| point.value()).sum())
+        }
+        _ => None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshot_counts_exported_spans_and_rpcs() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .start()
+        .expect("start SpanReceiver");
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build()
+        .expect("build Capnp SpanExporter");
+    export_twice(&mut exporter).await;
+
+    let snapshot = exporter.metrics_snapshot();
+    assert_eq!(snapshot.spans_exported, 20, "{snapshot:?}");
+    assert_eq!(snapshot.spans_failed, 0, "{snapshot:?}");
+    assert_eq!(snapshot.rpcs, 2, "{snapshot:?}");
+    assert_eq!(snapshot.queue_depth, 0, "{snapshot:?}");
+    assert!(snapshot.rpc_duration > Duration::ZERO, "{snapshot:?}");
+
+    exporter.shutdown().expect("shut down exporter");
+    assert_eq!(exporter.metrics_snapshot().spans_exported, 20);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn metrics_are_recorded_on_the_meter_provider() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .start()
+        .expect("start SpanReceiver");
+    let metric_exporter = InMemoryMetricExporter::default();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metric_exporter.clone())
+        .build();
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_meter_provider(&meter_provider)
+        .build()
+        .expect("build Capnp SpanExporter");
+    export_twice(&mut exporter).await;
+
+    meter_provider.force_flush().expect("collect metrics");
+    assert_eq!(
+        counter_value(&metric_exporter, "capnp.exporter.spans.exported"),
+        Some(20)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_exports_count_retries_and_spans() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(Duration::from_secs(5))
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_timeout(Duration::from_millis(50))
+        .with_retry_policy(RetryPolicy {
+            max_retries: 2,
+            initial_delay_ms: 10,
+            max_delay_ms: 10,
+            jitter_ms: 0,
+        })
+        .build()
+        .expect("build Capnp SpanExporter");
+    let result = exporter.export(batch(10)).await;
+    assert!(result.is_err());
+
+    let snapshot = exporter.metrics_snapshot();
+    assert_eq!(snapshot.retries, 2, "{snapshot:?}");
+    assert_eq!(snapshot.spans_failed, 10, "{snapshot:?}");
+    assert_eq!(snapshot.rpcs, 3, "{snapshot:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_dropped_while_disconnected_are_counted() {
+    // nothing listens on this port
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(free_endpoint())
+        .build()
+        .expect("build Capnp SpanExporter");
+    let exports: FuturesUnordered<_> = (0..40).map(|_| exporter.export(batch(3))).collect();
+    // the buffered batches wait for the receiver, the others are dropped at once
+    let dropped: Vec<_> = exports
+        .take_until(tokio::time::sleep(Duration::from_millis(500)))
+        .collect()
+        .await;
+    assert!(!dropped.is_empty());
+    assert!(dropped.iter().all(Result::is_err), "{dropped:?}");
+    assert_eq!(
+        exporter.metrics_snapshot().spans_dropped,
+        3 * dropped.len() as u64
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_left_in_the_queue_at_shutdown_are_counted_as_dropped() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(Duration::from_millis(500))
+        .start()
+        .expect("start SpanReceiver");
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_max_in_flight_requests(1)
+        .build()
+        .expect("build Capnp SpanExporter");
+    exporter.export(batch(1)).await.expect("export span batch");
+
+    // the first batch is in flight while the other two wait in the queue
+    let queueing = exporter.clone();
+    let mut exports: Vec<_> = (0..3)
+        .map(|_| Box::pin(queueing.export(batch(10))))
+        .collect();
+    for export in &mut exports {
+        assert!(futures::poll!(export).is_pending());
+    }
+    let _ = exporter.shutdown_with_timeout(Duration::from_millis(100));
+
+    let results = futures::future::join_all(exports).await;
+    assert!(results.iter().all(Result::is_err), "{results:?}");
+    assert_eq!(exporter.metrics_snapshot().spans_dropped, 20);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_connections_count_as_reconnects() {
+    let endpoint = free_endpoint();
+    // accepts every connection and closes it right away
+    let listener = std::net::TcpListener::bind(&endpoint).expect("bind listener");
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            drop(stream);
+        }
+    });
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build()
+        .expect("build Capnp SpanExporter");
+    // the exporter thread notices the lost connection with the next batch,
+    // which keeps waiting for a receiver that takes it
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let _ = tokio::time::timeout(Duration::from_millis(200), exporter.export(batch(1))).await;
+
+    let mut reconnects = 0;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        reconnects = exporter.metrics_snapshot().reconnects;
+        if reconnects > 0 {
+            break;
+        }
+    }
+    assert!(reconnects > 0);
+}
@@ -0,0 +1,143 @@
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::error::OTelSdkError;
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use utilities::capnp::fixtures::{batch, free_endpoint};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+/// Build an exporter once the receiver at `endpoint` listens and give it time
+/// to connect, so that its exports are not buffered as disconnected.
+async fn connected_exporter(endpoint: &str) -> SpanExporter {
+    let addr = endpoint.parse().expect("socket address");
+    opentelemetry_otlp_capnp::connect_with_retry(&addr, 1000)
+        .await
+        .expect("receiver is listening");
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(endpoint)
+        .build()
+        .expect("build Capnp SpanExporter");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    exporter
+}
+
+/// Collects what the OpenTelemetry internal logging macros write.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    /// The logs of every thread, captured by a global subscriber that is
+    /// installed once for the tests of this file, which run in parallel and
+    /// tell their logs apart by their contents.
+    fn global() -> &'static CapturedLogs {
+        static LOGS: OnceLock<CapturedLogs> = OnceLock::new();
+        LOGS.get_or_init(|| {
+            let logs = CapturedLogs::default();
+            let subscriber = tracing_subscriber::fmt()
+                .with_writer({
+                    let logs = logs.clone();
+                    move || logs.clone()
+                })
+                .with_ansi(false)
+                .finish();
+            tracing::subscriber::set_global_default(subscriber)
+                .expect("install the global subscriber");
+            logs
+        })
+    }
+
+    /// The captured lines containing `pattern`.
+    fn lines_with(&self, pattern: &str) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.lock().unwrap())
+            .lines()
+            .filter(|line| line.contains(pattern))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_spans_fail_the_export_without_a_retry() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_partial_success(2, "2 spans have an invalid trace ID")
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = connected_exporter(&endpoint).await;
+
+    let result = exporter.export(batch(5)).await;
+
+    match result {
+        Err(OTelSdkError::InternalFailure(message)) => {
+            assert!(message.contains("rejected 2 spans"), "{message}");
+            assert!(message.contains("invalid trace ID"), "{message}");
+        }
+        other => panic!("expected the rejection to fail the export, got {other:?}"),
+    }
+    let metrics = exporter.metrics_snapshot();
+    assert_eq!(metrics.spans_rejected, 2);
+    assert_eq!(metrics.spans_exported, 3);
+    assert_eq!(metrics.spans_failed, 0);
+    assert_eq!(metrics.retries, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_error_message_without_rejected_spans_is_a_success() {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_partial_success(0, "attribute values were truncated")
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = connected_exporter(&endpoint).await;
+
+    exporter
+        .export(batch(5))
+        .await
+        .expect("the receiver accepted every span");
+
+    let metrics = exporter.metrics_snapshot();
+    assert_eq!(metrics.spans_rejected, 0);
+    assert_eq!(metrics.spans_exported, 5);
+}
+
+#[test]
+fn rejections_are_logged() {
+    // the exporter logs on its own thread, so capture every thread
+    let logs = CapturedLogs::global();
+    let endpoint = free_endpoint();
+    // tells the warnings of this test apart from those of the others
+    let error_message = format!("span sent to {endpoint} is too large");
+    NoOpSpanReceiver::new(&endpoint)
+        .with_partial_success(1, &error_message)
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build_blocking()
+        .expect("build blocking Capnp SpanExporter");
+
+    let result = futures::executor::block_on(exporter.export(batch(2)));
+
+    assert!(result.is_err());
+    let lines = logs.lines_with(&error_message);
+    assert!(
+        lines.iter().any(|line| {
+            line.contains("CapnpSpanExporter.PartialSuccess") && line.contains("rejected_spans=1")
+        }),
+        "{lines:?}"
+    );
+}
@@ -0,0 +1,202 @@
+use futures::future::{BoxFuture, LocalBoxFuture};
+use opentelemetry_otlp_capnp::runtime::{AsyncStream, Runtime, TaskFactory, Tokio};
+use opentelemetry_otlp_capnp::{
+    ExporterBuildError, PersistentQueueConfig, SpanExporter, WithCapnpConfig, WithExportConfig,
+};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+use utilities::capnp::fixtures::{batch, free_endpoint};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+fn segment_count(directory: &Path) -> usize {
+    std::fs::read_dir(directory)
+        .expect("queue directory exists")
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .is_ok_and(|entry| entry.path().extension().is_some_and(|ext| ext == "capnp"))
+        })
+        .count()
+}
+
+fn build_exporter(endpoint: &str, directory: &Path) -> SpanExporter {
+    SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(endpoint)
+        .with_persistent_queue(PersistentQueueConfig::new(directory))
+        .build()
+        .expect("build Capnp SpanExporter with a persistent queue")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn persisted_batches_survive_restart_and_are_replayed() {
+    let directory = std::env::temp_dir().join(format!(
+        "opentelemetry-otlp-capnp-persistent-queue-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    let endpoint = free_endpoint();
+
+    // nothing listens on the endpoint yet, so every batch stays on disk
+    let exporter = build_exporter(&endpoint, &directory);
+    for _ in 0..5 {
+        exporter
+            .export(batch(10))
+            .await
+            .expect("batch is persisted while the receiver is unreachable");
+    }
+    assert_eq!(segment_count(&directory), 5);
+    drop(exporter);
+
+    let _receiver = NoOpSpanReceiver::new(&endpoint)
+        .start()
+        .expect("start SpanReceiver");
+    // a new exporter on the same directory picks up where the first one stopped
+    let _exporter = build_exporter(&endpoint, &directory);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while segment_count(&directory) > 0 {
+        assert!(
+            Instant::now() < deadline,
+            "persisted batches were not exported"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let _ = std::fs::remove_dir_all(&directory);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn new_batches_are_not_held_up_by_a_long_replay() {
+    let directory = std::env::temp_dir().join(format!(
+        "opentelemetry-otlp-capnp-persistent-queue-replay-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    let endpoint = free_endpoint();
+    let exporter = build_exporter(&endpoint, &directory);
+    for _ in 0..10 {
+        exporter
+            .export(batch(10))
+            .await
+            .expect("batch is persisted while the receiver is unreachable");
+    }
+    drop(exporter);
+
+    // replaying the ten batches takes three seconds
+    let _receiver = NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(Duration::from_millis(300))
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = build_exporter(&endpoint, &directory);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let started = Instant::now();
+    exporter
+        .export(batch(1))
+        .await
+        .expect("batch is persisted during the replay");
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(segment_count(&directory) > 5, "the replay is under way");
+
+    let _ = std::fs::remove_dir_all(&directory);
+}
+
+/// [Tokio], recording the threads that blocking work is handed over from and
+/// the ones it runs on.
+#[derive(Debug, Default)]
+struct RecordingBlocking {
+    tokio: Tokio,
+    threads: Arc<Mutex<Vec<(ThreadId, ThreadId)>>>,
+}
+
+impl Runtime for RecordingBlocking {
+    fn spawn_thread(
+        &self,
+        name: &str,
+        task: TaskFactory,
+    ) -> Result<std::thread::JoinHandle<()>, ExporterBuildError> {
+        self.tokio.spawn_thread(name, task)
+    }
+
+    fn spawn_local(&self, future: LocalBoxFuture<'static, ()>) {
+        self.tokio.spawn_local(future)
+    }
+
+    fn delay(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.tokio.delay(duration)
+    }
+
+    fn spawn_blocking(&self, work: Box<dyn FnOnce() + Send>) -> BoxFuture<'static, ()> {
+        let caller = std::thread::current().id();
+        let threads = self.threads.clone();
+        self.tokio.spawn_blocking(Box::new(move || {
+            threads
+                .lock()
+                .unwrap()
+                .push((caller, std::thread::current().id()));
+            work();
+        }))
+    }
+
+    fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        nodelay: bool,
+    ) -> LocalBoxFuture<'static, std::io::Result<Box<dyn AsyncStream>>> {
+        self.tokio.connect_tcp(addr, nodelay)
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(
+        &self,
+        path: std::path::PathBuf,
+    ) -> LocalBoxFuture<'static, std::io::Result<Box<dyn AsyncStream>>> {
+        self.tokio.connect_unix(path)
+    }
+}
+
+#[tokio::test]
+async fn disk_io_runs_off_the_executor_of_a_local_exporter() {
+    let directory = std::env::temp_dir().join(format!(
+        "opentelemetry-otlp-capnp-persistent-queue-local-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    let runtime = RecordingBlocking::default();
+    let threads = runtime.threads.clone();
+    // nothing listens on the endpoint, so every batch stays on disk
+    let (exporter, task) = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(free_endpoint())
+        .with_persistent_queue(PersistentQueueConfig::new(&directory))
+        .with_runtime(runtime)
+        .build_local()
+        .expect("build local Capnp SpanExporter with a persistent queue");
+    let local = tokio::task::LocalSet::new();
+    local.spawn_local(task);
+
+    local
+        .run_until(async {
+            for _ in 0..3 {
+                exporter
+                    .export(batch(10))
+                    .await
+                    .expect("batch is persisted while the receiver is unreachable");
+            }
+        })
+        .await;
+
+    assert_eq!(segment_count(&directory), 3);
+    let threads = threads.lock().unwrap();
+    assert!(threads.len() >= 3, "{threads:?}");
+    assert!(
+        threads.iter().all(|(caller, worker)| caller != worker),
+        "{threads:?}"
+    );
+    drop(exporter);
+    let _ = std::fs::remove_dir_all(&directory);
+}
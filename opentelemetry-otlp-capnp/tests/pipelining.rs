@@ -0,0 +1,49 @@
+use futures::future::join_all;
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::{Duration, Instant};
+use utilities::capnp::fixtures::{batch, free_endpoint};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+/// How long the receiver takes to answer every export request.
+const RESPONSE_DELAY: Duration = Duration::from_millis(200);
+const CONCURRENT_EXPORTS: usize = 4;
+
+/// Export [CONCURRENT_EXPORTS] batches at once through a connected exporter
+/// and return how long it took until all of them were acknowledged.
+async fn export_concurrently(max_in_flight_requests: usize) -> Duration {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(RESPONSE_DELAY)
+        .start()
+        .expect("start SpanReceiver");
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_max_in_flight_requests(max_in_flight_requests)
+        .build()
+        .expect("build Capnp SpanExporter");
+    exporter.export(batch(1)).await.expect("export span batch");
+    exporter.force_flush().expect("connect to SpanReceiver");
+
+    let start = Instant::now();
+    let results = join_all((0..CONCURRENT_EXPORTS).map(|_| exporter.export(batch(10)))).await;
+    let elapsed = start.elapsed();
+    assert!(results.iter().all(Result::is_ok), "{results:?}");
+    elapsed
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn one_request_in_flight_takes_a_round_trip_per_batch() {
+    let elapsed = export_concurrently(1).await;
+    assert!(
+        elapsed >= RESPONSE_DELAY * CONCURRENT_EXPORTS as u32,
+        "{elapsed:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_in_flight_share_the_round_trip() {
+    let elapsed = export_concurrently(CONCURRENT_EXPORTS).await;
+    assert!(elapsed < RESPONSE_DELAY * 2, "{elapsed:?}");
+}
@@ -0,0 +1,46 @@
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use utilities::capnp::fixtures::{batch, free_endpoint};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exports_resume_over_a_new_connection_once_the_receiver_restarts() {
+    let endpoint = free_endpoint();
+    let connections = Arc::new(AtomicU64::new(0));
+    let shutdown = Arc::new(Notify::new());
+    let receiver = NoOpSpanReceiver::new(&endpoint)
+        .with_connections(connections.clone())
+        .with_shutdown(shutdown.clone())
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build()
+        .expect("build Capnp SpanExporter");
+    exporter
+        .export(batch(1))
+        .await
+        .expect("export to the first receiver");
+    assert_eq!(connections.load(Ordering::Relaxed), 1);
+
+    // stop the receiver, closing the exporter's connection, and start a new
+    // one on the same endpoint
+    shutdown.notify_one();
+    tokio::task::spawn_blocking(move || receiver.join())
+        .await
+        .expect("join the receiver thread")
+        .expect("stop SpanReceiver");
+    NoOpSpanReceiver::new(&endpoint)
+        .with_connections(connections.clone())
+        .start()
+        .expect("restart SpanReceiver");
+
+    let exported = tokio::time::timeout(Duration::from_secs(5), exporter.export(batch(2))).await;
+    assert!(matches!(exported, Ok(Ok(()))), "{exported:?}");
+    assert_eq!(connections.load(Ordering::Relaxed), 2);
+}
@@ -0,0 +1,124 @@
+use opentelemetry_otlp_capnp::runtime::{Runtime, Tokio};
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::{Duration, Instant};
+use utilities::capnp::fixtures::{batch, free_endpoint, start_receiver};
+
+#[tokio::test]
+async fn exporter_task_runs_on_a_tokio_local_set() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let (mut exporter, task) = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build_local()
+        .expect("build local Capnp SpanExporter");
+    let local = tokio::task::LocalSet::new();
+    local.spawn_local(task);
+
+    local
+        .run_until(async {
+            exporter
+                .export(batch(3))
+                .await
+                .expect("export on the LocalSet");
+            // shutdown waits for the task, which runs on this thread
+            tokio::task::spawn_blocking(move || exporter.shutdown())
+                .await
+                .expect("join shutdown")
+                .expect("shut down the exporter");
+        })
+        .await;
+    tokio::time::timeout(Duration::from_secs(5), local)
+        .await
+        .expect("the exporter task completes after shutdown");
+
+    assert_eq!(received.lock().unwrap().len(), 3);
+}
+
+/// The count of threads of this process.
+#[cfg(target_os = "linux")]
+fn thread_count() -> usize {
+    std::fs::read_dir("/proc/self/task")
+        .expect("list the threads")
+        .count()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn delays_outside_a_tokio_runtime_share_one_timer_thread() {
+    let runtime = Tokio::new();
+    let threads = thread_count();
+
+    let pending: Vec<_> = (0..100)
+        .map(|_| runtime.delay(Duration::from_secs(3600)))
+        .collect();
+    // other tests may start a few threads meanwhile, but not one per delay
+    assert!(thread_count() < threads + 10);
+    drop(pending);
+
+    let started = Instant::now();
+    futures::executor::block_on(runtime.delay(Duration::from_millis(50)));
+    assert!(started.elapsed() >= Duration::from_millis(50));
+}
+
+#[cfg(feature = "rt-smol")]
+#[test]
+fn exporter_thread_runs_on_smol() {
+    use opentelemetry_otlp_capnp::runtime::Smol;
+    use opentelemetry_otlp_capnp::WithCapnpConfig;
+
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_runtime(Smol)
+        .build()
+        .expect("build Capnp SpanExporter on smol");
+
+    smol::block_on(exporter.export(batch(2))).expect("export outside of tokio");
+    exporter.force_flush().expect("flush on smol");
+    exporter.shutdown().expect("shut down the exporter");
+
+    assert_eq!(received.lock().unwrap().len(), 2);
+}
+
+#[cfg(feature = "rt-smol")]
+#[test]
+fn exporter_task_runs_on_a_smol_local_executor() {
+    use opentelemetry_otlp_capnp::runtime::Smol;
+    use opentelemetry_otlp_capnp::WithCapnpConfig;
+
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let (mut exporter, task) = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_runtime(Smol)
+        .build_local()
+        .expect("build local Capnp SpanExporter on smol");
+    let executor = smol::LocalExecutor::new();
+    let task = executor.spawn(task);
+
+    smol::block_on(executor.run(async {
+        exporter
+            .export(batch(4))
+            .await
+            .expect("export on the LocalExecutor");
+        // force_flush waits for the task, which runs on this thread
+        let exporter = smol::unblock(move || {
+            exporter.force_flush().expect("flush on the LocalExecutor");
+            exporter
+        })
+        .await;
+        drop(exporter);
+        smol::future::or(task, async {
+            smol::Timer::after(Duration::from_secs(5)).await;
+            panic!("the exporter task completes once the exporter is dropped");
+        })
+        .await;
+    }));
+
+    assert_eq!(received.lock().unwrap().len(), 4);
+}
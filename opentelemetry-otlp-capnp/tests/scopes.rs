@@ -0,0 +1,96 @@
+use opentelemetry::KeyValue;
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use opentelemetry_sdk::Resource;
+use std::sync::Mutex;
+use utilities::capnp::fixtures::{free_endpoint, scoped_batch, start_receiver};
+use utilities::capnp::receiver::ReceivedSpan;
+
+fn service(name: &str) -> Resource {
+    Resource::builder_empty()
+        .with_attribute(KeyValue::new("service.name", name.to_string()))
+        .build()
+}
+
+fn received_span(service: &str, scope: &str, name: &str) -> ReceivedSpan {
+    ReceivedSpan {
+        resource: vec![("service.name".to_string(), service.to_string())],
+        scope: scope.to_string(),
+        name: name.to_string(),
+    }
+}
+
+fn sorted(received: &Mutex<Vec<ReceivedSpan>>) -> Vec<ReceivedSpan> {
+    let mut spans = received.lock().unwrap().clone();
+    spans.sort();
+    spans
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receiver_sees_the_spans_of_every_scope() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build()
+        .expect("build Capnp SpanExporter");
+    exporter.set_resource(&service("checkout"));
+
+    exporter
+        .export(scoped_batch(&[
+            ("http", "GET /cart"),
+            ("db", "SELECT"),
+            ("http", "POST /cart"),
+            ("cache", "GET"),
+            ("db", "UPDATE"),
+        ]))
+        .await
+        .expect("export span batch");
+    exporter.force_flush().expect("span batch is delivered");
+
+    let mut expected = vec![
+        received_span("checkout", "cache", "GET"),
+        received_span("checkout", "db", "SELECT"),
+        received_span("checkout", "db", "UPDATE"),
+        received_span("checkout", "http", "GET /cart"),
+        received_span("checkout", "http", "POST /cart"),
+    ];
+    expected.sort();
+    assert_eq!(sorted(&received), expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receiver_sees_the_spans_of_every_resource() {
+    let endpoint = free_endpoint();
+    let received = start_receiver(&endpoint);
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build()
+        .expect("build Capnp SpanExporter");
+
+    exporter.set_resource(&service("checkout"));
+    exporter
+        .export(scoped_batch(&[("http", "GET /cart"), ("db", "SELECT")]))
+        .await
+        .expect("export checkout spans");
+    exporter.set_resource(&service("payments"));
+    exporter
+        .export(scoped_batch(&[
+            ("http", "POST /charge"),
+            ("queue", "publish"),
+        ]))
+        .await
+        .expect("export payments spans");
+    exporter.force_flush().expect("span batches are delivered");
+
+    let mut expected = vec![
+        received_span("checkout", "db", "SELECT"),
+        received_span("checkout", "http", "GET /cart"),
+        received_span("payments", "http", "POST /charge"),
+        received_span("payments", "queue", "publish"),
+    ];
+    expected.sort();
+    assert_eq!(sorted(&received), expected);
+}
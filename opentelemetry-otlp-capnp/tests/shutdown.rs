@@ -0,0 +1,64 @@
+use opentelemetry_otlp_capnp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::error::OTelSdkError;
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::Duration;
+use utilities::capnp::fixtures::{batch, free_endpoint};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+fn build_exporter(endpoint: &str) -> SpanExporter {
+    SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(endpoint)
+        .build()
+        .expect("build Capnp SpanExporter")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batches_buffered_while_disconnected_are_delivered_once_the_receiver_is_up() {
+    let endpoint = free_endpoint();
+    let mut exporter = build_exporter(&endpoint);
+    let buffering = exporter.clone();
+    let mut exports: Vec<_> = (0..5)
+        .map(|_| Box::pin(buffering.export(batch(10))))
+        .collect();
+    for export in &mut exports {
+        assert!(
+            futures::poll!(export).is_pending(),
+            "batch waits while the receiver is unreachable"
+        );
+    }
+
+    let _receiver = NoOpSpanReceiver::new(&endpoint)
+        .start()
+        .expect("start SpanReceiver");
+    for result in futures::future::join_all(exports).await {
+        result.expect("buffered batch is delivered once the receiver is up");
+    }
+    exporter
+        .shutdown_with_timeout(Duration::from_secs(10))
+        .expect("shutdown completes");
+    assert!(matches!(
+        exporter.shutdown(),
+        Err(OTelSdkError::AlreadyShutdown)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_times_out_when_buffered_batches_cannot_be_delivered() {
+    let endpoint = free_endpoint();
+    let mut exporter = build_exporter(&endpoint);
+    let buffering = exporter.clone();
+    let mut export = std::pin::pin!(buffering.export(batch(10)));
+    assert!(futures::poll!(&mut export).is_pending());
+
+    let timeout = Duration::from_millis(200);
+    assert!(matches!(
+        exporter.shutdown_with_timeout(timeout),
+        Err(OTelSdkError::Timeout(t)) if t == timeout
+    ));
+    assert!(
+        export.await.is_err(),
+        "the caller learns that the batch was dropped"
+    );
+    assert_eq!(exporter.metrics_snapshot().spans_dropped, 10);
+}
@@ -0,0 +1,242 @@
+use opentelemetry_otlp_capnp::{
+    ExporterBuildError, SpanExporter, WithExportConfig, OTEL_EXPORTER_CAPNP_TIMEOUT,
+    OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT,
+};
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::time::Duration;
+use utilities::capnp::fixtures::{batch, free_endpoint, ENV_LOCK};
+use utilities::capnp::receiver::NoOpSpanReceiver;
+
+/// How long the receivers take to answer an export request.
+const RESPONSE_DELAY: Duration = Duration::from_millis(1000);
+
+/// Build an exporter with `env` set, returning the outcome of an export to a
+/// receiver that answers after [RESPONSE_DELAY].
+async fn export_with(env: &[(&str, &str)], timeout: Option<Duration>) -> OTelSdkResult {
+    let endpoint = free_endpoint();
+    NoOpSpanReceiver::new(&endpoint)
+        .with_response_delay(RESPONSE_DELAY)
+        .start()
+        .expect("start SpanReceiver");
+    let exporter = {
+        let _env = ENV_LOCK.lock().await;
+        for (var, value) in env {
+            std::env::set_var(var, value);
+        }
+        let mut builder = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint);
+        if let Some(timeout) = timeout {
+            builder = builder.with_timeout(timeout);
+        }
+        let exporter = builder.build();
+        for (var, _) in env {
+            std::env::remove_var(var);
+        }
+        exporter.expect("build Capnp SpanExporter")
+    };
+    exporter.export(batch(1)).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn programmatic_timeout_overrides_the_environment() {
+    let result = export_with(
+        &[
+            (OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT, "300"),
+            (OTEL_EXPORTER_CAPNP_TIMEOUT, "400"),
+        ],
+        Some(Duration::from_millis(150)),
+    )
+    .await;
+    assert!(
+        matches!(result, Err(OTelSdkError::Timeout(timeout)) if timeout == Duration::from_millis(150)),
+        "{result:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn traces_timeout_variable_overrides_the_generic_one() {
+    let result = export_with(
+        &[
+            (OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT, "300"),
+            (OTEL_EXPORTER_CAPNP_TIMEOUT, "400"),
+        ],
+        None,
+    )
+    .await;
+    assert!(
+        matches!(result, Err(OTelSdkError::Timeout(timeout)) if timeout == Duration::from_millis(300)),
+        "{result:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generic_timeout_variable_applies_to_traces() {
+    let result = export_with(
+        &[
+            // empty, so it counts as unset
+            (OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT, ""),
+            (OTEL_EXPORTER_CAPNP_TIMEOUT, "400"),
+        ],
+        None,
+    )
+    .await;
+    assert!(
+        matches!(result, Err(OTelSdkError::Timeout(timeout)) if timeout == Duration::from_millis(400)),
+        "{result:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_timeout_variable_fails_the_build() {
+    let result = {
+        let _env = ENV_LOCK.lock().await;
+        // not a number of milliseconds
+        std::env::set_var(OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT, "soon");
+        std::env::set_var(OTEL_EXPORTER_CAPNP_TIMEOUT, "400");
+        let result = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(free_endpoint())
+            .build();
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT);
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TIMEOUT);
+        result
+    };
+    assert!(
+        matches!(
+            &result,
+            Err(ExporterBuildError::InvalidEnvValue { name, value, .. })
+                if name == OTEL_EXPORTER_CAPNP_TRACES_TIMEOUT && value == "soon"
+        ),
+        "{result:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn default_timeout_outlasts_a_slow_receiver() {
+    let result = export_with(&[], None).await;
+    assert!(result.is_ok(), "{result:?}");
+}
+
+#[cfg(feature = "tls")]
+mod connect_timeout {
+    use opentelemetry_otlp_capnp::{
+        ClientTlsConfig, SpanExporter, WithCapnpConfig, WithExportConfig,
+        OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT, OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT,
+    };
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use utilities::capnp::fixtures::{free_endpoint, ENV_LOCK};
+
+    /// Accept TCP connections but never answer, so every TLS handshake hangs
+    /// until the exporter's connect timeout. Returns the count of accepted
+    /// connections.
+    async fn start_silent_listener(endpoint: &str) -> Arc<AtomicU64> {
+        let listener = tokio::net::TcpListener::bind(endpoint)
+            .await
+            .expect("bind silent listener");
+        let accepted = Arc::new(AtomicU64::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                streams.push(stream);
+            }
+        });
+        accepted
+    }
+
+    fn ca_certificate(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "opentelemetry-otlp-capnp-connect-timeout-{name}-{}.pem",
+            std::process::id()
+        ));
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .expect("generate certificate");
+        std::fs::write(&path, certificate.cert.pem()).expect("write certificate");
+        path
+    }
+
+    /// Count the connection attempts an exporter configured with `env` and
+    /// `timeout` makes within a second.
+    async fn connection_attempts(
+        name: &str,
+        env: &[(&str, &str)],
+        timeout: Option<Duration>,
+    ) -> u64 {
+        let endpoint = free_endpoint();
+        let accepted = start_silent_listener(&endpoint).await;
+        let ca_certificate = ca_certificate(name);
+        let exporter = {
+            let _env = ENV_LOCK.lock().await;
+            for (var, value) in env {
+                std::env::set_var(var, value);
+            }
+            let mut builder = SpanExporter::builder()
+                .with_capnp()
+                .with_endpoint(&endpoint)
+                .with_tls_config(
+                    ClientTlsConfig::new(&ca_certificate).with_domain_name("localhost"),
+                );
+            if let Some(timeout) = timeout {
+                builder = builder.with_connect_timeout(timeout);
+            }
+            let exporter = builder.build();
+            for (var, _) in env {
+                std::env::remove_var(var);
+            }
+            exporter.expect("build Capnp SpanExporter")
+        };
+        // the exporter connects in the background right after it was built
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        drop(exporter);
+        let _ = std::fs::remove_file(ca_certificate);
+        accepted.load(Ordering::Relaxed)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn programmatic_connect_timeout_overrides_the_environment() {
+        let attempts = connection_attempts(
+            "programmatic",
+            &[(OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT, "60000")],
+            Some(Duration::from_millis(100)),
+        )
+        .await;
+        assert!(attempts >= 2, "{attempts} connection attempts");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn traces_connect_timeout_variable_overrides_the_generic_one() {
+        let attempts = connection_attempts(
+            "traces",
+            &[
+                (OTEL_EXPORTER_CAPNP_TRACES_CONNECT_TIMEOUT, "100"),
+                (OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT, "60000"),
+            ],
+            None,
+        )
+        .await;
+        assert!(attempts >= 2, "{attempts} connection attempts");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn generic_connect_timeout_variable_applies_to_traces() {
+        let attempts = connection_attempts(
+            "generic",
+            &[(OTEL_EXPORTER_CAPNP_CONNECT_TIMEOUT, "100")],
+            None,
+        )
+        .await;
+        assert!(attempts >= 2, "{attempts} connection attempts");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn default_connect_timeout_waits_for_the_handshake() {
+        let attempts = connection_attempts("default", &[], None).await;
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,195 @@
+#![cfg(feature = "tls")]
+
+use opentelemetry_otlp_capnp::{
+    ClientTlsConfig, ExporterBuildError, ServerTlsConfig, SpanExporter, SpanReceiver,
+    WithExportConfig, OTEL_EXPORTER_CAPNP_CLIENT_KEY, OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE,
+    OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE,
+};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use std::path::PathBuf;
+use std::time::Duration;
+use utilities::capnp::fixtures::{batch, free_endpoint, free_port, ENV_LOCK};
+
+/// PEM files of a throwaway CA with a server and a client certificate.
+struct Certificates {
+    directory: PathBuf,
+}
+
+impl Certificates {
+    fn generate(name: &str) -> Self {
+        let directory = std::env::temp_dir().join(format!(
+            "opentelemetry-otlp-capnp-tls-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).expect("create certificate directory");
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let leaf = |purpose: ExtendedKeyUsagePurpose| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let certificate = params.signed_by(&key, &ca, &ca_key).unwrap();
+            (certificate.pem(), key.serialize_pem())
+        };
+        let (server_certificate, server_key) = leaf(ExtendedKeyUsagePurpose::ServerAuth);
+        let (client_certificate, client_key) = leaf(ExtendedKeyUsagePurpose::ClientAuth);
+
+        let certificates = Self { directory };
+        for (file, pem) in [
+            ("ca.pem", ca.pem()),
+            ("server.pem", server_certificate),
+            ("server-key.pem", server_key),
+            ("client.pem", client_certificate),
+            ("client-key.pem", client_key),
+        ] {
+            std::fs::write(certificates.path(file), pem).expect("write PEM file");
+        }
+        certificates
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.directory.join(file)
+    }
+
+    fn server_config(&self) -> ServerTlsConfig {
+        ServerTlsConfig::new(self.path("server.pem"), self.path("server-key.pem"))
+    }
+
+    fn client_config(&self) -> ClientTlsConfig {
+        ClientTlsConfig::new(self.path("ca.pem")).with_domain_name("localhost")
+    }
+}
+
+impl Drop for Certificates {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+fn start_receiver(endpoint: &str, tls_config: ServerTlsConfig) {
+    SpanReceiver::new(endpoint)
+        .expect("valid receiver endpoint")
+        .with_tls_config(tls_config)
+        .start()
+        .expect("start SpanReceiver with TLS");
+}
+
+/// Whether the receiver acknowledged a batch before the exporter shut down.
+async fn export_and_flush(exporter: &mut SpanExporter, flush_timeout: Duration) -> bool {
+    // a batch waits for a connection, which a failed handshake never brings up
+    let exported = tokio::time::timeout(flush_timeout, exporter.export(batch(10))).await;
+    let flushed = exporter.shutdown_with_timeout(flush_timeout);
+    matches!(exported, Ok(Ok(()))) && flushed.is_ok()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_over_tls() {
+    let endpoint = free_endpoint();
+    let certificates = Certificates::generate("server-only");
+    start_receiver(&endpoint, certificates.server_config());
+
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_tls_config(certificates.client_config())
+        .build()
+        .expect("build Capnp SpanExporter with TLS");
+    assert!(export_and_flush(&mut exporter, Duration::from_secs(10)).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mutual_tls_requires_a_client_certificate() {
+    let endpoint = free_endpoint();
+    let certificates = Certificates::generate("mutual");
+    start_receiver(
+        &endpoint,
+        certificates
+            .server_config()
+            .with_client_ca_certificate(certificates.path("ca.pem")),
+    );
+
+    let mut anonymous_exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_tls_config(certificates.client_config())
+        .build()
+        .expect("build Capnp SpanExporter without a client certificate");
+    assert!(!export_and_flush(&mut anonymous_exporter, Duration::from_millis(500)).await);
+
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .with_tls_config(certificates.client_config().with_client_identity(
+            certificates.path("client.pem"),
+            certificates.path("client-key.pem"),
+        ))
+        .build()
+        .expect("build Capnp SpanExporter with a client certificate");
+    assert!(export_and_flush(&mut exporter, Duration::from_secs(10)).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tls_is_configured_from_the_environment() {
+    let endpoint = format!("localhost:{}", free_port());
+    let certificates = Certificates::generate("env");
+    start_receiver(&endpoint, certificates.server_config());
+
+    let exporter = {
+        let _env = ENV_LOCK.lock().await;
+        std::env::set_var(
+            OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE,
+            certificates.path("ca.pem"),
+        );
+        let exporter = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(&endpoint)
+            .build();
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE);
+        exporter
+    };
+    let mut exporter = exporter.expect("build Capnp SpanExporter with TLS from the environment");
+    assert!(export_and_flush(&mut exporter, Duration::from_secs(10)).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_client_certificate_without_a_key_fails_the_build() {
+    let certificates = Certificates::generate("half-identity");
+    let client_certificate = certificates.path("client.pem");
+
+    let result = {
+        let _env = ENV_LOCK.lock().await;
+        std::env::set_var(
+            OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE,
+            certificates.path("ca.pem"),
+        );
+        std::env::set_var(
+            OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE,
+            &client_certificate,
+        );
+        // set but empty, i.e. unset
+        std::env::set_var(OTEL_EXPORTER_CAPNP_CLIENT_KEY, "");
+        let result = SpanExporter::builder()
+            .with_capnp()
+            .with_endpoint(format!("localhost:{}", free_port()))
+            .build();
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_CERTIFICATE);
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE);
+        std::env::remove_var(OTEL_EXPORTER_CAPNP_CLIENT_KEY);
+        result
+    };
+    assert!(
+        matches!(
+            &result,
+            Err(ExporterBuildError::InvalidEnvValue { name, value, .. })
+                if name == OTEL_EXPORTER_CAPNP_TRACES_CLIENT_CERTIFICATE
+                    && *value == client_certificate.display().to_string()
+        ),
+        "{result:?}"
+    );
+}
@@ -0,0 +1,47 @@
+#![cfg(unix)]
+
+use opentelemetry_otlp_capnp::{SpanExporter, SpanReceiver, WithExportConfig};
+use opentelemetry_sdk::trace::SpanExporter as _;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use utilities::capnp::span::FakeCapnp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_over_a_unix_domain_socket() {
+    let directory = std::env::temp_dir().join(format!(
+        "opentelemetry-otlp-capnp-unix-socket-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).expect("create socket directory");
+    let path = directory.join("receiver.sock");
+    // a socket file left behind by a receiver that did not shut down cleanly
+    drop(std::os::unix::net::UnixListener::bind(&path).expect("bind stale socket"));
+
+    let endpoint = format!("unix://{}", path.display());
+    let receiver = SpanReceiver::new(&endpoint)
+        .expect("valid receiver endpoint")
+        .with_unix_socket_permissions(0o600)
+        .start()
+        .expect("start SpanReceiver");
+    let mut exporter = SpanExporter::builder()
+        .with_capnp()
+        .with_endpoint(&endpoint)
+        .build()
+        .expect("build Capnp SpanExporter with a unix endpoint");
+    exporter
+        .export(FakeCapnp::trace_service_request_with_spans(10).batch)
+        .await
+        .expect("export over the unix domain socket");
+    exporter
+        .force_flush()
+        .expect("receiver acknowledges every batch");
+
+    let metadata = std::fs::metadata(&path).expect("socket file exists");
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+    receiver.stop().expect("stop SpanReceiver");
+    assert!(!path.exists(), "the socket file is removed on stop");
+
+    let _ = std::fs::remove_dir_all(&directory);
+}
@@ -0,0 +1,65 @@
+//! Helpers shared by the integration tests of `opentelemetry-otlp-capnp`.
+
+use crate::capnp::receiver::{NoOpSpanReceiver, ReceivedSpan};
+use crate::capnp::span::FakeCapnp;
+use opentelemetry::InstrumentationScope;
+use opentelemetry_sdk::trace::SpanData;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+/// Held while a test sets `OTEL_EXPORTER_CAPNP_*` environment variables and
+/// builds the exporters that read them, since tests run on parallel threads
+/// of the same process.
+pub static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// A local port nothing listens on, never handed out twice in this process.
+pub fn free_port() -> u16 {
+    static HANDED_OUT: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+    loop {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("bind an ephemeral port")
+            .port();
+        if HANDED_OUT
+            .lock()
+            .expect("handed out ports lock")
+            .insert(port)
+        {
+            return port;
+        }
+    }
+}
+
+/// A `127.0.0.1:port` endpoint on a [free_port].
+pub fn free_endpoint() -> String {
+    format!("127.0.0.1:{}", free_port())
+}
+
+/// Start a [NoOpSpanReceiver] on `endpoint` that records every span it is sent.
+pub fn start_receiver(endpoint: &str) -> Arc<Mutex<Vec<ReceivedSpan>>> {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    NoOpSpanReceiver::new(endpoint)
+        .with_received_spans(received.clone())
+        .start()
+        .expect("start SpanReceiver");
+    received
+}
+
+/// A batch of `span_count` identical spans.
+pub fn batch(span_count: usize) -> Vec<SpanData> {
+    FakeCapnp::trace_service_request_with_spans(span_count).batch
+}
+
+/// A batch with one span per `(scope, name)`.
+pub fn scoped_batch(spans: &[(&'static str, &'static str)]) -> Vec<SpanData> {
+    let template = batch(1).remove(0);
+    spans
+        .iter()
+        .map(|(scope, name)| SpanData {
+            instrumentation_scope: InstrumentationScope::builder(*scope).build(),
+            name: Cow::Borrowed(name),
+            ..template.clone()
+        })
+        .collect()
+}
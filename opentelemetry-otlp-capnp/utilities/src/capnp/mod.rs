@@ -1,2 +1,3 @@
+pub mod fixtures;
 pub mod receiver;
 pub mod span;
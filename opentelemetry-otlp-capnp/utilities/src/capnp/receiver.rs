@@ -1,8 +1,14 @@
-use capnp::capability::Promise;
-use capnp_rpc::{rpc_twoparty_capnp, twoparty, RpcSystem};
-use futures::io::AsyncReadExt;
-use opentelemetry_capnp::capnp::capnp_rpc::trace_service;
+use capnp_rpc::RpcSystem;
+use opentelemetry_capnp::capnp::capnp_rpc::{common_capnp, trace_service};
+use opentelemetry_otlp_capnp::{Acceptor, Compression};
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
 
 /// A No-op Span receiver for Cap'n Proto RPC for benchmarking.
 ///
@@ -20,6 +26,24 @@ use std::net::{SocketAddr, ToSocketAddrs};
 /// ```
 pub struct NoOpSpanReceiver {
     addr: SocketAddr,
+    compression: Option<Compression>,
+    bytes_received: Arc<AtomicU64>,
+    connections: Arc<AtomicU64>,
+    shutdown: Arc<Notify>,
+    response_delay: Duration,
+    received_spans: Option<Arc<Mutex<Vec<ReceivedSpan>>>>,
+    rejected_spans: i64,
+    error_message: String,
+}
+
+/// A span decoded by the [NoOpSpanReceiver], with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReceivedSpan {
+    /// The string attributes of the span's resource.
+    pub resource: Vec<(String, String)>,
+    /// The name of the span's instrumentation scope.
+    pub scope: String,
+    pub name: String,
 }
 
 impl NoOpSpanReceiver {
@@ -29,10 +53,72 @@ impl NoOpSpanReceiver {
             .expect("Valid socket address")
             .next()
             .expect("At least one address");
-        Self { addr }
+        Self {
+            addr,
+            compression: None,
+            bytes_received: Arc::default(),
+            connections: Arc::default(),
+            shutdown: Arc::default(),
+            response_delay: Duration::ZERO,
+            received_spans: None,
+            rejected_spans: 0,
+            error_message: String::new(),
+        }
+    }
+
+    /// Expect the exporters to compress the message stream with `compression`.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Count the bytes read off the wire, i.e. before decompression, into
+    /// `bytes_received`.
+    pub fn with_bytes_received(mut self, bytes_received: Arc<AtomicU64>) -> Self {
+        self.bytes_received = bytes_received;
+        self
+    }
+
+    /// Count the connections accepted from exporters into `connections`.
+    pub fn with_connections(mut self, connections: Arc<AtomicU64>) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    /// Stop the receiver once `shutdown` is notified: it stops listening and
+    /// drops every open connection, and the thread started by
+    /// [start](Self::start) exits.
+    pub fn with_shutdown(mut self, shutdown: Arc<Notify>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Answer every export request only after `response_delay`, simulating a
+    /// high-latency link to the receiver.
+    pub fn with_response_delay(mut self, response_delay: Duration) -> Self {
+        self.response_delay = response_delay;
+        self
+    }
+
+    /// Decode every export request and append its spans to `received_spans`.
+    pub fn with_received_spans(mut self, received_spans: Arc<Mutex<Vec<ReceivedSpan>>>) -> Self {
+        self.received_spans = Some(received_spans);
+        self
+    }
+
+    /// Answer every export request with a partial success reporting
+    /// `rejected_spans` and `error_message`.
+    pub fn with_partial_success(mut self, rejected_spans: i64, error_message: &str) -> Self {
+        self.rejected_spans = rejected_spans;
+        self.error_message = error_message.to_string();
+        self
     }
 
+    /// Listen on the receiver's address and serve export requests on a new
+    /// thread. Exporters can connect as soon as this returns.
     pub fn start(self) -> std::io::Result<std::thread::JoinHandle<()>> {
+        let listener = std::net::TcpListener::bind(self.addr)?;
+        listener.set_nonblocking(true)?;
         let handle = std::thread::spawn(move || {
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
@@ -41,15 +127,33 @@ impl NoOpSpanReceiver {
             let local = tokio::task::LocalSet::new();
 
             local.block_on(&rt, async {
-                let listener = tokio::net::TcpListener::bind(self.addr).await.unwrap();
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                let acceptor = Acceptor::default().with_compression(self.compression);
+                let bytes_received = self.bytes_received.clone();
+                let connections = self.connections.clone();
+                let shutdown = self.shutdown.clone();
                 // let client: trace_service::Client = capnp_rpc::new_client(SpanReceiver);
                 let client: trace_service::Client = capnp_rpc::new_client(self);
 
                 loop {
-                    let (stream, _) = listener.accept().await.unwrap();
-                    stream.set_nodelay(true).unwrap();
-
-                    spawn_local_rpc_system_to_handle_stream(stream, client.clone()).await;
+                    let accepted = tokio::select! {
+                        accepted = listener.accept() => accepted,
+                        _ = shutdown.notified() => break,
+                    };
+                    let Ok((stream, _)) = accepted else {
+                        continue;
+                    };
+                    connections.fetch_add(1, Ordering::Relaxed);
+                    let _ = stream.set_nodelay(true);
+                    let stream = CountingStream {
+                        inner: stream,
+                        count: bytes_received.clone(),
+                    };
+                    let Ok(network) = acceptor.vat_network(Box::new(stream)) else {
+                        continue;
+                    };
+                    let rpc_system = RpcSystem::new(Box::new(network), Some(client.clone().client));
+                    tokio::task::spawn_local(rpc_system);
                 }
             })
         });
@@ -65,32 +169,103 @@ impl NoOpSpanReceiver {
 impl trace_service::Server for NoOpSpanReceiver {
     fn export(
         self: std::rc::Rc<Self>,
-        _params: trace_service::ExportParams,
+        params: trace_service::ExportParams,
         mut results: trace_service::ExportResults,
     ) -> impl futures::Future<Output = Result<(), capnp::Error>> + 'static {
+        let decoded = match &self.received_spans {
+            Some(received_spans) => params
+                .get()
+                .and_then(|params| decode_spans(params.get_request()?))
+                .map(|spans| {
+                    received_spans
+                        .lock()
+                        .expect("received spans lock")
+                        .extend(spans)
+                }),
+            None => Ok(()),
+        };
         let response_builder = results.get().init_response();
         let mut partial_success_builder = response_builder.init_partial_success();
-        let num_rejected_spans = 0;
         partial_success_builder
             .reborrow()
-            .set_rejected_spans(num_rejected_spans);
-        Promise::ok(())
+            .set_rejected_spans(self.rejected_spans);
+        if !self.error_message.is_empty() {
+            partial_success_builder.set_error_message(self.error_message.as_str());
+        }
+        let response_delay = self.response_delay;
+        async move {
+            decoded?;
+            if !response_delay.is_zero() {
+                tokio::time::sleep(response_delay).await;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn decode_spans(
+    request: opentelemetry_capnp::capnp::capnp_rpc::export_trace_service_request::Reader<'_>,
+) -> capnp::Result<Vec<ReceivedSpan>> {
+    let mut spans = Vec::new();
+    for resource_spans in request.get_resource_spans()? {
+        let mut resource = Vec::new();
+        for attribute in resource_spans.get_resource()?.get_attributes()? {
+            if let common_capnp::any_value::value::StringValue(value) =
+                attribute.get_value()?.get_value().which()?
+            {
+                resource.push((attribute.get_key()?.to_string()?, value?.to_string()?));
+            }
+        }
+        for scope_spans in resource_spans.get_scope_spans()? {
+            let scope = scope_spans.get_scope()?.get_name()?.to_string()?;
+            for span in scope_spans.get_spans()? {
+                spans.push(ReceivedSpan {
+                    resource: resource.clone(),
+                    scope: scope.clone(),
+                    name: span.get_name()?.to_string()?,
+                });
+            }
+        }
     }
+    Ok(spans)
+}
+
+/// Counts the bytes read from `inner` into `count`.
+struct CountingStream<S> {
+    inner: S,
+    count: Arc<AtomicU64>,
 }
 
-async fn spawn_local_rpc_system_to_handle_stream(
-    stream: tokio::net::TcpStream,
-    client: trace_service::Client,
-) {
-    let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
-
-    let rpc_network = twoparty::VatNetwork::new(
-        futures::io::BufReader::new(reader),
-        futures::io::BufWriter::new(writer),
-        rpc_twoparty_capnp::Side::Server,
-        Default::default(),
-    );
-
-    let rpc_system = RpcSystem::new(Box::new(rpc_network), Some(client.clone().client));
-    tokio::task::spawn_local(rpc_system);
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = buf.filled().len() - filled;
+            self.count.fetch_add(read as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
 }